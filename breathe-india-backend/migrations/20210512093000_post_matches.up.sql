@@ -0,0 +1,10 @@
+-- Add up migration script here
+CREATE TABLE post_matches (
+    needs_id uuid NOT NULL REFERENCES posts(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    supplies_id uuid NOT NULL REFERENCES posts(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    score DOUBLE PRECISION NOT NULL,
+    computed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (needs_id, supplies_id)
+);
+
+CREATE INDEX post_matches_supplies_id_idx ON post_matches(supplies_id);
@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP TABLE worker_cursors;
+DROP TRIGGER posts_record_event ON posts;
+DROP FUNCTION record_post_event;
+DROP TABLE post_events;
+DROP TYPE PostEventKind;
@@ -0,0 +1,33 @@
+-- Add up migration script here
+CREATE TYPE PostEventKind AS ENUM ('created', 'updated', 'deleted');
+
+-- An append-only log of changes to posts, for background workers to consume.
+CREATE TABLE post_events (
+    id BIGSERIAL PRIMARY KEY,
+    post_id uuid NOT NULL,
+    kind PostEventKind NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE FUNCTION record_post_event() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        INSERT INTO post_events(post_id, kind) VALUES (NEW.id, 'created');
+    ELSIF TG_OP = 'UPDATE' THEN
+        INSERT INTO post_events(post_id, kind) VALUES (NEW.id, 'updated');
+    ELSE
+        INSERT INTO post_events(post_id, kind) VALUES (OLD.id, 'deleted');
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER posts_record_event
+    AFTER INSERT OR UPDATE OR DELETE ON posts
+    FOR EACH ROW EXECUTE FUNCTION record_post_event();
+
+-- How far each worker has got through post_events.
+CREATE TABLE worker_cursors (
+    name TEXT NOT NULL PRIMARY KEY,
+    last_event_id BIGINT NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE saved_search_notifications;
+DROP TABLE saved_searches;
+DROP TYPE NotifyChannel;
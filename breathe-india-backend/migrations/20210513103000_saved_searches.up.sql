@@ -0,0 +1,25 @@
+-- Add up migration script here
+CREATE TYPE NotifyChannel AS ENUM ('email', 'webhook');
+
+CREATE TABLE saved_searches (
+    id uuid NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    userid uuid NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    post_type PostType NOT NULL,
+    location TEXT,
+    item TEXT,
+    channel NotifyChannel NOT NULL,
+    webhook_url TEXT,
+    paused BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX saved_searches_userid_idx ON saved_searches(userid);
+
+-- So that a search is notified about a post only once, however many times
+-- the post is edited.
+CREATE TABLE saved_search_notifications (
+    saved_search_id uuid NOT NULL REFERENCES saved_searches(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    post_id uuid NOT NULL REFERENCES posts(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    notified_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (saved_search_id, post_id)
+);
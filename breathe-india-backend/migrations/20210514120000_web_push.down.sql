@@ -0,0 +1,11 @@
+-- Add down migration script here
+DROP TABLE post_confirmations;
+DROP TABLE push_subscriptions;
+DROP TABLE vapid_keys;
+-- Postgres can't drop a value from an enum, so recreate it.
+DELETE FROM saved_searches WHERE channel = 'push';
+ALTER TYPE NotifyChannel RENAME TO NotifyChannel_old;
+CREATE TYPE NotifyChannel AS ENUM ('email', 'webhook');
+ALTER TABLE saved_searches
+    ALTER COLUMN channel TYPE NotifyChannel USING channel::text::NotifyChannel;
+DROP TYPE NotifyChannel_old;
@@ -0,0 +1,28 @@
+-- Add up migration script here
+ALTER TYPE NotifyChannel ADD VALUE 'push';
+
+-- Only ever holds one row.
+CREATE TABLE vapid_keys (
+    id BOOLEAN NOT NULL PRIMARY KEY DEFAULT TRUE CHECK (id),
+    private_key TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE push_subscriptions (
+    id uuid NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    userid uuid NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    endpoint TEXT NOT NULL UNIQUE,
+    p256dh TEXT NOT NULL,
+    auth TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX push_subscriptions_userid_idx ON push_subscriptions(userid);
+
+CREATE TABLE post_confirmations (
+    post_id uuid NOT NULL REFERENCES posts(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    userid uuid NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    available BOOLEAN NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (post_id, userid)
+);
@@ -0,0 +1,26 @@
+-- Add down migration script here
+CREATE OR REPLACE FUNCTION record_post_event() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        INSERT INTO post_events(post_id, kind) VALUES (NEW.id, 'created');
+    ELSIF TG_OP = 'UPDATE' THEN
+        INSERT INTO post_events(post_id, kind) VALUES (NEW.id, 'updated');
+    ELSE
+        INSERT INTO post_events(post_id, kind) VALUES (OLD.id, 'deleted');
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE post_events DROP COLUMN post;
+
+-- Postgres can't drop a value from an enum, so recreate it.
+UPDATE post_events SET kind = 'updated' WHERE kind = 'status_changed';
+ALTER TYPE PostEventKind RENAME TO PostEventKind_old;
+CREATE TYPE PostEventKind AS ENUM ('created', 'updated', 'deleted');
+ALTER TABLE post_events
+    ALTER COLUMN kind TYPE PostEventKind USING kind::text::PostEventKind;
+DROP TYPE PostEventKind_old;
+
+ALTER TABLE posts DROP COLUMN status;
+DROP TYPE PostStatus;
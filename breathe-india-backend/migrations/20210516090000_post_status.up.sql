@@ -0,0 +1,25 @@
+-- Add up migration script here
+CREATE TYPE PostStatus AS ENUM ('open', 'fulfilled', 'closed');
+
+ALTER TABLE posts ADD COLUMN status PostStatus NOT NULL DEFAULT 'open';
+
+ALTER TYPE PostEventKind ADD VALUE 'status_changed';
+
+-- What the post looked like at the time of the event. For deletes this is
+-- the only record left of it.
+ALTER TABLE post_events ADD COLUMN post JSONB;
+
+CREATE OR REPLACE FUNCTION record_post_event() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        INSERT INTO post_events(post_id, kind, post) VALUES (NEW.id, 'created', to_jsonb(NEW));
+    ELSIF TG_OP = 'UPDATE' AND OLD.status IS DISTINCT FROM NEW.status THEN
+        INSERT INTO post_events(post_id, kind, post) VALUES (NEW.id, 'status_changed', to_jsonb(NEW));
+    ELSIF TG_OP = 'UPDATE' THEN
+        INSERT INTO post_events(post_id, kind, post) VALUES (NEW.id, 'updated', to_jsonb(NEW));
+    ELSE
+        INSERT INTO post_events(post_id, kind, post) VALUES (OLD.id, 'deleted', to_jsonb(OLD));
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE webhook_deliveries;
+DROP TYPE WebhookDeliveryState;
+DROP TABLE webhooks;
@@ -0,0 +1,36 @@
+-- Add up migration script here
+CREATE TABLE webhooks (
+    id uuid NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    userid uuid NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    post_type PostType,
+    item TEXT,
+    location TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX webhooks_userid_idx ON webhooks(userid);
+
+CREATE TYPE WebhookDeliveryState AS ENUM ('pending', 'delivered', 'dead');
+
+CREATE TABLE webhook_deliveries (
+    id uuid NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    webhook_id uuid NOT NULL REFERENCES webhooks(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    event_id BIGINT NOT NULL,
+    event PostEventKind NOT NULL,
+    payload JSONB NOT NULL,
+    state WebhookDeliveryState NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_status_code INTEGER,
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ,
+    UNIQUE (webhook_id, event_id)
+);
+
+CREATE INDEX webhook_deliveries_due_idx
+    ON webhook_deliveries(next_attempt_at) WHERE state = 'pending';
+CREATE INDEX webhook_deliveries_webhook_id_idx
+    ON webhook_deliveries(webhook_id, created_at DESC);
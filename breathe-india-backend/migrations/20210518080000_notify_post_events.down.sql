@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TRIGGER post_events_notify ON post_events;
+DROP FUNCTION notify_post_event;
@@ -0,0 +1,12 @@
+-- Add up migration script here
+-- Wakes up listening backend instances whenever a post event is recorded.
+CREATE FUNCTION notify_post_event() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify('post_events', NEW.id::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER post_events_notify
+    AFTER INSERT ON post_events
+    FOR EACH ROW EXECUTE FUNCTION notify_post_event();
@@ -0,0 +1,2 @@
+-- Add down migration script here
+DROP INDEX posts_type_updated_at_id;
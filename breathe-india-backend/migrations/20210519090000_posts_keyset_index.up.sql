@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Matches the ORDER BY of `posts`, so cursor pages are index scans.
+CREATE INDEX posts_type_updated_at_id ON posts(post_type, updated_at DESC, id DESC);
@@ -0,0 +1,12 @@
+-- Add down migration script here
+ALTER TABLE posts
+    DROP COLUMN urgency,
+    DROP COLUMN patient_age_bracket,
+    DROP COLUMN patient_spo2,
+    DROP COLUMN patient_blood_group,
+    DROP COLUMN patient_hospital,
+    DROP COLUMN patient_public;
+
+DROP TYPE BloodGroup;
+DROP TYPE AgeBracket;
+DROP TYPE Urgency;
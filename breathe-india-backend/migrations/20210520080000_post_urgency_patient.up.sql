@@ -0,0 +1,13 @@
+-- Add up migration script here
+-- Declared most urgent first, so that ORDER BY urgency puts critical on top.
+CREATE TYPE Urgency AS ENUM ('critical', 'high', 'normal');
+CREATE TYPE AgeBracket AS ENUM ('under_18', '18_44', '45_59', '60_plus');
+CREATE TYPE BloodGroup AS ENUM ('A+', 'A-', 'B+', 'B-', 'AB+', 'AB-', 'O+', 'O-');
+
+ALTER TABLE posts
+    ADD COLUMN urgency Urgency,
+    ADD COLUMN patient_age_bracket AgeBracket,
+    ADD COLUMN patient_spo2 SMALLINT CHECK (patient_spo2 BETWEEN 1 AND 100),
+    ADD COLUMN patient_blood_group BloodGroup,
+    ADD COLUMN patient_hospital TEXT,
+    ADD COLUMN patient_public BOOLEAN NOT NULL DEFAULT FALSE;
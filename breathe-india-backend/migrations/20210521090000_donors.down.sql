@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TABLE donor_contact_requests;
+DROP TYPE DonorContactState;
+DROP TABLE donors;
+DROP TYPE DonationKind;
@@ -0,0 +1,36 @@
+-- Add up migration script here
+CREATE TYPE DonationKind AS ENUM ('blood', 'plasma');
+
+CREATE TABLE donors (
+    userid uuid NOT NULL PRIMARY KEY REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    -- Given out in searches instead of the userid.
+    id uuid NOT NULL UNIQUE DEFAULT gen_random_uuid(),
+    blood_group BloodGroup NOT NULL,
+    last_donated_on DATE,
+    covid_recovered_on DATE,
+    state TEXT NOT NULL,
+    district TEXT NOT NULL,
+    opted_in BOOLEAN NOT NULL DEFAULT TRUE,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX donors_search_idx ON donors(blood_group, district) WHERE opted_in;
+
+CREATE TYPE DonorContactState AS ENUM ('pending', 'accepted', 'declined');
+
+CREATE TABLE donor_contact_requests (
+    id uuid NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    donor_userid uuid NOT NULL REFERENCES donors(userid) ON UPDATE RESTRICT ON DELETE CASCADE,
+    requester uuid NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    kind DonationKind NOT NULL,
+    post_id uuid REFERENCES posts(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    message TEXT NOT NULL,
+    state DonorContactState NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    responded_at TIMESTAMPTZ
+);
+
+-- Only one open request from the same person at a time.
+CREATE UNIQUE INDEX donor_contact_requests_pending_idx
+    ON donor_contact_requests(donor_userid, requester) WHERE state = 'pending';
+CREATE INDEX donor_contact_requests_requester_idx ON donor_contact_requests(requester);
@@ -0,0 +1,8 @@
+-- Add down migration script here
+DROP TRIGGER hospital_beds_history ON hospital_beds;
+DROP FUNCTION record_hospital_beds();
+DROP TABLE hospital_bed_history;
+DROP TABLE hospital_beds;
+DROP TYPE BedType;
+DROP TABLE hospital_staff;
+DROP TABLE hospitals;
@@ -0,0 +1,59 @@
+-- Add up migration script here
+CREATE TABLE hospitals (
+    id uuid NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    address TEXT NOT NULL,
+    state TEXT NOT NULL,
+    district TEXT NOT NULL,
+    city TEXT NOT NULL,
+    phone TEXT NOT NULL,
+    created_by uuid REFERENCES users(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX hospitals_district_idx ON hospitals(district);
+
+-- Who can update a hospital's beds.
+CREATE TABLE hospital_staff (
+    hospital_id uuid NOT NULL REFERENCES hospitals(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    userid uuid NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (hospital_id, userid)
+);
+
+CREATE TYPE BedType AS ENUM ('general', 'oxygen', 'icu', 'ventilator');
+
+CREATE TABLE hospital_beds (
+    hospital_id uuid NOT NULL REFERENCES hospitals(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    bed_type BedType NOT NULL,
+    total INTEGER NOT NULL CHECK (total >= 0),
+    available INTEGER NOT NULL CHECK (available >= 0 AND available <= total),
+    updated_by uuid REFERENCES users(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (hospital_id, bed_type)
+);
+
+CREATE TABLE hospital_bed_history (
+    id BIGSERIAL PRIMARY KEY,
+    hospital_id uuid NOT NULL REFERENCES hospitals(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    bed_type BedType NOT NULL,
+    total INTEGER NOT NULL,
+    available INTEGER NOT NULL,
+    recorded_by uuid REFERENCES users(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX hospital_bed_history_idx ON hospital_bed_history(hospital_id, recorded_at);
+
+-- Every change to the counts is kept, however it was made.
+CREATE FUNCTION record_hospital_beds() RETURNS TRIGGER AS $$
+BEGIN
+    INSERT INTO hospital_bed_history(hospital_id, bed_type, total, available, recorded_by, recorded_at)
+    VALUES (NEW.hospital_id, NEW.bed_type, NEW.total, NEW.available, NEW.updated_by, NEW.updated_at);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER hospital_beds_history
+    AFTER INSERT OR UPDATE ON hospital_beds
+    FOR EACH ROW EXECUTE FUNCTION record_hospital_beds();
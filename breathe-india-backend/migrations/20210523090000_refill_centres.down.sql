@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TABLE refill_centre_confirmations;
+DROP TABLE refill_centre_operators;
+DROP TABLE refill_centres;
+DROP TYPE RefillStatus;
@@ -0,0 +1,40 @@
+-- Add up migration script here
+-- Declared in the order centres are listed in.
+CREATE TYPE RefillStatus AS ENUM ('open', 'stock_low', 'closed');
+
+CREATE TABLE refill_centres (
+    id uuid NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    address TEXT NOT NULL,
+    state TEXT NOT NULL,
+    district TEXT NOT NULL,
+    city TEXT NOT NULL,
+    spot TEXT NOT NULL,
+    phone TEXT NOT NULL,
+    opening_hours TEXT NOT NULL,
+    cylinder_sizes TEXT[] NOT NULL DEFAULT '{}',
+    status RefillStatus NOT NULL DEFAULT 'open',
+    status_note TEXT NOT NULL DEFAULT '',
+    status_updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    created_by uuid REFERENCES users(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE refill_centre_operators (
+    centre_id uuid NOT NULL REFERENCES refill_centres(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    userid uuid NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (centre_id, userid)
+);
+
+-- The latest status each visitor saw.
+CREATE TABLE refill_centre_confirmations (
+    centre_id uuid NOT NULL REFERENCES refill_centres(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    userid uuid NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    status RefillStatus NOT NULL,
+    confirmed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (centre_id, userid)
+);
+
+CREATE INDEX refill_centre_confirmations_recent_idx
+    ON refill_centre_confirmations(centre_id, confirmed_at);
@@ -0,0 +1,11 @@
+-- Add down migration script here
+-- Numbers moved out of messages are not put back.
+DROP TABLE contact_reveals;
+
+ALTER TABLE posts
+    DROP COLUMN contact_phones,
+    DROP COLUMN contact_whatsapp,
+    DROP COLUMN contact_email,
+    DROP COLUMN contact_visibility;
+
+DROP TYPE ContactVisibility;
@@ -0,0 +1,41 @@
+-- Add up migration script here
+CREATE TYPE ContactVisibility AS ENUM ('public', 'logged_in', 'verified');
+
+ALTER TABLE posts
+    ADD COLUMN contact_phones TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN contact_whatsapp TEXT,
+    ADD COLUMN contact_email TEXT,
+    ADD COLUMN contact_visibility ContactVisibility NOT NULL DEFAULT 'logged_in';
+
+-- Who looked at a post's contact details. `viewer` is NULL for anonymous
+-- reveals of public details.
+CREATE TABLE contact_reveals (
+    id uuid NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    post_id uuid NOT NULL REFERENCES posts(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    viewer uuid REFERENCES users(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    revealed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX contact_reveals_post_idx ON contact_reveals(post_id, revealed_at);
+
+-- Move mobile numbers people pasted into the message into the contact
+-- details, keeping the last ten digits like `contact::normalize_phone`. The
+-- posts say the same as before, so no events.
+ALTER TABLE posts DISABLE TRIGGER posts_record_event;
+UPDATE posts
+SET contact_phones = ARRAY(
+        SELECT DISTINCT right(regexp_replace(m[1], '\D', '', 'g'), 10)
+        FROM regexp_matches(
+            message,
+            '(?<![\d+])((?:\+?91[\s-]?|0)?[6-9]\d{4}[\s-]?\d{5})(?!\d)',
+            'g'
+        ) AS m
+    ),
+    message = regexp_replace(
+        message,
+        '(?<![\d+])(?:\+?91[\s-]?|0)?[6-9]\d{4}[\s-]?\d{5}(?!\d)',
+        '[see contact details]',
+        'g'
+    )
+WHERE message ~ '(?<![\d+])(?:\+?91[\s-]?|0)?[6-9]\d{4}[\s-]?\d{5}(?!\d)';
+ALTER TABLE posts ENABLE TRIGGER posts_record_event;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- The message as written, when personal data in it was masked and the
+-- author asked to keep it. Only shown to the author.
+ALTER TABLE posts ADD COLUMN message_raw TEXT;
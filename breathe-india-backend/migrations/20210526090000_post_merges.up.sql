@@ -0,0 +1,12 @@
+-- Add up migration script here
+-- Duplicates are deleted when merged, so their ids are kept here without a
+-- foreign key.
+CREATE TABLE post_merges (
+    duplicate_id uuid NOT NULL PRIMARY KEY,
+    duplicate_userid uuid REFERENCES users(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    canonical_id uuid NOT NULL REFERENCES posts(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    merged_by uuid REFERENCES users(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    merged_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX post_merges_canonical_idx ON post_merges(canonical_id);
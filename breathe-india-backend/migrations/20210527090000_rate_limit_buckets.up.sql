@@ -0,0 +1,10 @@
+-- Add up migration script here
+-- Token buckets, when RATE_LIMIT_STORE=postgres. Keys are
+-- "<route>:user:<id>" or "<route>:ip:<address>".
+CREATE TABLE rate_limit_buckets (
+    key TEXT NOT NULL PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX rate_limit_buckets_updated_at_idx ON rate_limit_buckets(updated_at);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX posts_type_bumped_at_id;
+ALTER TABLE posts DROP COLUMN bumped_at;
@@ -0,0 +1,11 @@
+-- Add up migration script here
+-- When the author last moved the post to the top of the listing. Edits only
+-- touch updated_at, so they no longer reorder it.
+ALTER TABLE posts ADD COLUMN bumped_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+-- Existing posts keep their place. Nothing about them changed, so no events.
+ALTER TABLE posts DISABLE TRIGGER posts_record_event;
+UPDATE posts SET bumped_at = updated_at;
+ALTER TABLE posts ENABLE TRIGGER posts_record_event;
+
+-- Matches the default ORDER BY of `posts`, like posts_type_updated_at_id.
+CREATE INDEX posts_type_bumped_at_id ON posts(post_type, bumped_at DESC, id DESC);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE used_challenges;
+ALTER TABLE users DROP COLUMN created_at;
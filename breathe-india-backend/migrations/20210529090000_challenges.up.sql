@@ -0,0 +1,16 @@
+-- Add up migration script here
+-- Accounts from before this have no sign up time. Their first post is the
+-- best guess, otherwise they count as new.
+ALTER TABLE users ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+UPDATE users SET created_at = COALESCE(
+    (SELECT MIN(created_at) FROM posts WHERE posts.userid = users.id),
+    NOW()
+);
+
+-- Solved proof-of-work challenges, kept until they expire so they can't be
+-- used twice.
+CREATE TABLE used_challenges (
+    id TEXT PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX used_challenges_expires_at ON used_challenges(expires_at);
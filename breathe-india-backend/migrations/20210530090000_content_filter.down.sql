@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE posts DROP COLUMN held;
+DROP TABLE content_filter_decisions;
+DROP TABLE content_filter_rules;
+DROP TYPE FilterAction;
+DROP TYPE FilterRuleKind;
@@ -0,0 +1,30 @@
+-- Add up migration script here
+CREATE TYPE FilterRuleKind AS ENUM ('keyword', 'regex', 'domain_deny', 'domain_allow', 'max_links');
+CREATE TYPE FilterAction AS ENUM ('flag', 'hold', 'reject');
+
+CREATE TABLE content_filter_rules (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    kind FilterRuleKind NOT NULL,
+    pattern TEXT NOT NULL,
+    action FilterAction NOT NULL,
+    note TEXT NOT NULL DEFAULT '',
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_by UUID REFERENCES users(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- One row per rule a post matched. Rejected posts were never saved, so
+-- they have no post_id.
+CREATE TABLE content_filter_decisions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    rule_id UUID REFERENCES content_filter_rules(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    post_id UUID REFERENCES posts(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    userid UUID REFERENCES users(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    action FilterAction NOT NULL,
+    matched TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX content_filter_decisions_created_at ON content_filter_decisions(created_at DESC);
+
+-- Held posts are only shown to their authors and admins until reviewed.
+ALTER TABLE posts ADD COLUMN held BOOLEAN NOT NULL DEFAULT FALSE;
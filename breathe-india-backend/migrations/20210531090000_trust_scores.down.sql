@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE post_reports;
+ALTER TABLE users DROP COLUMN trust_score;
@@ -0,0 +1,17 @@
+-- Add up migration script here
+-- Recomputed by the trust worker. New accounts start at the base score.
+ALTER TABLE users ADD COLUMN trust_score SMALLINT NOT NULL DEFAULT 20;
+CREATE INDEX users_trust_score ON users(trust_score);
+
+-- The reported user is kept apart from the post, so reports still count
+-- after the post is deleted.
+CREATE TABLE post_reports (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    post_id UUID REFERENCES posts(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    reported_userid UUID NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    userid UUID NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (post_id, userid)
+);
+CREATE INDEX post_reports_reported_userid ON post_reports(reported_userid, created_at);
@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE posts DROP COLUMN org_id;
+DROP TABLE org_invitations;
+DROP TABLE org_members;
+DROP TABLE orgs;
+DROP TYPE OrgRole;
@@ -0,0 +1,37 @@
+-- Add up migration script here
+CREATE TYPE OrgRole AS ENUM ('owner', 'editor', 'viewer');
+
+-- Hospitals and NGOs that post as one, with each member signed in as
+-- themselves.
+CREATE TABLE orgs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE org_members (
+    org_id UUID NOT NULL REFERENCES orgs(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    userid UUID NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    role OrgRole NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (org_id, userid)
+);
+CREATE INDEX org_members_userid ON org_members(userid);
+
+-- By email, so people can be invited before they have signed in once.
+-- Emails are stored lowercased.
+CREATE TABLE org_invitations (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    org_id UUID NOT NULL REFERENCES orgs(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    role OrgRole NOT NULL,
+    invited_by UUID REFERENCES users(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (org_id, email)
+);
+CREATE INDEX org_invitations_email ON org_invitations(email);
+
+-- The author stays in userid. Posts of a deleted org go back to being
+-- their author's own.
+ALTER TABLE posts ADD COLUMN org_id UUID REFERENCES orgs(id) ON UPDATE RESTRICT ON DELETE SET NULL;
+CREATE INDEX posts_org_id ON posts(org_id);
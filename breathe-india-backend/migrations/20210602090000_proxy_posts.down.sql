@@ -0,0 +1,8 @@
+-- Add down migration script here
+ALTER TABLE posts
+    DROP COLUMN beneficiary_name,
+    DROP COLUMN beneficiary_relationship,
+    DROP COLUMN beneficiary_phone,
+    DROP COLUMN beneficiary_consent,
+    DROP COLUMN beneficiary_consent_at;
+DROP TYPE ConsentMethod;
@@ -0,0 +1,16 @@
+-- Add up migration script here
+CREATE TYPE ConsentMethod AS ENUM ('verbal', 'written', 'next_of_kin');
+
+-- Set when a volunteer posts for someone else. The contact_ columns stay
+-- the volunteer's, beneficiary_phone is the beneficiary's own.
+ALTER TABLE posts
+    ADD COLUMN beneficiary_name TEXT,
+    ADD COLUMN beneficiary_relationship TEXT,
+    ADD COLUMN beneficiary_phone TEXT,
+    ADD COLUMN beneficiary_consent ConsentMethod,
+    ADD COLUMN beneficiary_consent_at TIMESTAMPTZ,
+    ADD CONSTRAINT posts_beneficiary_consent CHECK (
+        beneficiary_name IS NULL
+        OR (beneficiary_consent IS NOT NULL AND beneficiary_consent_at IS NOT NULL)
+    );
+CREATE INDEX posts_userid_on_behalf_of ON posts(userid) WHERE beneficiary_name IS NOT NULL;
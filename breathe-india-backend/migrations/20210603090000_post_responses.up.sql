@@ -0,0 +1,18 @@
+-- Add up migration script here
+CREATE TYPE ResponseKind AS ENUM ('offer', 'question', 'update');
+
+-- Replies point at the response that started their thread, never at
+-- another reply. Deleted responses are blanked rather than removed, so
+-- the replies under them still make sense.
+CREATE TABLE post_responses (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    post_id UUID NOT NULL REFERENCES posts(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    parent_id UUID REFERENCES post_responses(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    userid UUID NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    kind ResponseKind NOT NULL,
+    body TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    deleted_at TIMESTAMPTZ,
+    deleted_by UUID REFERENCES users(id) ON UPDATE RESTRICT ON DELETE SET NULL
+);
+CREATE INDEX post_responses_post_id ON post_responses(post_id, created_at);
@@ -0,0 +1,14 @@
+-- Add down migration script here
+ALTER TABLE posts DROP COLUMN quantity_remaining;
+DROP TABLE pledges;
+DROP TYPE PledgeState;
+
+-- Postgres can't drop a value from an enum, so recreate it.
+UPDATE posts SET status = 'open' WHERE status = 'partially_fulfilled';
+ALTER TYPE PostStatus RENAME TO PostStatus_old;
+CREATE TYPE PostStatus AS ENUM ('open', 'fulfilled', 'closed');
+ALTER TABLE posts ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE posts
+    ALTER COLUMN status TYPE PostStatus USING status::text::PostStatus;
+ALTER TABLE posts ALTER COLUMN status SET DEFAULT 'open';
+DROP TYPE PostStatus_old;
@@ -0,0 +1,39 @@
+-- Add up migration script here
+ALTER TYPE PostStatus ADD VALUE 'partially_fulfilled' AFTER 'open';
+
+CREATE TYPE PledgeState AS ENUM ('pledged', 'delivered', 'cancelled');
+
+-- Someone promising part of what a needs post asks for, optionally from
+-- one of their supplies posts. Quantities are in whatever unit the need
+-- counts in.
+CREATE TABLE pledges (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    needs_id UUID NOT NULL REFERENCES posts(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    supplies_id UUID REFERENCES posts(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    userid UUID NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    eta TIMESTAMPTZ,
+    note TEXT NOT NULL DEFAULT '',
+    state PledgeState NOT NULL DEFAULT 'pledged',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX pledges_needs_id ON pledges(needs_id, created_at);
+CREATE INDEX pledges_userid ON pledges(userid, created_at);
+-- One open pledge per person per need; they can change it by cancelling.
+CREATE UNIQUE INDEX pledges_one_open ON pledges(needs_id, userid) WHERE state = 'pledged';
+
+-- How much of a need nobody has pledged or delivered yet. NULL when the
+-- need's quantity isn't a number.
+ALTER TABLE posts ADD COLUMN quantity_remaining INTEGER;
+
+-- Existing needs start with all of it remaining, parsed like
+-- `pledges::parse_amount` does. Nothing about them changed, so no events.
+ALTER TABLE posts DISABLE TRIGGER posts_record_event;
+UPDATE posts SET quantity_remaining = NULLIF(CEIL(needs.amount::NUMERIC)::INTEGER, 0)
+FROM (
+    SELECT id, SUBSTRING(quantity FROM '[0-9]+(?:\.[0-9]+)?') as amount
+    FROM posts WHERE post_type = 'needs'
+) needs
+WHERE posts.id = needs.id AND needs.amount::NUMERIC < 2147483647;
+ALTER TABLE posts ENABLE TRIGGER posts_record_event;
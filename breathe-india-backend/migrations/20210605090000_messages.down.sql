@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE user_blocks;
+DROP TABLE messages;
+DROP TABLE conversations;
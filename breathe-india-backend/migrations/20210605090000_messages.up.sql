@@ -0,0 +1,45 @@
+-- Add up migration script here
+-- Private conversations between a post's author and someone answering it,
+-- so neither has to publish a phone number. There is one per post and
+-- responder. Conversations outlive their post, so reports still have
+-- something to look at.
+CREATE TABLE conversations (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    post_id UUID REFERENCES posts(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    -- Whoever wrote the post when the conversation started.
+    author_id UUID NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    responder_id UUID NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    -- Each side has read every message sent up to then.
+    author_read_at TIMESTAMPTZ,
+    responder_read_at TIMESTAMPTZ,
+    last_message_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Moderators can only read a conversation once one side reports it.
+    reported_at TIMESTAMPTZ,
+    reported_by UUID REFERENCES users(id) ON UPDATE RESTRICT ON DELETE SET NULL,
+    report_reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (post_id, responder_id),
+    CHECK (author_id <> responder_id)
+);
+CREATE INDEX conversations_author_id ON conversations(author_id, last_message_at);
+CREATE INDEX conversations_responder_id ON conversations(responder_id, last_message_at);
+CREATE INDEX conversations_reported_at ON conversations(reported_at)
+    WHERE reported_at IS NOT NULL;
+
+CREATE TABLE messages (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    conversation_id UUID NOT NULL REFERENCES conversations(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    sender UUID NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    body TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX messages_conversation_id ON messages(conversation_id, created_at);
+
+-- Nobody can message someone who blocked them, or whom they blocked.
+CREATE TABLE user_blocks (
+    userid UUID NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    blocked_userid UUID NOT NULL REFERENCES users(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (userid, blocked_userid),
+    CHECK (userid <> blocked_userid)
+);
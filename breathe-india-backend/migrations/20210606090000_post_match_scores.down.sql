@@ -0,0 +1,6 @@
+-- Add down migration script here
+UPDATE post_matches
+SET score = post_matches.score + 0.2 * POWER(0.5,
+        GREATEST(EXTRACT(EPOCH FROM post_matches.computed_at - supplies.updated_at), 0) / 3600 / 24)
+FROM posts supplies
+WHERE supplies.id = post_matches.supplies_id;
@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- Stored scores no longer include freshness, which is added when they are
+-- read. Takes out what it was when each match was computed.
+UPDATE post_matches
+SET score = post_matches.score - 0.2 * POWER(0.5,
+        GREATEST(EXTRACT(EPOCH FROM post_matches.computed_at - supplies.updated_at), 0) / 3600 / 24)
+FROM posts supplies
+WHERE supplies.id = post_matches.supplies_id;
@@ -0,0 +1,13 @@
+-- Add up migration script here
+-- Who can edit, bump or delete each post. Org posts need the author to
+-- still be a member, so someone who was removed from the org can't keep
+-- editing what they posted for it.
+CREATE VIEW post_editors AS
+    SELECT id as post_id, userid
+    FROM posts
+    WHERE org_id IS NULL
+    UNION ALL
+    SELECT posts.id, org_members.userid
+    FROM posts
+    JOIN org_members ON org_members.org_id = posts.org_id
+    WHERE org_members.role IN ('owner', 'editor') OR org_members.userid = posts.userid;
@@ -0,0 +1,18 @@
+-- Add down migration script here
+DROP INDEX post_events_created_at;
+
+-- Snapshots taken meanwhile stay trimmed.
+CREATE OR REPLACE FUNCTION record_post_event() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        INSERT INTO post_events(post_id, kind, post) VALUES (NEW.id, 'created', to_jsonb(NEW));
+    ELSIF TG_OP = 'UPDATE' AND OLD.status IS DISTINCT FROM NEW.status THEN
+        INSERT INTO post_events(post_id, kind, post) VALUES (NEW.id, 'status_changed', to_jsonb(NEW));
+    ELSIF TG_OP = 'UPDATE' THEN
+        INSERT INTO post_events(post_id, kind, post) VALUES (NEW.id, 'updated', to_jsonb(NEW));
+    ELSE
+        INSERT INTO post_events(post_id, kind, post) VALUES (OLD.id, 'deleted', to_jsonb(OLD));
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
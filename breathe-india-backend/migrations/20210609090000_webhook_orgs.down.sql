@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP VIEW webhook_managers;
+DROP INDEX webhooks_org_id;
+ALTER TABLE webhooks DROP COLUMN org_id;
@@ -0,0 +1,17 @@
+-- Add up migration script here
+-- Webhooks an org's dashboard subscribes to belong to the org, so they
+-- outlive whoever set them up.
+ALTER TABLE webhooks ADD COLUMN org_id UUID REFERENCES orgs(id) ON UPDATE RESTRICT ON DELETE CASCADE;
+CREATE INDEX webhooks_org_id ON webhooks(org_id);
+
+-- Who can see and manage each webhook. Org webhooks are managed by the
+-- org's owners and editors, like its posts.
+CREATE VIEW webhook_managers AS
+    SELECT id as webhook_id, userid
+    FROM webhooks
+    WHERE org_id IS NULL
+    UNION ALL
+    SELECT webhooks.id, org_members.userid
+    FROM webhooks
+    JOIN org_members ON org_members.org_id = webhooks.org_id
+    WHERE org_members.role IN ('owner', 'editor');
@@ -0,0 +1,31 @@
+-- Add down migration script here
+CREATE OR REPLACE FUNCTION record_post_event() RETURNS TRIGGER AS $$
+DECLARE
+    p posts;
+    snapshot JSONB;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        p := OLD;
+    ELSE
+        p := NEW;
+    END IF;
+    snapshot := jsonb_build_object(
+        'post_type', p.post_type,
+        'state', p.state,
+        'district', p.district,
+        'city', p.city,
+        'spot', p.spot,
+        'item', p.item
+    );
+    IF TG_OP = 'INSERT' THEN
+        INSERT INTO post_events(post_id, kind, post) VALUES (p.id, 'created', snapshot);
+    ELSIF TG_OP = 'UPDATE' AND OLD.status IS DISTINCT FROM NEW.status THEN
+        INSERT INTO post_events(post_id, kind, post) VALUES (p.id, 'status_changed', snapshot);
+    ELSIF TG_OP = 'UPDATE' THEN
+        INSERT INTO post_events(post_id, kind, post) VALUES (p.id, 'updated', snapshot);
+    ELSE
+        INSERT INTO post_events(post_id, kind, post) VALUES (p.id, 'deleted', snapshot);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
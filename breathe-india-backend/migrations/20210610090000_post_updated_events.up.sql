@@ -0,0 +1,46 @@
+-- Add up migration script here
+-- Only edits to what a post says are `updated` events. Bumps and the like
+-- used to be too, so every subscriber heard about them.
+CREATE OR REPLACE FUNCTION record_post_event() RETURNS TRIGGER AS $$
+DECLARE
+    p posts;
+    snapshot JSONB;
+    -- Columns that change without the post saying anything new: bumps,
+    -- pledges coming in and content filter reviews.
+    bookkeeping TEXT[] := ARRAY[
+        'updated_at',
+        'bumped_at',
+        'quantity_remaining',
+        'held',
+        'beneficiary_consent_at'
+    ];
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        p := OLD;
+    ELSE
+        p := NEW;
+    END IF;
+    snapshot := jsonb_build_object(
+        'post_type', p.post_type,
+        'state', p.state,
+        'district', p.district,
+        'city', p.city,
+        'spot', p.spot,
+        'item', p.item
+    );
+    IF TG_OP = 'INSERT' THEN
+        INSERT INTO post_events(post_id, kind, post) VALUES (p.id, 'created', snapshot);
+    ELSIF TG_OP = 'UPDATE' AND OLD.status IS DISTINCT FROM NEW.status THEN
+        INSERT INTO post_events(post_id, kind, post) VALUES (p.id, 'status_changed', snapshot);
+    ELSIF TG_OP = 'UPDATE' AND OLD.held AND NOT NEW.held THEN
+        -- Nobody saw it while it was held, so to them it is new.
+        INSERT INTO post_events(post_id, kind, post) VALUES (p.id, 'created', snapshot);
+    ELSIF TG_OP = 'UPDATE' AND
+            to_jsonb(OLD) - bookkeeping IS DISTINCT FROM to_jsonb(NEW) - bookkeeping THEN
+        INSERT INTO post_events(post_id, kind, post) VALUES (p.id, 'updated', snapshot);
+    ELSIF TG_OP = 'DELETE' THEN
+        INSERT INTO post_events(post_id, kind, post) VALUES (p.id, 'deleted', snapshot);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- Every invitation email sent, so an org's daily cap still holds when
+-- invitations are withdrawn and sent again.
+CREATE TABLE org_invitations_sent (
+    org_id UUID NOT NULL REFERENCES orgs(id) ON UPDATE RESTRICT ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX org_invitations_sent_org_id ON org_invitations_sent(org_id, created_at);
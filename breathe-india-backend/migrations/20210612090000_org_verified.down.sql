@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE orgs DROP COLUMN verified;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Set by admins once they have checked the org is who it says it is.
+ALTER TABLE orgs ADD COLUMN verified BOOLEAN NOT NULL DEFAULT FALSE;
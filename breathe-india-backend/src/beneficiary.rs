@@ -0,0 +1,127 @@
+// Posts a volunteer makes for someone else, like a patient without a
+// smartphone. The post's contact stays the volunteer's, and the person it
+// is for is named on it with their own phone, so readers know who they are
+// helping and who to call. Volunteers have to say how the person agreed to
+// being posted about.
+
+use crate::contact::normalize_phone;
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_RELATIONSHIP_LEN: usize = 50;
+
+// How the person the post is for agreed to it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "snake_case")]
+pub enum ConsentMethod {
+    Verbal,
+    Written,
+    // For patients who can't answer for themselves.
+    NextOfKin,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BeneficiaryNew {
+    pub name: String,
+    // To the volunteer, like "neighbour" or "patient at our centre".
+    pub relationship: String,
+    pub phone: Option<String>,
+    pub consent: Option<ConsentMethod>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub enum BeneficiaryInvalid {
+    MissingName,
+    MissingRelationship,
+    TooLong,
+    InvalidPhone(String),
+    ConsentRequired,
+}
+
+impl BeneficiaryNew {
+    // Trims the name and relationship and normalizes the phone.
+    pub fn validate(&mut self) -> Result<(), BeneficiaryInvalid> {
+        self.name = self.name.trim().to_owned();
+        self.relationship = self.relationship.trim().to_owned();
+        if self.name.is_empty() {
+            return Err(BeneficiaryInvalid::MissingName);
+        }
+        if self.relationship.is_empty() {
+            return Err(BeneficiaryInvalid::MissingRelationship);
+        }
+        if self.name.chars().count() > MAX_NAME_LEN
+            || self.relationship.chars().count() > MAX_RELATIONSHIP_LEN
+        {
+            return Err(BeneficiaryInvalid::TooLong);
+        }
+        self.phone = match self.phone.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(phone) => Some(
+                normalize_phone(phone)
+                    .ok_or_else(|| BeneficiaryInvalid::InvalidPhone(phone.to_owned()))?,
+            ),
+        };
+        if self.consent.is_none() {
+            return Err(BeneficiaryInvalid::ConsentRequired);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn beneficiary() -> BeneficiaryNew {
+        BeneficiaryNew {
+            name: " Lakshmi Amma ".to_owned(),
+            relationship: "neighbour ".to_owned(),
+            phone: Some("+91 98765 43210".to_owned()),
+            consent: Some(ConsentMethod::Verbal),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut b = beneficiary();
+        assert_eq!(b.validate(), Ok(()));
+        assert_eq!(b.name, "Lakshmi Amma");
+        assert_eq!(b.relationship, "neighbour");
+        assert_eq!(b.phone.as_deref(), Some("9876543210"));
+
+        let mut b = BeneficiaryNew {
+            phone: Some(" ".to_owned()),
+            ..beneficiary()
+        };
+        assert_eq!(b.validate(), Ok(()));
+        assert_eq!(b.phone, None);
+
+        let mut b = BeneficiaryNew {
+            consent: None,
+            ..beneficiary()
+        };
+        assert_eq!(b.validate(), Err(BeneficiaryInvalid::ConsentRequired));
+        let mut b = BeneficiaryNew {
+            name: "  ".to_owned(),
+            ..beneficiary()
+        };
+        assert_eq!(b.validate(), Err(BeneficiaryInvalid::MissingName));
+        let mut b = BeneficiaryNew {
+            relationship: "".to_owned(),
+            ..beneficiary()
+        };
+        assert_eq!(b.validate(), Err(BeneficiaryInvalid::MissingRelationship));
+        let mut b = BeneficiaryNew {
+            phone: Some("12345".to_owned()),
+            ..beneficiary()
+        };
+        assert_eq!(
+            b.validate(),
+            Err(BeneficiaryInvalid::InvalidPhone("12345".to_owned()))
+        );
+        let mut b = BeneficiaryNew {
+            name: "x".repeat(MAX_NAME_LEN + 1),
+            ..beneficiary()
+        };
+        assert_eq!(b.validate(), Err(BeneficiaryInvalid::TooLong));
+    }
+}
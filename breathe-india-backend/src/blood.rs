@@ -0,0 +1,210 @@
+// ABO/Rh blood groups, as written on blood bank slips, and who can donate
+// to whom.
+
+use chrono::{Duration, NaiveDate};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, sqlx::Type)]
+pub enum BloodGroup {
+    #[serde(rename = "A+")]
+    #[sqlx(rename = "A+")]
+    APos,
+    #[serde(rename = "A-")]
+    #[sqlx(rename = "A-")]
+    ANeg,
+    #[serde(rename = "B+")]
+    #[sqlx(rename = "B+")]
+    BPos,
+    #[serde(rename = "B-")]
+    #[sqlx(rename = "B-")]
+    BNeg,
+    #[serde(rename = "AB+")]
+    #[sqlx(rename = "AB+")]
+    AbPos,
+    #[serde(rename = "AB-")]
+    #[sqlx(rename = "AB-")]
+    AbNeg,
+    #[serde(rename = "O+")]
+    #[sqlx(rename = "O+")]
+    OPos,
+    #[serde(rename = "O-")]
+    #[sqlx(rename = "O-")]
+    ONeg,
+}
+
+impl BloodGroup {
+    pub const ALL: [BloodGroup; 8] = [
+        BloodGroup::APos,
+        BloodGroup::ANeg,
+        BloodGroup::BPos,
+        BloodGroup::BNeg,
+        BloodGroup::AbPos,
+        BloodGroup::AbNeg,
+        BloodGroup::OPos,
+        BloodGroup::ONeg,
+    ];
+
+    // Same as the Postgres enum labels.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BloodGroup::APos => "A+",
+            BloodGroup::ANeg => "A-",
+            BloodGroup::BPos => "B+",
+            BloodGroup::BNeg => "B-",
+            BloodGroup::AbPos => "AB+",
+            BloodGroup::AbNeg => "AB-",
+            BloodGroup::OPos => "O+",
+            BloodGroup::ONeg => "O-",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().to_uppercase();
+        BloodGroup::ALL.iter().copied().find(|g| g.as_str() == s)
+    }
+
+    // (A antigen, B antigen, RhD antigen) on the red cells.
+    fn antigens(self) -> (bool, bool, bool) {
+        match self {
+            BloodGroup::APos => (true, false, true),
+            BloodGroup::ANeg => (true, false, false),
+            BloodGroup::BPos => (false, true, true),
+            BloodGroup::BNeg => (false, true, false),
+            BloodGroup::AbPos => (true, true, true),
+            BloodGroup::AbNeg => (true, true, false),
+            BloodGroup::OPos => (false, false, true),
+            BloodGroup::ONeg => (false, false, false),
+        }
+    }
+
+    pub fn can_donate_to(self, recipient: BloodGroup, kind: DonationKind) -> bool {
+        let (donor_a, donor_b, donor_rh) = self.antigens();
+        let (recipient_a, recipient_b, recipient_rh) = recipient.antigens();
+        match kind {
+            // Red cells must not carry an antigen the recipient lacks.
+            DonationKind::Blood => {
+                (!donor_a || recipient_a)
+                    && (!donor_b || recipient_b)
+                    && (!donor_rh || recipient_rh)
+            }
+            // Plasma carries antibodies against the antigens the donor
+            // lacks, so it works the other way round. Rh doesn't matter.
+            DonationKind::Plasma => (!recipient_a || donor_a) && (!recipient_b || donor_b),
+        }
+    }
+
+    // Every group that can donate to `recipient`.
+    pub fn donors_for(recipient: BloodGroup, kind: DonationKind) -> Vec<BloodGroup> {
+        BloodGroup::ALL
+            .iter()
+            .copied()
+            .filter(|donor| donor.can_donate_to(recipient, kind))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum DonationKind {
+    Blood,
+    // Convalescent plasma from people who have recovered from COVID.
+    Plasma,
+}
+
+// Whole blood donors have to wait this long between donations.
+const BLOOD_DONATION_GAP_DAYS: i64 = 90;
+// Plasma is given back quicker, since the red cells are returned.
+const PLASMA_DONATION_GAP_DAYS: i64 = 14;
+// Convalescent plasma is only useful between these many days after
+// recovery.
+const PLASMA_MIN_DAYS_AFTER_RECOVERY: i64 = 28;
+const PLASMA_MAX_DAYS_AFTER_RECOVERY: i64 = 120;
+
+// The dates a donor's history has to fall in to be able to donate today.
+#[derive(Debug, PartialEq)]
+pub struct EligibilityWindow {
+    pub last_donated_before: NaiveDate,
+    pub recovered_between: Option<(NaiveDate, NaiveDate)>,
+}
+
+impl EligibilityWindow {
+    pub fn new(kind: DonationKind, today: NaiveDate) -> Self {
+        let days = Duration::days;
+        match kind {
+            DonationKind::Blood => EligibilityWindow {
+                last_donated_before: today - days(BLOOD_DONATION_GAP_DAYS),
+                recovered_between: None,
+            },
+            DonationKind::Plasma => EligibilityWindow {
+                last_donated_before: today - days(PLASMA_DONATION_GAP_DAYS),
+                recovered_between: Some((
+                    today - days(PLASMA_MAX_DAYS_AFTER_RECOVERY),
+                    today - days(PLASMA_MIN_DAYS_AFTER_RECOVERY),
+                )),
+            },
+        }
+    }
+
+    // The queries check the same window in SQL, this mirrors it for tests.
+    #[cfg(test)]
+    pub fn admits(
+        &self,
+        last_donated_on: Option<NaiveDate>,
+        recovered_on: Option<NaiveDate>,
+    ) -> bool {
+        let donated_ok = last_donated_on.map_or(true, |d| d <= self.last_donated_before);
+        let recovered_ok = match self.recovered_between {
+            None => true,
+            Some((from, to)) => recovered_on.map_or(false, |r| from <= r && r <= to),
+        };
+        donated_ok && recovered_ok
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_blood_compatibility() {
+        use BloodGroup::*;
+        let blood = |recipient| BloodGroup::donors_for(recipient, DonationKind::Blood);
+        assert_eq!(blood(ONeg), vec![ONeg]);
+        assert_eq!(blood(APos), vec![APos, ANeg, OPos, ONeg]);
+        assert_eq!(blood(BNeg), vec![BNeg, ONeg]);
+        assert_eq!(blood(AbPos), BloodGroup::ALL.to_vec());
+    }
+
+    #[test]
+    fn test_plasma_compatibility() {
+        use BloodGroup::*;
+        let plasma = |recipient| BloodGroup::donors_for(recipient, DonationKind::Plasma);
+        assert_eq!(plasma(OPos), BloodGroup::ALL.to_vec());
+        assert_eq!(plasma(ANeg), vec![APos, ANeg, AbPos, AbNeg]);
+        assert_eq!(plasma(AbPos), vec![AbPos, AbNeg]);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(BloodGroup::parse(" ab- "), Some(BloodGroup::AbNeg));
+        assert_eq!(BloodGroup::parse("O+"), Some(BloodGroup::OPos));
+        assert_eq!(BloodGroup::parse("C+"), None);
+    }
+
+    #[test]
+    fn test_eligibility() {
+        let today = NaiveDate::from_ymd_opt(2021, 5, 20).unwrap();
+        let days_ago = |n| Some(today - Duration::days(n));
+
+        let blood = EligibilityWindow::new(DonationKind::Blood, today);
+        assert!(blood.admits(None, None));
+        assert!(blood.admits(days_ago(90), None));
+        assert!(!blood.admits(days_ago(30), None));
+
+        let plasma = EligibilityWindow::new(DonationKind::Plasma, today);
+        assert!(!plasma.admits(None, None));
+        assert!(plasma.admits(None, days_ago(30)));
+        assert!(!plasma.admits(None, days_ago(10)));
+        assert!(!plasma.admits(None, days_ago(150)));
+        assert!(!plasma.admits(days_ago(7), days_ago(30)));
+    }
+}
@@ -0,0 +1,347 @@
+// Proof-of-work challenges for new and low trust accounts, so that signing
+// up and posting in bulk costs CPU time, without a third-party CAPTCHA.
+// Challenges are signed rather than stored. Solved ones are remembered until
+// they expire, so each can only be used once.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, SubsecRound, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Request, State};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::myres::{log_background_error, HasStatusCode, MyRes};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![challenge]
+}
+
+// Leading zero bits in the hash of a solution. Each one doubles the work.
+const DEFAULT_DIFFICULTY: u32 = 16;
+const MAX_DIFFICULTY: u32 = 32;
+const DEFAULT_NEW_ACCOUNT_DAYS: i64 = 3;
+// Accounts whose trust score is below this keep getting challenges, however
+// old they are. New accounts start right at it.
+const DEFAULT_LOW_TRUST_SCORE: i16 = 20;
+const CHALLENGE_TTL_MINUTES: i64 = 10;
+const PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+// "<challenge>:<nonce>", sent with requests that need a solved challenge.
+pub const SOLUTION_HEADER: &str = "X-Challenge-Solution";
+
+pub struct Challenges {
+    secret: String,
+    difficulty: u32,
+    new_account_age: Duration,
+    low_trust_score: i16,
+}
+
+#[derive(Serialize)]
+pub struct Challenge {
+    challenge: String,
+    difficulty: u32,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub enum ChallengeError {
+    // Fetch one from /challenge, solve it and send it again.
+    Required,
+    Invalid,
+    Expired,
+    Reused,
+}
+
+impl HasStatusCode for ChallengeError {
+    fn get_status(&self) -> Status {
+        Status::Forbidden
+    }
+}
+
+impl Challenges {
+    // CHALLENGE_DIFFICULTY sets the leading zero bits, 0 turns challenges
+    // off. Accounts count as new for CHALLENGE_NEW_ACCOUNT_DAYS, and as low
+    // trust below CHALLENGE_LOW_TRUST_SCORE.
+    pub fn from_env() -> Result<Self> {
+        let secret = std::env::var("JWT_SECRET").context("Get JWT_SECRET env var")?;
+        let difficulty = match std::env::var("CHALLENGE_DIFFICULTY") {
+            Ok(d) => d
+                .trim()
+                .parse()
+                .ok()
+                .filter(|d| *d <= MAX_DIFFICULTY)
+                .ok_or_else(|| anyhow!("Invalid CHALLENGE_DIFFICULTY"))?,
+            Err(_) => DEFAULT_DIFFICULTY,
+        };
+        let days = match std::env::var("CHALLENGE_NEW_ACCOUNT_DAYS") {
+            Ok(d) => d
+                .trim()
+                .parse()
+                .ok()
+                .filter(|d| *d >= 0)
+                .ok_or_else(|| anyhow!("Invalid CHALLENGE_NEW_ACCOUNT_DAYS"))?,
+            Err(_) => DEFAULT_NEW_ACCOUNT_DAYS,
+        };
+        let low_trust_score = match std::env::var("CHALLENGE_LOW_TRUST_SCORE") {
+            Ok(s) => s
+                .trim()
+                .parse()
+                .ok()
+                .filter(|s| *s >= 0)
+                .ok_or_else(|| anyhow!("Invalid CHALLENGE_LOW_TRUST_SCORE"))?,
+            Err(_) => DEFAULT_LOW_TRUST_SCORE,
+        };
+        Ok(Challenges {
+            secret,
+            difficulty,
+            new_account_age: Duration::days(days),
+            low_trust_score,
+        })
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC takes keys of any size");
+        mac.update(b"challenge.");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        hex(&self.mac(payload).finalize().into_bytes())
+    }
+
+    fn signed(&self, payload: &str, signature: &str) -> bool {
+        let signature = (0..signature.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(signature.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>();
+        match signature {
+            Some(signature) => self.mac(payload).verify_slice(&signature).is_ok(),
+            None => false,
+        }
+    }
+
+    // "<id>.<difficulty>.<expiry timestamp>.<signature>"
+    fn issue(&self, now: DateTime<Utc>) -> Challenge {
+        let id = hex(&rand::random::<[u8; 16]>());
+        // Whole seconds, as that is what the challenge carries.
+        let expires_at = (now + Duration::minutes(CHALLENGE_TTL_MINUTES)).trunc_subsecs(0);
+        let payload = format!("{}.{}.{}", id, self.difficulty, expires_at.timestamp());
+        Challenge {
+            challenge: format!("{}.{}", payload, self.sign(&payload)),
+            difficulty: self.difficulty,
+            expires_at,
+        }
+    }
+
+    // Returns the id of the solved challenge and when it expires.
+    fn verify(
+        &self,
+        solution: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(String, DateTime<Utc>), ChallengeError> {
+        let (challenge, _nonce) = solution.rsplit_once(':').ok_or(ChallengeError::Invalid)?;
+        let (payload, signature) = challenge.rsplit_once('.').ok_or(ChallengeError::Invalid)?;
+        if !self.signed(payload, signature) {
+            return Err(ChallengeError::Invalid);
+        }
+        let mut parts = payload.split('.');
+        let (id, difficulty, expires_at) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(id), Some(difficulty), Some(expires_at)) => (id, difficulty, expires_at),
+            _ => return Err(ChallengeError::Invalid),
+        };
+        let difficulty: u32 = difficulty.parse().map_err(|_| ChallengeError::Invalid)?;
+        let expires_at: i64 = expires_at.parse().map_err(|_| ChallengeError::Invalid)?;
+        let expires_at = Utc
+            .timestamp_opt(expires_at, 0)
+            .single()
+            .ok_or(ChallengeError::Invalid)?;
+        if expires_at < now {
+            return Err(ChallengeError::Expired);
+        }
+        // Challenges issued before the difficulty was raised are too easy.
+        if difficulty < self.difficulty || leading_zero_bits(&Sha256::digest(solution)) < difficulty
+        {
+            return Err(ChallengeError::Invalid);
+        }
+        Ok((id.to_owned(), expires_at))
+    }
+
+    // New accounts, and ones that haven't been signed up yet when `userid`
+    // is None, need challenges until they are verified. So do accounts with
+    // a low trust score, verified or not.
+    pub async fn required(&self, db: &PgPool, userid: Option<Uuid>) -> sqlx::Result<bool> {
+        if self.difficulty == 0 {
+            return Ok(false);
+        }
+        let userid = match userid {
+            Some(userid) => userid,
+            None => return Ok(true),
+        };
+        let user = sqlx::query!(
+            "SELECT verified, created_at, trust_score FROM users WHERE id = $1",
+            userid
+        )
+        .fetch_optional(db)
+        .await?;
+        Ok(match user {
+            Some(user) => {
+                user.trust_score < self.low_trust_score
+                    || (!user.verified && user.created_at > Utc::now() - self.new_account_age)
+            }
+            None => true,
+        })
+    }
+
+    // Lets the request through if it needs no challenge, or carries a solved
+    // one, which is then used up.
+    pub async fn check(
+        &self,
+        db: &PgPool,
+        userid: Option<Uuid>,
+        solution: &ChallengeSolution,
+    ) -> Result<Result<(), ChallengeError>> {
+        if !self.required(db, userid).await? {
+            return Ok(Ok(()));
+        }
+        let solution = match &solution.0 {
+            Some(solution) => solution,
+            None => return Ok(Err(ChallengeError::Required)),
+        };
+        let (id, expires_at) = match self.verify(solution, Utc::now()) {
+            Ok(solved) => solved,
+            Err(e) => return Ok(Err(e)),
+        };
+        let res = sqlx::query!(
+            r#"INSERT INTO used_challenges(id, expires_at)
+            VALUES ($1, $2)
+            ON CONFLICT (id) DO NOTHING"#,
+            id,
+            expires_at
+        )
+        .execute(db)
+        .await
+        .context("Use up challenge")?;
+        if res.rows_affected() == 0 {
+            return Ok(Err(ChallengeError::Reused));
+        }
+        Ok(Ok(()))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for b in hash {
+        bits += b.leading_zeros();
+        if *b != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+// The solution sent with a request, if any.
+pub struct ChallengeSolution(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ChallengeSolution {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let solution = request.headers().get_one(SOLUTION_HEADER);
+        Outcome::Success(ChallengeSolution(solution.map(|s| s.to_owned())))
+    }
+}
+
+// Removes used challenges once they have expired, as they can't be
+// replayed anymore.
+pub async fn run_pruner(db: PgPool) {
+    loop {
+        let res = sqlx::query!("DELETE FROM used_challenges WHERE expires_at < NOW()")
+            .execute(&db)
+            .await;
+        if let Err(e) = res {
+            log_background_error("challenge_pruner", &anyhow::Error::from(e));
+        }
+        tokio::time::sleep(PRUNE_INTERVAL).await;
+    }
+}
+
+// A puzzle to solve by finding a nonce for which the SHA-256 of
+// "<challenge>:<nonce>" starts with `difficulty` zero bits.
+#[get("/challenge")]
+async fn challenge(challenges: State<'_, Challenges>) -> MyRes<Challenge, ()> {
+    MyRes::Ok(challenges.issue(Utc::now()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn challenges(difficulty: u32) -> Challenges {
+        Challenges {
+            secret: "secret".to_owned(),
+            difficulty,
+            new_account_age: Duration::days(DEFAULT_NEW_ACCOUNT_DAYS),
+            low_trust_score: DEFAULT_LOW_TRUST_SCORE,
+        }
+    }
+
+    fn solve(challenge: &str, difficulty: u32) -> String {
+        (0u64..)
+            .map(|nonce| format!("{}:{}", challenge, nonce))
+            .find(|s| leading_zero_bits(&Sha256::digest(s)) >= difficulty)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0x00, 0x10, 0x00]), 11);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+
+    #[test]
+    fn test_verify() {
+        let now = Utc::now();
+        let c = challenges(8);
+        let issued = c.issue(now);
+        let solution = solve(&issued.challenge, 8);
+        let (id, expires_at) = c.verify(&solution, now).unwrap();
+        assert_eq!(id.len(), 32);
+        assert_eq!(expires_at, issued.expires_at);
+
+        // Unsolved, expired or tampered with.
+        let unsolved = (0u64..)
+            .map(|nonce| format!("{}:{}", issued.challenge, nonce))
+            .find(|s| leading_zero_bits(&Sha256::digest(s)) < 8)
+            .unwrap();
+        assert_eq!(c.verify(&unsolved, now), Err(ChallengeError::Invalid));
+        let later = now + Duration::minutes(CHALLENGE_TTL_MINUTES + 1);
+        assert_eq!(c.verify(&solution, later), Err(ChallengeError::Expired));
+        let easier = solution.replacen(".8.", ".0.", 1);
+        assert_eq!(c.verify(&easier, now), Err(ChallengeError::Invalid));
+        assert_eq!(c.verify("garbage", now), Err(ChallengeError::Invalid));
+
+        // Signed by someone else.
+        let other = Challenges {
+            secret: "other".to_owned(),
+            ..challenges(8)
+        };
+        assert_eq!(other.verify(&solution, now), Err(ChallengeError::Invalid));
+
+        // Solved before the difficulty went up.
+        assert_eq!(
+            challenges(12).verify(&solution, now),
+            Err(ChallengeError::Invalid)
+        );
+    }
+}
@@ -0,0 +1,290 @@
+// Contact details for a post. They are kept out of the listing and only
+// handed out one post at a time through `reveal_contact`, so they can't be
+// scraped in bulk, and every reveal is logged.
+
+use rocket::http::Status;
+use rocket::State;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::myres::{HasStatusCode, MyRes};
+use crate::{fail, LoggedInUser};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![posts_reveal_contact]
+}
+
+pub const MAX_PHONES: usize = 5;
+
+// Who may reveal a post's contact details. Its author always can.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "snake_case")]
+pub enum ContactVisibility {
+    Public,
+    LoggedIn,
+    Verified,
+}
+
+impl Default for ContactVisibility {
+    fn default() -> Self {
+        ContactVisibility::LoggedIn
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct ContactNew {
+    #[serde(default)]
+    pub phones: Vec<String>,
+    pub whatsapp: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub visibility: ContactVisibility,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub enum ContactInvalid {
+    TooManyPhones,
+    InvalidPhone(String),
+    InvalidEmail,
+}
+
+impl ContactNew {
+    // Normalizes phone numbers to their ten digits and drops empty fields.
+    pub fn validate(&mut self) -> Result<(), ContactInvalid> {
+        let mut phones = vec![];
+        for phone in self.phones.iter().filter(|p| !p.trim().is_empty()) {
+            let phone = normalize_phone(phone)
+                .ok_or_else(|| ContactInvalid::InvalidPhone(phone.clone()))?;
+            if !phones.contains(&phone) {
+                phones.push(phone);
+            }
+        }
+        if phones.len() > MAX_PHONES {
+            return Err(ContactInvalid::TooManyPhones);
+        }
+        self.phones = phones;
+
+        self.whatsapp = match self.whatsapp.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(whatsapp) => Some(
+                normalize_phone(whatsapp)
+                    .ok_or_else(|| ContactInvalid::InvalidPhone(whatsapp.to_owned()))?,
+            ),
+        };
+
+        self.email = match self.email.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(email) if is_email(email) => Some(email.to_owned()),
+            Some(_) => return Err(ContactInvalid::InvalidEmail),
+        };
+        Ok(())
+    }
+}
+
+// Indian numbers, written with or without +91 or a leading 0, and with any
+// spaces, dashes, dots or brackets. Returns the ten digits.
+pub fn normalize_phone(phone: &str) -> Option<String> {
+    let phone = phone.trim();
+    let mut digits = String::new();
+    for (i, c) in phone.chars().enumerate() {
+        match c {
+            '0'..='9' => digits.push(c),
+            '+' if i == 0 => {}
+            ' ' | '-' | '.' | '(' | ')' => {}
+            _ => return None,
+        }
+    }
+    let digits = match digits.len() {
+        10 => &digits[..],
+        11 if digits.starts_with('0') => &digits[1..],
+        12 if digits.starts_with("91") => &digits[2..],
+        _ => return None,
+    };
+    if digits.starts_with('0') {
+        return None;
+    }
+    Some(digits.to_owned())
+}
+
+pub fn is_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((user, domain)) => {
+            !user.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.chars().any(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+#[derive(Serialize)]
+pub struct Contact {
+    phones: Vec<String>,
+    whatsapp: Option<String>,
+    email: Option<String>,
+    visibility: ContactVisibility,
+    // The phone of whoever the post is for, when the author posted for
+    // someone else. The other fields are the author's.
+    beneficiary_phone: Option<String>,
+}
+
+#[derive(Serialize)]
+pub enum RevealError {
+    NotFound,
+    LoginRequired,
+    VerifiedOnly,
+}
+
+impl HasStatusCode for RevealError {
+    fn get_status(&self) -> Status {
+        match self {
+            RevealError::NotFound => Status::NotFound,
+            RevealError::LoginRequired => Status::Unauthorized,
+            RevealError::VerifiedOnly => Status::Forbidden,
+        }
+    }
+}
+
+// Posts held by the content filter are only revealed to whoever can edit
+// them and to admins, like the posts themselves.
+#[post("/posts/<id>/reveal_contact")]
+async fn posts_reveal_contact(
+    id: rocket_contrib::uuid::Uuid,
+    viewer: Option<LoggedInUser>,
+    db: State<'_, PgPool>,
+) -> MyRes<Contact, RevealError> {
+    let id: Uuid = id.into_inner();
+    let viewer = viewer.map(|v| v.0);
+    let res = sqlx::query!(
+        r#"SELECT posts.userid,
+                  posts.contact_phones,
+                  posts.contact_whatsapp,
+                  posts.contact_email,
+                  posts.contact_visibility as "contact_visibility: ContactVisibility",
+                  posts.beneficiary_phone,
+                  COALESCE(users.verified, FALSE) as "viewer_verified!"
+           FROM posts
+           LEFT JOIN users ON users.id = $2
+           WHERE posts.id = $1
+             AND (NOT posts.held
+                  OR posts.id IN (SELECT post_id FROM post_editors WHERE userid = $2)
+                  OR COALESCE(users.admin, FALSE))"#,
+        id,
+        viewer,
+    )
+    .fetch_optional(&*db)
+    .await;
+    let post = match fail!(res) {
+        Some(post) => post,
+        None => return MyRes::Err(RevealError::NotFound),
+    };
+
+    let is_author = viewer == Some(post.userid);
+    if !is_author {
+        match post.contact_visibility {
+            ContactVisibility::Public => {}
+            ContactVisibility::LoggedIn if viewer.is_none() => {
+                return MyRes::Err(RevealError::LoginRequired)
+            }
+            ContactVisibility::LoggedIn => {}
+            ContactVisibility::Verified if viewer.is_none() => {
+                return MyRes::Err(RevealError::LoginRequired)
+            }
+            ContactVisibility::Verified if !post.viewer_verified => {
+                return MyRes::Err(RevealError::VerifiedOnly)
+            }
+            ContactVisibility::Verified => {}
+        }
+
+        let res = sqlx::query!(
+            "INSERT INTO contact_reveals(post_id, viewer) VALUES ($1, $2)",
+            id,
+            viewer,
+        )
+        .execute(&*db)
+        .await;
+        fail!(res);
+    }
+
+    MyRes::Ok(Contact {
+        phones: post.contact_phones,
+        whatsapp: post.contact_whatsapp,
+        email: post.contact_email,
+        visibility: post.contact_visibility,
+        beneficiary_phone: post.beneficiary_phone,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_phone() {
+        let valid = &[
+            "9876543210",
+            "98765 43210",
+            "+91 98765-43210",
+            "+919876543210",
+            "919876543210",
+            "09876543210",
+            "(987) 654.3210",
+        ];
+        for phone in valid {
+            assert_eq!(
+                normalize_phone(phone).as_deref(),
+                Some("9876543210"),
+                "{}",
+                phone
+            );
+        }
+        assert_eq!(
+            normalize_phone("0484 2345678").as_deref(),
+            Some("4842345678")
+        );
+
+        let invalid = &[
+            "",
+            "12345",
+            "98765432100",
+            "0012345678",
+            "9876x43210",
+            "98+76543210",
+        ];
+        for phone in invalid {
+            assert_eq!(normalize_phone(phone), None, "{}", phone);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut contact = ContactNew {
+            phones: vec![
+                "98765 43210".to_owned(),
+                "".to_owned(),
+                "+919876543210".to_owned(),
+            ],
+            whatsapp: Some(" ".to_owned()),
+            email: Some(" help@example.org ".to_owned()),
+            visibility: ContactVisibility::Public,
+        };
+        assert_eq!(contact.validate(), Ok(()));
+        assert_eq!(contact.phones, vec!["9876543210"]);
+        assert_eq!(contact.whatsapp, None);
+        assert_eq!(contact.email.as_deref(), Some("help@example.org"));
+
+        let mut contact = ContactNew {
+            email: Some("not an email".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(contact.validate(), Err(ContactInvalid::InvalidEmail));
+
+        let mut contact = ContactNew {
+            phones: (0..6).map(|i| format!("987654321{}", i)).collect(),
+            ..Default::default()
+        };
+        assert_eq!(contact.validate(), Err(ContactInvalid::TooManyPhones));
+    }
+}
@@ -0,0 +1,657 @@
+// Rules that catch spam and scams in posts, like "advance payment" offers
+// with payment links. Admins keep the rules in the database. A post matching
+// a rule is rejected, held until an admin reviews it, or let through but
+// flagged, and every match is logged.
+
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::myres::{HasStatusCode, MyRes};
+use crate::params::{InvalidParam, PageParams};
+use crate::{bail, fail, is_admin, LoggedInUser};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        rules,
+        rules_create,
+        rules_update,
+        rules_delete,
+        decisions,
+        posts_review,
+    ]
+}
+
+const MAX_REGEX_SIZE: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "snake_case")]
+pub enum FilterRuleKind {
+    // Text that appears anywhere, ignoring case and spacing.
+    Keyword,
+    // A regular expression, ignoring case.
+    Regex,
+    // Comma separated domains that links may not point to. Links that aren't
+    // http(s) go by their scheme, so "upi" catches payment links.
+    DomainDeny,
+    // Comma separated domains that are the only ones links may point to.
+    DomainAllow,
+    // The most links a post may have.
+    MaxLinks,
+}
+
+// In increasing order of severity, the most severe matching rule wins.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(rename_all = "lowercase")]
+pub enum FilterAction {
+    Flag,
+    Hold,
+    Reject,
+}
+
+impl FilterAction {
+    fn parse(action: &str) -> Option<Self> {
+        match action {
+            "flag" => Some(FilterAction::Flag),
+            "hold" => Some(FilterAction::Hold),
+            "reject" => Some(FilterAction::Reject),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct FilterRule {
+    id: Uuid,
+    kind: FilterRuleKind,
+    pattern: String,
+    action: FilterAction,
+    note: String,
+    enabled: bool,
+    created_by: Option<Uuid>,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct FilterRuleNew {
+    kind: FilterRuleKind,
+    pattern: String,
+    action: FilterAction,
+    // Why the rule is there, for other admins.
+    #[serde(default)]
+    note: String,
+    #[serde(default = "enabled_default")]
+    enabled: bool,
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub enum FilterRuleInvalid {
+    EmptyPattern,
+    InvalidRegex(String),
+    InvalidDomain(String),
+    InvalidMaxLinks,
+}
+
+enum Matcher {
+    Keyword(String),
+    Regex(Regex),
+    DomainDeny(Vec<String>),
+    DomainAllow(Vec<String>),
+    MaxLinks(usize),
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn parse_domains(pattern: &str) -> Result<Vec<String>, FilterRuleInvalid> {
+    let domains = pattern
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|d| !d.is_empty())
+        .map(|d| d.trim_start_matches("www.").to_lowercase())
+        .collect::<Vec<_>>();
+    if let Some(d) = domains.iter().find(|d| {
+        !d.chars()
+            .all(|c| c.is_alphanumeric() || c == '.' || c == '-')
+    }) {
+        return Err(FilterRuleInvalid::InvalidDomain(d.clone()));
+    }
+    Ok(domains)
+}
+
+impl Matcher {
+    fn compile(kind: FilterRuleKind, pattern: &str) -> Result<Self, FilterRuleInvalid> {
+        if pattern.trim().is_empty() {
+            return Err(FilterRuleInvalid::EmptyPattern);
+        }
+        Ok(match kind {
+            FilterRuleKind::Keyword => Matcher::Keyword(normalize(pattern)),
+            FilterRuleKind::Regex => {
+                let regex = RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .size_limit(MAX_REGEX_SIZE)
+                    .build()
+                    .map_err(|e| FilterRuleInvalid::InvalidRegex(e.to_string()))?;
+                Matcher::Regex(regex)
+            }
+            FilterRuleKind::DomainDeny => Matcher::DomainDeny(parse_domains(pattern)?),
+            FilterRuleKind::DomainAllow => Matcher::DomainAllow(parse_domains(pattern)?),
+            FilterRuleKind::MaxLinks => {
+                let max = pattern.trim().parse();
+                Matcher::MaxLinks(max.map_err(|_| FilterRuleInvalid::InvalidMaxLinks)?)
+            }
+        })
+    }
+
+    // Returns what matched.
+    fn find(&self, text: &str, links: &[Link]) -> Option<String> {
+        let in_list = |domain: &str, list: &[String]| {
+            list.iter()
+                .any(|d| domain == d || domain.ends_with(&format!(".{}", d)))
+        };
+        match self {
+            Matcher::Keyword(keyword) => normalize(text)
+                .contains(keyword.as_str())
+                .then(|| keyword.clone()),
+            Matcher::Regex(regex) => regex.find(text).map(|m| m.as_str().to_owned()),
+            Matcher::DomainDeny(domains) => links
+                .iter()
+                .find(|l| in_list(&l.domain, domains))
+                .map(|l| l.url.clone()),
+            Matcher::DomainAllow(domains) => links
+                .iter()
+                .find(|l| !in_list(&l.domain, domains))
+                .map(|l| l.url.clone()),
+            Matcher::MaxLinks(max) => {
+                (links.len() > *max).then(|| format!("{} links", links.len()))
+            }
+        }
+    }
+}
+
+struct Link {
+    url: String,
+    domain: String,
+}
+
+// Links with a scheme, starting with www. or a bare domain with a path.
+fn links(text: &str) -> Vec<Link> {
+    static LINK: once_cell::sync::Lazy<Regex> = once_cell::sync::Lazy::new(|| {
+        Regex::new(
+            r"(?i)\b(?:[a-z][a-z0-9+.-]*://[^\s<>]+|www\.[^\s<>]+|(?:[a-z0-9-]+\.)+[a-z]{2,}/[^\s<>]*)",
+        )
+        .unwrap()
+    });
+    LINK.find_iter(text)
+        .map(|m| {
+            let url = m.as_str().trim_end_matches(['.', ',', ')', '!', '?']);
+            let parsed = if url.contains("://") {
+                reqwest::Url::parse(url)
+            } else {
+                reqwest::Url::parse(&format!("http://{}", url))
+            };
+            let domain = match &parsed {
+                Ok(u) if u.scheme() == "http" || u.scheme() == "https" => {
+                    u.host_str().unwrap_or("").to_lowercase()
+                }
+                Ok(u) => u.scheme().to_owned(),
+                Err(_) => url.split("://").next().unwrap_or("").to_lowercase(),
+            };
+            Link {
+                url: url.to_owned(),
+                domain: domain.trim_start_matches("www.").to_owned(),
+            }
+        })
+        .collect()
+}
+
+struct RuleMatch {
+    rule_id: Uuid,
+    action: FilterAction,
+    matched: String,
+}
+
+// The rules a post matched.
+pub struct Verdict {
+    matches: Vec<RuleMatch>,
+}
+
+impl Verdict {
+    // None if the post can go up as it is.
+    pub fn action(&self) -> Option<FilterAction> {
+        self.matches.iter().map(|m| m.action).max()
+    }
+
+    pub fn held(&self) -> bool {
+        self.action() == Some(FilterAction::Hold)
+    }
+
+    // `post_id` is None for rejected posts, which were never saved.
+    pub async fn log(&self, db: &PgPool, userid: Uuid, post_id: Option<Uuid>) -> Result<()> {
+        for m in &self.matches {
+            sqlx::query!(
+                r#"INSERT INTO content_filter_decisions(rule_id, post_id, userid, action, matched)
+                VALUES ($1, $2, $3, $4, $5)"#,
+                m.rule_id,
+                post_id,
+                userid,
+                m.action: _,
+                m.matched,
+            )
+            .execute(db)
+            .await
+            .context("Log content filter decision")?;
+        }
+        Ok(())
+    }
+}
+
+fn evaluate_rules(rules: &[(Uuid, FilterAction, Matcher)], text: &str) -> Verdict {
+    let links = links(text);
+    let matches = rules
+        .iter()
+        .filter_map(|(rule_id, action, matcher)| {
+            Some(RuleMatch {
+                rule_id: *rule_id,
+                action: *action,
+                matched: matcher.find(text, &links)?,
+            })
+        })
+        .collect();
+    Verdict { matches }
+}
+
+// Runs the enabled rules over everything the author wrote in a post.
+pub async fn evaluate(db: &PgPool, text: &str) -> Result<Verdict> {
+    let rules = sqlx::query!(
+        r#"SELECT id, kind as "kind: FilterRuleKind", pattern, action as "action: FilterAction"
+        FROM content_filter_rules WHERE enabled"#
+    )
+    .fetch_all(db)
+    .await
+    .context("Fetch content filter rules")?;
+    // Rules were checked when they were saved.
+    let rules = rules
+        .into_iter()
+        .filter_map(|r| Some((r.id, r.action, Matcher::compile(r.kind, &r.pattern).ok()?)))
+        .collect::<Vec<_>>();
+    Ok(evaluate_rules(&rules, text))
+}
+
+#[derive(Serialize)]
+pub enum FilterError {
+    NotAdmin,
+    NotFound,
+    Invalid(FilterRuleInvalid),
+    InvalidParam(InvalidParam),
+}
+
+impl HasStatusCode for FilterError {
+    fn get_status(&self) -> Status {
+        match self {
+            FilterError::NotAdmin => Status::Forbidden,
+            FilterError::NotFound => Status::NotFound,
+            FilterError::Invalid(_) | FilterError::InvalidParam(_) => Status::BadRequest,
+        }
+    }
+}
+
+#[get("/content_filter/rules")]
+async fn rules(user: LoggedInUser, db: State<'_, PgPool>) -> MyRes<Vec<FilterRule>, FilterError> {
+    if !fail!(is_admin(&db, user.0).await) {
+        return MyRes::Err(FilterError::NotAdmin);
+    }
+    let res = sqlx::query_as!(
+        FilterRule,
+        r#"
+        SELECT id,
+               kind as "kind: _",
+               pattern,
+               action as "action: _",
+               note,
+               enabled,
+               created_by,
+               created_at
+        FROM content_filter_rules
+        ORDER BY created_at DESC
+        "#
+    )
+    .fetch_all(&*db)
+    .await;
+    let rules = fail!(res);
+
+    MyRes::Ok(rules)
+}
+
+#[post("/content_filter/rules", data = "<data>")]
+async fn rules_create(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<FilterRuleNew>,
+) -> MyRes<FilterRule, FilterError> {
+    if !fail!(is_admin(&db, user.0).await) {
+        return MyRes::Err(FilterError::NotAdmin);
+    }
+    bail!(
+        Matcher::compile(data.kind, &data.pattern),
+        FilterError::Invalid
+    );
+    let res = sqlx::query_as!(
+        FilterRule,
+        r#"INSERT INTO content_filter_rules(kind, pattern, action, note, enabled, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING
+               id,
+               kind as "kind: _",
+               pattern,
+               action as "action: _",
+               note,
+               enabled,
+               created_by,
+               created_at
+        "#,
+        data.kind: _,
+        data.pattern.trim(),
+        data.action: _,
+        data.note.trim(),
+        data.enabled,
+        user.0,
+    )
+    .fetch_one(&*db)
+    .await;
+    let rule = fail!(res);
+
+    MyRes::Ok(rule)
+}
+
+#[put("/content_filter/rules/<id>", data = "<data>")]
+async fn rules_update(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<FilterRuleNew>,
+) -> MyRes<FilterRule, FilterError> {
+    if !fail!(is_admin(&db, user.0).await) {
+        return MyRes::Err(FilterError::NotAdmin);
+    }
+    bail!(
+        Matcher::compile(data.kind, &data.pattern),
+        FilterError::Invalid
+    );
+    let res = sqlx::query_as!(
+        FilterRule,
+        r#"UPDATE content_filter_rules SET
+            kind = $2,
+            pattern = $3,
+            action = $4,
+            note = $5,
+            enabled = $6
+        WHERE id = $1
+        RETURNING
+               id,
+               kind as "kind: _",
+               pattern,
+               action as "action: _",
+               note,
+               enabled,
+               created_by,
+               created_at
+        "#,
+        id.into_inner(),
+        data.kind: _,
+        data.pattern.trim(),
+        data.action: _,
+        data.note.trim(),
+        data.enabled,
+    )
+    .fetch_optional(&*db)
+    .await;
+    let rule = fail!(res);
+    let rule = bail!(rule.ok_or(()), |_| FilterError::NotFound);
+
+    MyRes::Ok(rule)
+}
+
+// Decisions the rule made stay in the log, but without the rule. Disabling
+// it instead keeps them readable.
+#[delete("/content_filter/rules/<id>")]
+async fn rules_delete(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<(), FilterError> {
+    if !fail!(is_admin(&db, user.0).await) {
+        return MyRes::Err(FilterError::NotAdmin);
+    }
+    let res = sqlx::query!(
+        "DELETE FROM content_filter_rules WHERE id = $1",
+        id.into_inner()
+    )
+    .execute(&*db)
+    .await;
+    let res = fail!(res);
+    if res.rows_affected() == 0 {
+        return MyRes::Err(FilterError::NotFound);
+    }
+    MyRes::Ok(())
+}
+
+#[derive(Serialize)]
+pub struct Decision {
+    id: Uuid,
+    rule_id: Option<Uuid>,
+    rule_kind: Option<FilterRuleKind>,
+    rule_pattern: Option<String>,
+    post_id: Option<Uuid>,
+    // Whether the post is still waiting for review.
+    held: Option<bool>,
+    userid: Option<Uuid>,
+    action: FilterAction,
+    matched: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+// Most recent first. `action=hold` with posts still held is the review
+// queue.
+#[get("/content_filter/decisions?<action>&<start>&<n>")]
+async fn decisions(
+    action: Option<String>,
+    start: Option<String>,
+    n: Option<String>,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<Decision>, FilterError> {
+    if !fail!(is_admin(&db, user.0).await) {
+        return MyRes::Err(FilterError::NotAdmin);
+    }
+    let page = bail!(
+        PageParams::parse(start.as_deref(), n.as_deref()),
+        FilterError::InvalidParam
+    );
+    let action = match action.as_deref().map(FilterAction::parse) {
+        None => None,
+        Some(Some(action)) => Some(action),
+        Some(None) => {
+            let err = InvalidParam::new("action", "must be flag, hold or reject");
+            return MyRes::Err(FilterError::InvalidParam(err));
+        }
+    };
+    let res = sqlx::query_as!(
+        Decision,
+        r#"
+        SELECT d.id,
+               d.rule_id,
+               r.kind as "rule_kind?: FilterRuleKind",
+               r.pattern as "rule_pattern?",
+               d.post_id,
+               p.held as "held?",
+               d.userid,
+               d.action as "action: _",
+               d.matched,
+               d.created_at
+        FROM content_filter_decisions d
+        LEFT JOIN content_filter_rules r ON r.id = d.rule_id
+        LEFT JOIN posts p ON p.id = d.post_id
+        WHERE ($1::FilterAction IS NULL OR d.action = $1)
+        ORDER BY d.created_at DESC, d.id
+        OFFSET $2 LIMIT $3
+        "#,
+        action: _,
+        page.start,
+        page.n,
+    )
+    .fetch_all(&*db)
+    .await;
+    let decisions = fail!(res);
+
+    MyRes::Ok(decisions)
+}
+
+#[derive(Deserialize)]
+pub struct Review {
+    approve: bool,
+}
+
+// Puts a held post up, or takes it down for good.
+#[post("/posts/<id>/review", data = "<data>")]
+async fn posts_review(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<Review>,
+) -> MyRes<(), FilterError> {
+    if !fail!(is_admin(&db, user.0).await) {
+        return MyRes::Err(FilterError::NotAdmin);
+    }
+    let id: Uuid = id.into_inner();
+    let res = if data.approve {
+        sqlx::query!("UPDATE posts SET held = FALSE WHERE id = $1 AND held", id)
+            .execute(&*db)
+            .await
+    } else {
+        sqlx::query!("DELETE FROM posts WHERE id = $1 AND held", id)
+            .execute(&*db)
+            .await
+    };
+    let res = fail!(res);
+    if res.rows_affected() == 0 {
+        return MyRes::Err(FilterError::NotFound);
+    }
+    MyRes::Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule(
+        kind: FilterRuleKind,
+        pattern: &str,
+        action: FilterAction,
+    ) -> (Uuid, FilterAction, Matcher) {
+        (
+            Uuid::nil(),
+            action,
+            Matcher::compile(kind, pattern).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_links() {
+        let found = links(
+            "Pay at upi://pay?pa=scam@ybl, see https://www.Example.com/o2. \
+             Or bit.ly/abc (www.gov.in) e.g. Dr.Rao",
+        );
+        let found = found
+            .iter()
+            .map(|l| (l.url.as_str(), l.domain.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                ("upi://pay?pa=scam@ybl", "upi"),
+                ("https://www.Example.com/o2", "example.com"),
+                ("bit.ly/abc", "bit.ly"),
+                ("www.gov.in", "gov.in"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile() {
+        assert!(Matcher::compile(FilterRuleKind::Keyword, " ").is_err());
+        assert!(matches!(
+            Matcher::compile(FilterRuleKind::Regex, "(unclosed"),
+            Err(FilterRuleInvalid::InvalidRegex(_))
+        ));
+        assert_eq!(
+            Matcher::compile(FilterRuleKind::DomainDeny, "bit.ly, http://x").err(),
+            Some(FilterRuleInvalid::InvalidDomain("http://x".to_owned()))
+        );
+        assert_eq!(
+            Matcher::compile(FilterRuleKind::MaxLinks, "two").err(),
+            Some(FilterRuleInvalid::InvalidMaxLinks)
+        );
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let rules = [
+            rule(
+                FilterRuleKind::Keyword,
+                "Advance  payment",
+                FilterAction::Hold,
+            ),
+            rule(FilterRuleKind::Regex, r"\bgpay\b", FilterAction::Flag),
+            rule(
+                FilterRuleKind::DomainDeny,
+                "upi, bit.ly",
+                FilterAction::Reject,
+            ),
+            rule(FilterRuleKind::DomainAllow, "gov.in", FilterAction::Flag),
+            rule(FilterRuleKind::MaxLinks, "1", FilterAction::Hold),
+        ];
+
+        let verdict = evaluate_rules(&rules, "Oxygen available, call 98765 43210");
+        assert_eq!(verdict.action(), None);
+
+        let verdict = evaluate_rules(&rules, "Cylinders, ADVANCE\npayment via GPay");
+        assert_eq!(verdict.action(), Some(FilterAction::Hold));
+        assert_eq!(verdict.matches.len(), 2);
+        assert_eq!(verdict.matches[0].matched, "advance payment");
+        assert_eq!(verdict.matches[1].matched, "GPay");
+
+        // Subdomains are covered.
+        let verdict = evaluate_rules(&rules, "Details at https://covid.kerala.gov.in/beds");
+        assert_eq!(verdict.action(), None);
+
+        let verdict = evaluate_rules(&rules, "Book at example.com/o2 and upi://pay?pa=x@ybl");
+        assert_eq!(verdict.action(), Some(FilterAction::Reject));
+        let matched = verdict
+            .matches
+            .iter()
+            .map(|m| (m.action, m.matched.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            matched,
+            [
+                (FilterAction::Reject, "upi://pay?pa=x@ybl"),
+                (FilterAction::Flag, "example.com/o2"),
+                (FilterAction::Hold, "2 links"),
+            ]
+        );
+    }
+}
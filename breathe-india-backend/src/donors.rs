@@ -0,0 +1,618 @@
+// A registry of blood and plasma donors. Searches only ever return a
+// donor's blood group and district. Anyone who wants to get in touch sends
+// a contact request, and the donor's name and email are only shown to them
+// once the donor accepts it.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::blood::{BloodGroup, DonationKind, EligibilityWindow};
+use crate::content_filter::{self, FilterAction};
+use crate::myres::{log_background_error, HasStatusCode, MyRes};
+use crate::notify::{self, Notification, Notifiers};
+use crate::params::{InvalidParam, PageParams};
+use crate::rate_limit::RateLimited;
+use crate::{bail, fail, LoggedInUser};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        donors_me,
+        donors_me_update,
+        donors_me_delete,
+        donors_search,
+        contact_requests_create,
+        contact_requests_sent,
+        contact_requests_received,
+        contact_requests_respond,
+    ]
+}
+
+#[derive(Serialize)]
+pub struct Donor {
+    id: Uuid,
+    blood_group: BloodGroup,
+    last_donated_on: Option<NaiveDate>,
+    covid_recovered_on: Option<NaiveDate>,
+    state: String,
+    district: String,
+    opted_in: bool,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct DonorUpdate {
+    blood_group: BloodGroup,
+    last_donated_on: Option<NaiveDate>,
+    covid_recovered_on: Option<NaiveDate>,
+    state: String,
+    district: String,
+    opted_in: bool,
+}
+
+#[derive(Serialize)]
+pub struct DonorSearchResult {
+    id: Uuid,
+    blood_group: BloodGroup,
+    state: String,
+    district: String,
+}
+
+#[derive(Clone, Copy, Serialize, sqlx::Type)]
+#[sqlx(type_name = "DonorContactState", rename_all = "lowercase")]
+pub enum ContactRequestState {
+    Pending,
+    Accepted,
+    Declined,
+}
+
+#[derive(Deserialize)]
+pub struct ContactRequestNew {
+    kind: DonationKind,
+    post_id: Option<Uuid>,
+    message: String,
+}
+
+#[derive(Deserialize)]
+pub struct ContactRequestResponse {
+    accept: bool,
+}
+
+// What the donor sees.
+#[derive(Serialize)]
+pub struct ContactRequestReceived {
+    id: Uuid,
+    kind: DonationKind,
+    post_id: Option<Uuid>,
+    message: String,
+    state: ContactRequestState,
+    created_at: DateTime<Utc>,
+    requester_name: String,
+}
+
+// What the requester sees. The donor's name and email are only filled in
+// once the request is accepted.
+#[derive(Serialize)]
+pub struct ContactRequestSent {
+    id: Uuid,
+    donor_id: Uuid,
+    kind: DonationKind,
+    post_id: Option<Uuid>,
+    message: String,
+    state: ContactRequestState,
+    created_at: DateTime<Utc>,
+    responded_at: Option<DateTime<Utc>>,
+    donor_name: Option<String>,
+    donor_email: Option<String>,
+}
+
+#[derive(Serialize)]
+enum DonorError {
+    NotFound,
+    InvalidParam(InvalidParam),
+    MissingLocation,
+    DateInFuture,
+    CannotContactSelf,
+    AlreadyRequested,
+    // The donor declined a request from them less than
+    // DECLINE_COOLDOWN_DAYS ago.
+    RecentlyDeclined,
+    TooLong,
+    // By the content filter.
+    Rejected,
+}
+
+impl HasStatusCode for DonorError {
+    fn get_status(&self) -> Status {
+        match self {
+            DonorError::NotFound => Status::NotFound,
+            DonorError::InvalidParam(_) => Status::BadRequest,
+            DonorError::MissingLocation => Status::BadRequest,
+            DonorError::DateInFuture => Status::BadRequest,
+            DonorError::CannotContactSelf => Status::BadRequest,
+            DonorError::AlreadyRequested => Status::Conflict,
+            DonorError::RecentlyDeclined => Status::Conflict,
+            DonorError::TooLong => Status::BadRequest,
+            DonorError::Rejected => Status::BadRequest,
+        }
+    }
+}
+
+#[get("/donors/me")]
+async fn donors_me(user: LoggedInUser, db: State<'_, PgPool>) -> MyRes<Donor, DonorError> {
+    let res = sqlx::query_as!(
+        Donor,
+        r#"
+        SELECT id,
+               blood_group as "blood_group: _",
+               last_donated_on,
+               covid_recovered_on,
+               state,
+               district,
+               opted_in,
+               updated_at
+        FROM donors
+        WHERE userid = $1
+        "#,
+        user.0
+    )
+    .fetch_optional(&*db)
+    .await;
+    let donor = fail!(res);
+    let donor = bail!(donor.ok_or(()), |_| DonorError::NotFound);
+
+    MyRes::Ok(donor)
+}
+
+#[put("/donors/me", data = "<data>")]
+async fn donors_me_update(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<DonorUpdate>,
+) -> MyRes<Donor, DonorError> {
+    let state = data.state.trim();
+    let district = data.district.trim();
+    if state.is_empty() || district.is_empty() {
+        return MyRes::Err(DonorError::MissingLocation);
+    }
+    let today = Utc::now().date().naive_utc();
+    let dates = [data.last_donated_on, data.covid_recovered_on];
+    if dates.iter().flatten().any(|d| *d > today) {
+        return MyRes::Err(DonorError::DateInFuture);
+    }
+
+    let res = sqlx::query_as!(
+        Donor,
+        r#"
+        INSERT INTO donors(
+            userid,
+            blood_group,
+            last_donated_on,
+            covid_recovered_on,
+            state,
+            district,
+            opted_in
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (userid) DO UPDATE SET
+            blood_group = EXCLUDED.blood_group,
+            last_donated_on = EXCLUDED.last_donated_on,
+            covid_recovered_on = EXCLUDED.covid_recovered_on,
+            state = EXCLUDED.state,
+            district = EXCLUDED.district,
+            opted_in = EXCLUDED.opted_in,
+            updated_at = NOW()
+        RETURNING
+               id,
+               blood_group as "blood_group: _",
+               last_donated_on,
+               covid_recovered_on,
+               state,
+               district,
+               opted_in,
+               updated_at
+        "#,
+        user.0,
+        data.blood_group: _,
+        data.last_donated_on,
+        data.covid_recovered_on,
+        state,
+        district,
+        data.opted_in
+    )
+    .fetch_one(&*db)
+    .await;
+    let donor = fail!(res);
+
+    MyRes::Ok(donor)
+}
+
+#[delete("/donors/me")]
+async fn donors_me_delete(user: LoggedInUser, db: State<'_, PgPool>) -> MyRes<(), DonorError> {
+    let res = sqlx::query!("DELETE FROM donors WHERE userid = $1", user.0)
+        .execute(&*db)
+        .await;
+
+    let res = fail!(res);
+    if res.rows_affected() == 0 {
+        return MyRes::Err(DonorError::NotFound);
+    }
+    MyRes::Ok(())
+}
+
+#[derive(FromForm)]
+struct DonorSearchParams {
+    kind: Option<String>,
+    // The recipient's. Without it, donors of every group are returned.
+    blood_group: Option<String>,
+    state: Option<String>,
+    district: Option<String>,
+    start: Option<String>,
+    n: Option<String>,
+}
+
+impl DonorSearchParams {
+    fn parse(&self) -> Result<(DonationKind, Option<BloodGroup>, PageParams), InvalidParam> {
+        let kind = match self
+            .kind
+            .as_deref()
+            .map(|k| k.trim().to_lowercase())
+            .as_deref()
+        {
+            None | Some("blood") => DonationKind::Blood,
+            Some("plasma") => DonationKind::Plasma,
+            Some(_) => return Err(InvalidParam::new("kind", "must be blood or plasma")),
+        };
+        let blood_group = match &self.blood_group {
+            None => None,
+            // An unescaped + in a query string comes through as a space.
+            Some(group) => match BloodGroup::parse(&group.trim_start().replace(' ', "+")) {
+                Some(group) => Some(group),
+                None => return Err(InvalidParam::new("blood_group", "must be like A+ or O-")),
+            },
+        };
+        let page = PageParams::parse(self.start.as_deref(), self.n.as_deref())?;
+        Ok((kind, blood_group, page))
+    }
+}
+
+fn like(s: &Option<String>) -> Option<String> {
+    s.as_ref()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| format!("%{}%", s))
+}
+
+// Only opted in donors who are compatible with the recipient and able to
+// donate today.
+#[get("/donors/search?<query..>")]
+async fn donors_search(
+    query: DonorSearchParams,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<DonorSearchResult>, DonorError> {
+    let (kind, blood_group, page) = bail!(query.parse(), DonorError::InvalidParam);
+    let groups = match blood_group {
+        Some(recipient) => BloodGroup::donors_for(recipient, kind),
+        None => BloodGroup::ALL.to_vec(),
+    };
+    let groups = groups
+        .iter()
+        .map(|g| g.as_str().to_owned())
+        .collect::<Vec<_>>();
+    let window = EligibilityWindow::new(kind, Utc::now().date().naive_utc());
+    let (recovered_from, recovered_to) = match window.recovered_between {
+        Some((from, to)) => (Some(from), Some(to)),
+        None => (None, None),
+    };
+
+    let res = sqlx::query_as!(
+        DonorSearchResult,
+        r#"
+        SELECT id,
+               blood_group as "blood_group: _",
+               state,
+               district
+        FROM donors
+        WHERE opted_in
+          AND userid <> $1
+          AND blood_group::text = ANY($2)
+          AND ($3::text IS NULL OR state ILIKE $3)
+          AND ($4::text IS NULL OR district ILIKE $4)
+          AND (last_donated_on IS NULL OR last_donated_on <= $5)
+          AND ($6::date IS NULL OR covid_recovered_on BETWEEN $6 AND $7)
+        ORDER BY updated_at DESC, id
+        OFFSET $8
+        LIMIT $9
+        "#,
+        user.0,
+        &groups[..],
+        like(&query.state),
+        like(&query.district),
+        window.last_donated_before,
+        recovered_from,
+        recovered_to,
+        page.start,
+        page.n
+    )
+    .fetch_all(&*db)
+    .await;
+    let donors = fail!(res);
+
+    MyRes::Ok(donors)
+}
+
+const MAX_MESSAGE_LEN: usize = 2000;
+const DECLINE_COOLDOWN_DAYS: i32 = 7;
+
+// Donors only hear from people they haven't just turned down, and the
+// message goes through the content filter like responses do.
+#[post("/donors/<id>/contact_requests", data = "<data>")]
+async fn contact_requests_create(
+    _limit: RateLimited,
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    notifiers: State<'_, Arc<Notifiers>>,
+    data: Json<ContactRequestNew>,
+) -> MyRes<ContactRequestSent, DonorError> {
+    let res = sqlx::query!(
+        r#"
+        SELECT donors.userid, users.email
+        FROM donors
+        JOIN users ON users.id = donors.userid
+        WHERE donors.id = $1 AND opted_in
+        "#,
+        id.into_inner()
+    )
+    .fetch_optional(&*db)
+    .await;
+    let donor = fail!(res);
+    let donor = bail!(donor.ok_or(()), |_| DonorError::NotFound);
+    if donor.userid == user.0 {
+        return MyRes::Err(DonorError::CannotContactSelf);
+    }
+    let message = data.message.trim();
+    if message.chars().count() > MAX_MESSAGE_LEN {
+        return MyRes::Err(DonorError::TooLong);
+    }
+
+    let res = sqlx::query!(
+        r#"
+        SELECT 1 as one FROM donor_contact_requests
+        WHERE donor_userid = $1 AND requester = $2 AND state = 'declined'
+          AND responded_at > NOW() - make_interval(days => $3)
+        LIMIT 1
+        "#,
+        donor.userid,
+        user.0,
+        DECLINE_COOLDOWN_DAYS
+    )
+    .fetch_optional(&*db)
+    .await;
+    if fail!(res).is_some() {
+        return MyRes::Err(DonorError::RecentlyDeclined);
+    }
+
+    let verdict = fail!(content_filter::evaluate(&db, message).await);
+    fail!(verdict.log(&db, user.0, None).await);
+    if verdict.action() >= Some(FilterAction::Hold) {
+        return MyRes::Err(DonorError::Rejected);
+    }
+
+    // One open request per requester and donor.
+    let res = sqlx::query_as!(
+        ContactRequestSent,
+        r#"
+        INSERT INTO donor_contact_requests(donor_userid, requester, kind, post_id, message)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (donor_userid, requester) WHERE state = 'pending' DO NOTHING
+        RETURNING id,
+                  $6::uuid as "donor_id!",
+                  kind as "kind: _",
+                  post_id,
+                  message,
+                  state as "state: _",
+                  created_at,
+                  responded_at,
+                  NULL::text as donor_name,
+                  NULL::text as donor_email
+        "#,
+        donor.userid,
+        user.0,
+        data.kind: _,
+        data.post_id,
+        message,
+        id.into_inner()
+    )
+    .fetch_optional(&*db)
+    .await;
+    let request = fail!(res);
+    let request = bail!(request.ok_or(()), |_| DonorError::AlreadyRequested);
+
+    let notification = Notification {
+        title: "Someone is asking for your help".to_owned(),
+        body: match data.kind {
+            DonationKind::Blood => {
+                "A patient needs blood. Open the app to share your contact details or decline."
+            }
+            DonationKind::Plasma => {
+                "A patient needs plasma. Open the app to share your contact details or decline."
+            }
+        }
+        .to_owned(),
+        url: notify::app_url("/donors/requests"),
+    };
+    spawn_notify_user(&notifiers, donor.userid, donor.email, notification);
+
+    MyRes::Ok(request)
+}
+
+#[get("/donors/contact_requests")]
+async fn contact_requests_sent(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<ContactRequestSent>, DonorError> {
+    let res = sqlx::query_as!(
+        ContactRequestSent,
+        r#"
+        SELECT r.id,
+               donors.id as donor_id,
+               r.kind as "kind: _",
+               r.post_id,
+               r.message,
+               r.state as "state: _",
+               r.created_at,
+               r.responded_at,
+               CASE WHEN r.state = 'accepted' THEN users.name END as donor_name,
+               CASE WHEN r.state = 'accepted' THEN users.email END as donor_email
+        FROM donor_contact_requests r
+        JOIN donors ON donors.userid = r.donor_userid
+        JOIN users ON users.id = r.donor_userid
+        WHERE r.requester = $1
+        ORDER BY r.created_at DESC
+        "#,
+        user.0
+    )
+    .fetch_all(&*db)
+    .await;
+    let requests = fail!(res);
+
+    MyRes::Ok(requests)
+}
+
+#[get("/donors/me/contact_requests")]
+async fn contact_requests_received(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<ContactRequestReceived>, DonorError> {
+    let res = sqlx::query_as!(
+        ContactRequestReceived,
+        r#"
+        SELECT r.id,
+               r.kind as "kind: _",
+               r.post_id,
+               r.message,
+               r.state as "state: _",
+               r.created_at,
+               users.name as requester_name
+        FROM donor_contact_requests r
+        JOIN users ON users.id = r.requester
+        WHERE r.donor_userid = $1
+        ORDER BY r.created_at DESC
+        "#,
+        user.0
+    )
+    .fetch_all(&*db)
+    .await;
+    let requests = fail!(res);
+
+    MyRes::Ok(requests)
+}
+
+// Accepting is the donor's consent to share their name and email with the
+// requester. Either way the requester gets to know.
+#[post("/donors/me/contact_requests/<id>", data = "<data>")]
+async fn contact_requests_respond(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    notifiers: State<'_, Arc<Notifiers>>,
+    data: Json<ContactRequestResponse>,
+) -> MyRes<(), DonorError> {
+    let state = if data.accept {
+        ContactRequestState::Accepted
+    } else {
+        ContactRequestState::Declined
+    };
+    let res = sqlx::query!(
+        r#"
+        UPDATE donor_contact_requests r
+        SET state = $3, responded_at = NOW()
+        FROM users
+        WHERE r.id = $1
+          AND r.donor_userid = $2
+          AND r.state = 'pending'
+          AND users.id = r.requester
+        RETURNING r.requester, users.email
+        "#,
+        id.into_inner(),
+        user.0,
+        state: _
+    )
+    .fetch_optional(&*db)
+    .await;
+    let requester = fail!(res);
+    let requester = bail!(requester.ok_or(()), |_| DonorError::NotFound);
+
+    let notification = Notification {
+        title: if data.accept {
+            "A donor shared their contact details".to_owned()
+        } else {
+            "A donor can't help this time".to_owned()
+        },
+        body: if data.accept {
+            "Open the app to see how to reach them.".to_owned()
+        } else {
+            "They declined your request. Try searching for other donors.".to_owned()
+        },
+        url: notify::app_url("/donors/contact_requests"),
+    };
+    spawn_notify_user(
+        &notifiers,
+        requester.requester,
+        requester.email,
+        notification,
+    );
+
+    MyRes::Ok(())
+}
+
+fn spawn_notify_user(
+    notifiers: &Arc<Notifiers>,
+    userid: Uuid,
+    email: String,
+    notification: Notification,
+) {
+    let notifiers = notifiers.clone();
+    tokio::spawn(async move {
+        let res = notify::notify_user(&notifiers, userid, email, &notification).await;
+        if let Err(e) = res {
+            log_background_error("notify_donors", &e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn params(kind: Option<&str>, blood_group: Option<&str>) -> DonorSearchParams {
+        DonorSearchParams {
+            kind: kind.map(|k| k.to_owned()),
+            blood_group: blood_group.map(|g| g.to_owned()),
+            state: None,
+            district: None,
+            start: None,
+            n: None,
+        }
+    }
+
+    #[test]
+    fn test_search_params() {
+        let (kind, group, _) = params(None, None).parse().unwrap();
+        assert_eq!((kind, group), (DonationKind::Blood, None));
+        let (kind, group, _) = params(Some("Plasma"), Some("AB ")).parse().unwrap();
+        assert_eq!(
+            (kind, group),
+            (DonationKind::Plasma, Some(BloodGroup::AbPos))
+        );
+        let (_, group, _) = params(None, Some("o-")).parse().unwrap();
+        assert_eq!(group, Some(BloodGroup::ONeg));
+        assert!(params(Some("organ"), None).parse().is_err());
+        assert!(params(None, Some("Z+")).parse().is_err());
+    }
+}
@@ -0,0 +1,392 @@
+// Spots posts that are the same lead posted again, often by another
+// volunteer, and lets admins merge them into one.
+
+use chrono::{DateTime, Utc};
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::matching::{self, Location};
+use crate::myres::{HasStatusCode, MyRes};
+use crate::{fail, is_admin, pii, pledges, LoggedInUser, PostType};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![posts_merge]
+}
+
+const WEIGHT_ITEM: f64 = 0.3;
+const WEIGHT_LOCATION: f64 = 0.3;
+const WEIGHT_MESSAGE: f64 = 0.4;
+
+// Posts scoring below this are not reported as duplicates.
+const MIN_SCORE: f64 = 0.75;
+
+// Only posts touched within this window are compared.
+const CANDIDATE_MAX_AGE_DAYS: i64 = 7;
+
+// At most this many duplicates are reported.
+const MAX_DUPLICATES: usize = 5;
+
+// Messages are compared by their runs of this many words.
+const SHINGLE_WORDS: usize = 3;
+
+pub struct Fingerprint<'a> {
+    item: &'a str,
+    location: Location<'a>,
+    phones: HashSet<String>,
+    shingles: HashSet<String>,
+}
+
+impl<'a> Fingerprint<'a> {
+    // `phones` are the contact numbers. Numbers in the message count too.
+    pub fn new(item: &'a str, location: Location<'a>, phones: &[String], message: &str) -> Self {
+        let mut all_phones = pii::phones(message);
+        all_phones.extend(phones.iter().cloned());
+        Fingerprint {
+            item,
+            location,
+            phones: all_phones.into_iter().collect(),
+            shingles: shingles(message),
+        }
+    }
+}
+
+fn shingles(message: &str) -> HashSet<String> {
+    let words = message
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<_>>();
+    if words.len() < SHINGLE_WORDS {
+        return words.into_iter().collect();
+    }
+    words.windows(SHINGLE_WORDS).map(|w| w.join(" ")).collect()
+}
+
+fn jaccard(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / a.union(b).count() as f64
+}
+
+// Returns None if the posts are not duplicates. Posts with the same item
+// and a phone number in common always are, wherever they say they are.
+pub fn score(a: &Fingerprint, b: &Fingerprint) -> Option<f64> {
+    let item = matching::item_score(a.item, b.item);
+    if item == 0.0 {
+        return None;
+    }
+    if !a.phones.is_disjoint(&b.phones) {
+        return Some(1.0);
+    }
+    let location = matching::location_score(&a.location, &b.location);
+    let score = WEIGHT_ITEM * item
+        + WEIGHT_LOCATION * location
+        + WEIGHT_MESSAGE * jaccard(&a.shingles, &b.shingles);
+    if score < MIN_SCORE {
+        None
+    } else {
+        Some(score)
+    }
+}
+
+#[derive(Serialize)]
+pub struct ProbableDuplicate {
+    id: Uuid,
+    item: String,
+    state: String,
+    district: String,
+    city: String,
+    spot: String,
+    updated_at: DateTime<Utc>,
+    score: f64,
+}
+
+// Recent open posts of the same type that look like `post`, best first.
+// `exclude` is the post itself when it is being updated.
+pub async fn find(
+    db: &PgPool,
+    post_type: PostType,
+    post: &Fingerprint<'_>,
+    exclude: Option<Uuid>,
+) -> sqlx::Result<Vec<ProbableDuplicate>> {
+    let since = Utc::now() - chrono::Duration::days(CANDIDATE_MAX_AGE_DAYS);
+    let candidates = sqlx::query!(
+        r#"
+        SELECT id, item, state, district, city, spot, message, contact_phones, updated_at
+        FROM posts
+        WHERE post_type = $1
+          AND status IN ('open', 'partially_fulfilled')
+          AND NOT held
+          AND state ILIKE $2
+          AND updated_at > $3
+          AND id IS DISTINCT FROM $4
+        "#,
+        post_type: _,
+        post.location.state.trim(),
+        since,
+        exclude,
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut duplicates = candidates
+        .into_iter()
+        .filter_map(|c| {
+            let other = Fingerprint::new(
+                &c.item,
+                Location {
+                    state: &c.state,
+                    district: &c.district,
+                    city: &c.city,
+                    spot: &c.spot,
+                },
+                &c.contact_phones,
+                &c.message,
+            );
+            let score = score(post, &other)?;
+            Some(ProbableDuplicate {
+                id: c.id,
+                item: c.item.clone(),
+                state: c.state.clone(),
+                district: c.district.clone(),
+                city: c.city.clone(),
+                spot: c.spot.clone(),
+                updated_at: c.updated_at,
+                score,
+            })
+        })
+        .collect::<Vec<_>>();
+    duplicates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+    duplicates.truncate(MAX_DUPLICATES);
+    Ok(duplicates)
+}
+
+#[derive(Deserialize)]
+pub struct Merge {
+    duplicates: Vec<Uuid>,
+}
+
+#[derive(Serialize)]
+pub struct Merged {
+    merged: Vec<Uuid>,
+}
+
+#[derive(Serialize)]
+pub enum MergeError {
+    NotAdmin,
+    NotFound,
+    NothingToMerge,
+    DifferentTypes,
+}
+
+impl HasStatusCode for MergeError {
+    fn get_status(&self) -> Status {
+        match self {
+            MergeError::NotAdmin => Status::Forbidden,
+            MergeError::NotFound => Status::NotFound,
+            MergeError::NothingToMerge | MergeError::DifferentTypes => Status::BadRequest,
+        }
+    }
+}
+
+// Folds the duplicates into the canonical post `id` and deletes them.
+// Confirmations move over, keeping each user's latest one. So do responses
+// and pledges, though someone pledging to more than one of the posts only
+// keeps one pledge waiting, and the rest are cancelled.
+#[post("/posts/<id>/merge", data = "<data>")]
+async fn posts_merge(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<Merge>,
+) -> MyRes<Merged, MergeError> {
+    let id: Uuid = id.into_inner();
+    if !fail!(is_admin(&db, user.0).await) {
+        return MyRes::Err(MergeError::NotAdmin);
+    }
+    let mut duplicates = data.duplicates.clone();
+    duplicates.sort();
+    duplicates.dedup();
+    duplicates.retain(|d| *d != id);
+    if duplicates.is_empty() {
+        return MyRes::Err(MergeError::NothingToMerge);
+    }
+
+    let mut tx = fail!(db.begin().await);
+    let res = sqlx::query!(
+        r#"SELECT id, post_type as "post_type: PostType"
+        FROM posts WHERE id = $1 OR id = ANY($2)
+        FOR UPDATE"#,
+        id,
+        &duplicates[..],
+    )
+    .fetch_all(&mut tx)
+    .await;
+    let posts = fail!(res);
+    if posts.len() != duplicates.len() + 1 {
+        return MyRes::Err(MergeError::NotFound);
+    }
+    if posts.iter().any(|p| p.post_type != posts[0].post_type) {
+        return MyRes::Err(MergeError::DifferentTypes);
+    }
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO post_confirmations(post_id, userid, available, created_at)
+        SELECT DISTINCT ON (userid) $1, userid, available, created_at
+        FROM post_confirmations
+        WHERE post_id = ANY($2)
+        ORDER BY userid, created_at DESC
+        ON CONFLICT (post_id, userid) DO UPDATE SET
+            available = EXCLUDED.available,
+            created_at = EXCLUDED.created_at
+        WHERE post_confirmations.created_at < EXCLUDED.created_at
+        "#,
+        id,
+        &duplicates[..],
+    )
+    .execute(&mut tx)
+    .await;
+    fail!(res);
+
+    let res = sqlx::query!(
+        "UPDATE post_responses SET post_id = $1 WHERE post_id = ANY($2)",
+        id,
+        &duplicates[..],
+    )
+    .execute(&mut tx)
+    .await;
+    fail!(res);
+
+    // Keeps the pledge on the canonical post, or else the latest one. Ones
+    // by the canonical post's author would be pledges to their own need.
+    let res = sqlx::query!(
+        r#"
+        UPDATE pledges SET state = 'cancelled', updated_at = NOW()
+        WHERE needs_id = ANY($2) AND state = 'pledged' AND (
+            userid = (SELECT userid FROM posts WHERE id = $1)
+            OR id NOT IN (
+                SELECT DISTINCT ON (userid) id FROM pledges
+                WHERE (needs_id = $1 OR needs_id = ANY($2)) AND state = 'pledged'
+                ORDER BY userid, needs_id = $1 DESC, created_at DESC
+            )
+        )
+        "#,
+        id,
+        &duplicates[..],
+    )
+    .execute(&mut tx)
+    .await;
+    fail!(res);
+    let res = sqlx::query!(
+        "UPDATE pledges SET needs_id = $1 WHERE needs_id = ANY($2)",
+        id,
+        &duplicates[..],
+    )
+    .execute(&mut tx)
+    .await;
+    fail!(res);
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO post_merges(duplicate_id, duplicate_userid, canonical_id, merged_by)
+        SELECT id, userid, $1, $3 FROM posts WHERE id = ANY($2)
+        "#,
+        id,
+        &duplicates[..],
+        user.0,
+    )
+    .execute(&mut tx)
+    .await;
+    fail!(res);
+
+    let res = sqlx::query!("DELETE FROM posts WHERE id = ANY($1)", &duplicates[..])
+        .execute(&mut tx)
+        .await;
+    fail!(res);
+    fail!(pledges::refresh(&mut tx, id).await);
+    fail!(tx.commit().await);
+
+    MyRes::Ok(Merged { merged: duplicates })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fingerprint<'a>(
+        item: &'a str,
+        city: &'a str,
+        phones: &[String],
+        message: &str,
+    ) -> Fingerprint<'a> {
+        Fingerprint::new(
+            item,
+            Location {
+                state: "Kerala",
+                district: "Ernakulam",
+                city,
+                spot: "",
+            },
+            phones,
+            message,
+        )
+    }
+
+    #[test]
+    fn test_shingles() {
+        assert_eq!(
+            shingles("O2, available!"),
+            ["o2", "available"].iter().map(|s| s.to_string()).collect()
+        );
+        assert_eq!(shingles("Oxygen refill at Kochi").len(), 2);
+    }
+
+    #[test]
+    fn test_score() {
+        let post = fingerprint(
+            "Oxygen",
+            "Kochi",
+            &[],
+            "Oxygen cylinders available at the Kakkanad refill plant, call before coming",
+        );
+
+        // Reworded a little.
+        let other = fingerprint(
+            "O2 cylinder",
+            "kochi",
+            &[],
+            "Oxygen cylinders available at the Kakkanad refill plant, please call before coming",
+        );
+        assert!(score(&post, &other).is_some());
+
+        // Same place and item, but a different lead.
+        let other = fingerprint(
+            "oxygen",
+            "Kochi",
+            &[],
+            "Two cylinders with us, free for anyone who needs them",
+        );
+        assert_eq!(score(&post, &other), None);
+
+        // Same number, written differently, even with the place worded otherwise.
+        let phones = ["9876543210".to_owned()];
+        let other = fingerprint("oxygen", "Cochin", &phones, "Refills available");
+        let post = fingerprint("oxygen", "Kochi", &[], "Call +91 98765 43210 for refills");
+        assert_eq!(score(&post, &other), Some(1.0));
+
+        // Never across items.
+        let other = fingerprint(
+            "plasma",
+            "Kochi",
+            &phones,
+            "Call +91 98765 43210 for refills",
+        );
+        assert_eq!(score(&post, &other), None);
+    }
+}
@@ -0,0 +1,655 @@
+// Hospitals and how many beds of each type they have free, kept up to date
+// by their own staff. Every change to the counts is also recorded in
+// `hospital_bed_history`, so availability can be charted over time.
+
+use chrono::{DateTime, Duration, Utc};
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::myres::{HasStatusCode, MyRes};
+use crate::params::{InvalidParam, PageParams};
+use crate::{bail, fail, LoggedInUser};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        hospitals_search,
+        hospitals_single,
+        hospitals_create,
+        hospitals_update,
+        hospitals_staff_add,
+        hospitals_staff_remove,
+        hospitals_beds_update,
+        hospitals_beds_history,
+    ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum BedType {
+    General,
+    Oxygen,
+    Icu,
+    Ventilator,
+}
+
+impl BedType {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "general" => Some(BedType::General),
+            "oxygen" | "o2" => Some(BedType::Oxygen),
+            "icu" => Some(BedType::Icu),
+            "ventilator" => Some(BedType::Ventilator),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Hospital {
+    id: Uuid,
+    name: String,
+    address: String,
+    state: String,
+    district: String,
+    city: String,
+    phone: String,
+    beds: Vec<Beds>,
+}
+
+#[derive(Serialize)]
+pub struct Beds {
+    bed_type: BedType,
+    total: i32,
+    available: i32,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct HospitalNew {
+    name: String,
+    address: String,
+    state: String,
+    district: String,
+    city: String,
+    phone: String,
+}
+
+#[derive(Deserialize)]
+pub struct BedCount {
+    bed_type: BedType,
+    total: i32,
+    available: i32,
+}
+
+#[derive(Deserialize)]
+pub struct StaffNew {
+    userid: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct BedHistoryEntry {
+    bed_type: BedType,
+    total: i32,
+    available: i32,
+    recorded_at: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+enum HospitalError {
+    NotFound,
+    NotVerified,
+    NotStaff,
+    // Only verified users can be added as staff.
+    StaffNotVerified,
+    // Every hospital keeps at least one staff member to update it.
+    LastStaff,
+    MissingFields,
+    InvalidParam(InvalidParam),
+    InvalidBedCount,
+    DuplicateBedType,
+}
+
+impl HasStatusCode for HospitalError {
+    fn get_status(&self) -> Status {
+        match self {
+            HospitalError::NotFound => Status::NotFound,
+            HospitalError::NotVerified => Status::Forbidden,
+            HospitalError::NotStaff => Status::Forbidden,
+            HospitalError::StaffNotVerified => Status::BadRequest,
+            HospitalError::LastStaff => Status::BadRequest,
+            HospitalError::MissingFields => Status::BadRequest,
+            HospitalError::InvalidParam(_) => Status::BadRequest,
+            HospitalError::InvalidBedCount => Status::BadRequest,
+            HospitalError::DuplicateBedType => Status::BadRequest,
+        }
+    }
+}
+
+impl HospitalNew {
+    fn validate(&self) -> Result<(), HospitalError> {
+        if self.name.trim().is_empty()
+            || self.state.trim().is_empty()
+            || self.district.trim().is_empty()
+        {
+            return Err(HospitalError::MissingFields);
+        }
+        Ok(())
+    }
+}
+
+fn validate_bed_counts(counts: &[BedCount]) -> Result<(), HospitalError> {
+    for (i, count) in counts.iter().enumerate() {
+        if count.total < 0 || count.available < 0 || count.available > count.total {
+            return Err(HospitalError::InvalidBedCount);
+        }
+        if counts[..i].iter().any(|c| c.bed_type == count.bed_type) {
+            return Err(HospitalError::DuplicateBedType);
+        }
+    }
+    Ok(())
+}
+
+fn like(s: &Option<String>) -> Option<String> {
+    s.as_ref()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| format!("%{}%", s))
+}
+
+struct HospitalRow {
+    id: Uuid,
+    name: String,
+    address: String,
+    state: String,
+    district: String,
+    city: String,
+    phone: String,
+}
+
+async fn with_beds(db: &PgPool, rows: Vec<HospitalRow>) -> sqlx::Result<Vec<Hospital>> {
+    let ids = rows.iter().map(|h| h.id).collect::<Vec<_>>();
+    let beds = sqlx::query!(
+        r#"
+        SELECT hospital_id,
+               bed_type as "bed_type: BedType",
+               total,
+               available,
+               updated_at
+        FROM hospital_beds
+        WHERE hospital_id = ANY($1)
+        ORDER BY bed_type
+        "#,
+        &ids[..]
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut by_hospital: HashMap<Uuid, Vec<Beds>> = HashMap::new();
+    for b in beds {
+        by_hospital.entry(b.hospital_id).or_default().push(Beds {
+            bed_type: b.bed_type,
+            total: b.total,
+            available: b.available,
+            updated_at: b.updated_at,
+        });
+    }
+    Ok(rows
+        .into_iter()
+        .map(|h| Hospital {
+            beds: by_hospital.remove(&h.id).unwrap_or_default(),
+            id: h.id,
+            name: h.name,
+            address: h.address,
+            state: h.state,
+            district: h.district,
+            city: h.city,
+            phone: h.phone,
+        })
+        .collect())
+}
+
+async fn is_staff(db: &PgPool, hospital_id: Uuid, userid: Uuid) -> sqlx::Result<bool> {
+    let row = sqlx::query!(
+        "SELECT 1 as one FROM hospital_staff WHERE hospital_id = $1 AND userid = $2",
+        hospital_id,
+        userid
+    )
+    .fetch_optional(db)
+    .await?;
+    Ok(row.is_some())
+}
+
+#[derive(FromForm)]
+struct HospitalSearchParams {
+    state: Option<String>,
+    district: Option<String>,
+    bed_type: Option<String>,
+    start: Option<String>,
+    n: Option<String>,
+}
+
+impl HospitalSearchParams {
+    fn parse(&self) -> Result<(Option<BedType>, PageParams), InvalidParam> {
+        let bed_type = match &self.bed_type {
+            None => None,
+            Some(t) => match BedType::parse(t) {
+                Some(t) => Some(t),
+                None => {
+                    return Err(InvalidParam::new(
+                        "bed_type",
+                        "must be general, oxygen, icu or ventilator",
+                    ))
+                }
+            },
+        };
+        let page = PageParams::parse(self.start.as_deref(), self.n.as_deref())?;
+        Ok((bed_type, page))
+    }
+}
+
+// With a bed type, only hospitals that have one free are returned, the
+// ones with the most free first.
+#[get("/hospitals/search?<query..>")]
+async fn hospitals_search(
+    query: HospitalSearchParams,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<Hospital>, HospitalError> {
+    let (bed_type, page) = bail!(query.parse(), HospitalError::InvalidParam);
+    let res = sqlx::query_as!(
+        HospitalRow,
+        r#"
+        SELECT h.id, h.name, h.address, h.state, h.district, h.city, h.phone
+        FROM hospitals h
+        LEFT JOIN hospital_beds b ON b.hospital_id = h.id AND b.bed_type = $3
+        WHERE ($1::text IS NULL OR h.state ILIKE $1)
+          AND ($2::text IS NULL OR h.district ILIKE $2)
+          AND ($3::BedType IS NULL OR b.available > 0)
+        ORDER BY b.available DESC NULLS LAST, h.name
+        OFFSET $4
+        LIMIT $5
+        "#,
+        like(&query.state),
+        like(&query.district),
+        bed_type: _,
+        page.start,
+        page.n
+    )
+    .fetch_all(&*db)
+    .await;
+    let rows = fail!(res);
+    let hospitals = fail!(with_beds(&db, rows).await);
+
+    MyRes::Ok(hospitals)
+}
+
+async fn fetch_hospital(db: &PgPool, id: Uuid) -> sqlx::Result<Option<Hospital>> {
+    let row = sqlx::query_as!(
+        HospitalRow,
+        r#"
+        SELECT id, name, address, state, district, city, phone
+        FROM hospitals
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(db)
+    .await?;
+    match row {
+        Some(row) => Ok(with_beds(db, vec![row]).await?.pop()),
+        None => Ok(None),
+    }
+}
+
+#[get("/hospitals/<id>")]
+async fn hospitals_single(
+    id: rocket_contrib::uuid::Uuid,
+    db: State<'_, PgPool>,
+) -> MyRes<Hospital, HospitalError> {
+    let res = fetch_hospital(&db, id.into_inner()).await;
+    let hospital = fail!(res);
+    let hospital = bail!(hospital.ok_or(()), |_| HospitalError::NotFound);
+
+    MyRes::Ok(hospital)
+}
+
+// Only verified users can list a hospital. They become its first staff
+// member.
+#[post("/hospitals", data = "<data>")]
+async fn hospitals_create(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<HospitalNew>,
+) -> MyRes<Hospital, HospitalError> {
+    let res = sqlx::query!("SELECT verified FROM users WHERE id = $1", user.0)
+        .fetch_optional(&*db)
+        .await;
+    let verified = fail!(res).map(|u| u.verified).unwrap_or(false);
+    if !verified {
+        return MyRes::Err(HospitalError::NotVerified);
+    }
+    bail!(data.validate(), |e| e);
+
+    let mut tx = fail!(db.begin().await);
+    let res = sqlx::query_as!(
+        HospitalRow,
+        r#"
+        INSERT INTO hospitals(name, address, state, district, city, phone, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, name, address, state, district, city, phone
+        "#,
+        data.name.trim(),
+        data.address.trim(),
+        data.state.trim(),
+        data.district.trim(),
+        data.city.trim(),
+        data.phone.trim(),
+        user.0
+    )
+    .fetch_one(&mut tx)
+    .await;
+    let row = fail!(res);
+    let res = sqlx::query!(
+        "INSERT INTO hospital_staff(hospital_id, userid) VALUES ($1, $2)",
+        row.id,
+        user.0
+    )
+    .execute(&mut tx)
+    .await;
+    fail!(res);
+    fail!(tx.commit().await);
+
+    let hospital = Hospital {
+        id: row.id,
+        name: row.name,
+        address: row.address,
+        state: row.state,
+        district: row.district,
+        city: row.city,
+        phone: row.phone,
+        beds: vec![],
+    };
+    MyRes::Ok(hospital)
+}
+
+#[patch("/hospitals/<id>", data = "<data>")]
+async fn hospitals_update(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<HospitalNew>,
+) -> MyRes<Hospital, HospitalError> {
+    let id = id.into_inner();
+    if !fail!(is_staff(&db, id, user.0).await) {
+        return MyRes::Err(HospitalError::NotStaff);
+    }
+    bail!(data.validate(), |e| e);
+
+    let res = sqlx::query!(
+        r#"
+        UPDATE hospitals SET
+            name = $2,
+            address = $3,
+            state = $4,
+            district = $5,
+            city = $6,
+            phone = $7
+        WHERE id = $1
+        "#,
+        id,
+        data.name.trim(),
+        data.address.trim(),
+        data.state.trim(),
+        data.district.trim(),
+        data.city.trim(),
+        data.phone.trim()
+    )
+    .execute(&*db)
+    .await;
+    fail!(res);
+
+    let res = fetch_hospital(&db, id).await;
+    let hospital = fail!(res);
+    let hospital = bail!(hospital.ok_or(()), |_| HospitalError::NotFound);
+    MyRes::Ok(hospital)
+}
+
+// Staff can add others, as long as they are verified like whoever listed
+// the hospital had to be.
+#[post("/hospitals/<id>/staff", data = "<data>")]
+async fn hospitals_staff_add(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<StaffNew>,
+) -> MyRes<(), HospitalError> {
+    let id = id.into_inner();
+    if !fail!(is_staff(&db, id, user.0).await) {
+        return MyRes::Err(HospitalError::NotStaff);
+    }
+    let res = sqlx::query!("SELECT verified FROM users WHERE id = $1", data.userid)
+        .fetch_optional(&*db)
+        .await;
+    let verified = fail!(res).map(|u| u.verified).unwrap_or(false);
+    if !verified {
+        return MyRes::Err(HospitalError::StaffNotVerified);
+    }
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO hospital_staff(hospital_id, userid)
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        id,
+        data.userid
+    )
+    .execute(&*db)
+    .await;
+    fail!(res);
+
+    MyRes::Ok(())
+}
+
+// Staff can remove anyone, themselves included, and admins can remove
+// staff that shouldn't be there.
+#[delete("/hospitals/<id>/staff/<member>")]
+async fn hospitals_staff_remove(
+    id: rocket_contrib::uuid::Uuid,
+    member: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<(), HospitalError> {
+    let id: Uuid = id.into_inner();
+    let member: Uuid = member.into_inner();
+    if !fail!(is_staff(&db, id, user.0).await) && !fail!(crate::is_admin(&db, user.0).await) {
+        return MyRes::Err(HospitalError::NotStaff);
+    }
+
+    // Locks the hospital, so two staff members can't both leave at once.
+    let mut tx = fail!(db.begin().await);
+    let res = sqlx::query!("SELECT id FROM hospitals WHERE id = $1 FOR UPDATE", id)
+        .fetch_optional(&mut tx)
+        .await;
+    fail!(res);
+    let res = sqlx::query!(
+        "DELETE FROM hospital_staff WHERE hospital_id = $1 AND userid = $2",
+        id,
+        member
+    )
+    .execute(&mut tx)
+    .await;
+    if fail!(res).rows_affected() == 0 {
+        return MyRes::Err(HospitalError::NotFound);
+    }
+    let res = sqlx::query!(
+        r#"SELECT COUNT(*) as "staff!" FROM hospital_staff WHERE hospital_id = $1"#,
+        id
+    )
+    .fetch_one(&mut tx)
+    .await;
+    // Dropping `tx` rolls the removal back.
+    if fail!(res).staff == 0 {
+        return MyRes::Err(HospitalError::LastStaff);
+    }
+    fail!(tx.commit().await);
+
+    MyRes::Ok(())
+}
+
+// Replaces the counts for every bed type given. Types left out are not
+// touched.
+#[put("/hospitals/<id>/beds", data = "<data>")]
+async fn hospitals_beds_update(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<Vec<BedCount>>,
+) -> MyRes<Hospital, HospitalError> {
+    let id = id.into_inner();
+    if !fail!(is_staff(&db, id, user.0).await) {
+        return MyRes::Err(HospitalError::NotStaff);
+    }
+    bail!(validate_bed_counts(&data), |e| e);
+
+    let mut tx = fail!(db.begin().await);
+    for count in data.iter() {
+        let res = sqlx::query!(
+            r#"
+            INSERT INTO hospital_beds(hospital_id, bed_type, total, available, updated_by)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (hospital_id, bed_type) DO UPDATE SET
+                total = EXCLUDED.total,
+                available = EXCLUDED.available,
+                updated_by = EXCLUDED.updated_by,
+                updated_at = NOW()
+            "#,
+            id,
+            count.bed_type: _,
+            count.total,
+            count.available,
+            user.0
+        )
+        .execute(&mut tx)
+        .await;
+        fail!(res);
+    }
+    fail!(tx.commit().await);
+
+    let res = fetch_hospital(&db, id).await;
+    let hospital = fail!(res);
+    let hospital = bail!(hospital.ok_or(()), |_| HospitalError::NotFound);
+    MyRes::Ok(hospital)
+}
+
+const DEFAULT_HISTORY_DAYS: i64 = 7;
+const MAX_HISTORY_DAYS: i64 = 90;
+
+#[get("/hospitals/<id>/beds/history?<bed_type>&<days>")]
+async fn hospitals_beds_history(
+    id: rocket_contrib::uuid::Uuid,
+    bed_type: Option<String>,
+    days: Option<String>,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<BedHistoryEntry>, HospitalError> {
+    let bed_type = match bed_type.as_deref().map(BedType::parse) {
+        None => None,
+        Some(Some(t)) => Some(t),
+        Some(None) => {
+            let err = InvalidParam::new("bed_type", "must be general, oxygen, icu or ventilator");
+            return MyRes::Err(HospitalError::InvalidParam(err));
+        }
+    };
+    let days = match days.as_deref().map(|d| d.trim().parse::<i64>()) {
+        None => DEFAULT_HISTORY_DAYS,
+        Some(Ok(d)) if (1..=MAX_HISTORY_DAYS).contains(&d) => d,
+        Some(_) => {
+            let err = InvalidParam::new("days", "must be between 1 and 90");
+            return MyRes::Err(HospitalError::InvalidParam(err));
+        }
+    };
+
+    let res = sqlx::query_as!(
+        BedHistoryEntry,
+        r#"
+        SELECT bed_type as "bed_type: _",
+               total,
+               available,
+               recorded_at
+        FROM hospital_bed_history
+        WHERE hospital_id = $1
+          AND ($2::BedType IS NULL OR bed_type = $2)
+          AND recorded_at > $3
+        ORDER BY recorded_at
+        "#,
+        id.into_inner(),
+        bed_type: _,
+        Utc::now() - Duration::days(days)
+    )
+    .fetch_all(&*db)
+    .await;
+    let history = fail!(res);
+
+    MyRes::Ok(history)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn count(bed_type: BedType, total: i32, available: i32) -> BedCount {
+        BedCount {
+            bed_type,
+            total,
+            available,
+        }
+    }
+
+    #[test]
+    fn test_validate_bed_counts() {
+        let ok = [count(BedType::General, 100, 12), count(BedType::Icu, 10, 0)];
+        assert_eq!(validate_bed_counts(&ok), Ok(()));
+        assert_eq!(
+            validate_bed_counts(&[count(BedType::Icu, 10, 11)]),
+            Err(HospitalError::InvalidBedCount)
+        );
+        assert_eq!(
+            validate_bed_counts(&[count(BedType::Icu, -1, 0)]),
+            Err(HospitalError::InvalidBedCount)
+        );
+        assert_eq!(
+            validate_bed_counts(&[count(BedType::Oxygen, 5, 1), count(BedType::Oxygen, 5, 2)]),
+            Err(HospitalError::DuplicateBedType)
+        );
+    }
+
+    #[test]
+    fn test_validate_hospital() {
+        let hospital = |name: &str, district: &str| HospitalNew {
+            name: name.to_owned(),
+            address: String::new(),
+            state: "Kerala".to_owned(),
+            district: district.to_owned(),
+            city: String::new(),
+            phone: String::new(),
+        };
+        assert_eq!(hospital("General Hospital", "Ernakulam").validate(), Ok(()));
+        assert_eq!(
+            hospital(" ", "Ernakulam").validate(),
+            Err(HospitalError::MissingFields)
+        );
+        assert_eq!(
+            hospital("General Hospital", "").validate(),
+            Err(HospitalError::MissingFields)
+        );
+    }
+
+    #[test]
+    fn test_parse_bed_type() {
+        assert_eq!(BedType::parse("O2"), Some(BedType::Oxygen));
+        assert_eq!(BedType::parse(" ICU"), Some(BedType::Icu));
+        assert_eq!(BedType::parse("isolation"), None);
+    }
+}
@@ -23,17 +23,63 @@ use std::time::Instant;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+mod beneficiary;
+mod blood;
+mod challenge;
+mod contact;
+mod content_filter;
+mod donors;
+mod duplicates;
 mod google_jwt;
+mod hospitals;
 mod jwt;
+mod matching;
+mod messages;
 mod models;
 mod myres;
+mod notify;
+mod orgs;
+mod pagination;
+mod params;
+mod patient;
+mod pii;
+mod pledges;
+mod post_events;
+mod post_query;
+mod public_url;
+mod push;
+mod rate_limit;
+mod refill_centres;
+mod responses;
+mod saved_searches;
 mod slog_nested;
+mod stream;
+#[cfg(test)]
+mod test_util;
+mod trust;
+mod webhooks;
+use beneficiary::{BeneficiaryInvalid, BeneficiaryNew};
+use blood::BloodGroup;
+use challenge::{ChallengeError, ChallengeSolution, Challenges};
+use contact::{ContactInvalid, ContactNew, ContactVisibility};
+use content_filter::{FilterAction, Verdict};
+use duplicates::{Fingerprint, ProbableDuplicate};
 use google_jwt::Claims;
 use google_jwt::JwkKeys;
 use google_jwt::JwtVerifier;
 use models::*;
+use myres::log_background_error;
 use myres::HasStatusCode;
 use myres::MyRes;
+use notify::{Notifiers, PostActivity};
+use orgs::{OrgPublic, OrgRole};
+use pagination::{Cursor, Page};
+use params::{InvalidParam, PageParams};
+use patient::{AgeBracket, Urgency};
+use pii::{PiiFinding, PiiPolicy};
+use post_query::{PostFilter, PostSort};
+use rate_limit::{RateLimited, RateLimiter};
+use refill_centres::RefillCentre;
 
 struct GoogleJwkKeys(RwLock<Arc<JwkKeys>>);
 
@@ -108,6 +154,26 @@ async fn main() -> Result<()> {
     let jwt_verifier = JwtVerifier { audience, issuers };
     let _ = JWT_VERIFIER.set(jwt_verifier);
 
+    let vapid_key = push::VapidKey::load(&pool)
+        .await
+        .context("Load VAPID key")?;
+    let web_push = Arc::new(push::WebPushSender::new(vapid_key));
+    let push_notifier = Arc::new(push::PushNotifier::new(pool.clone(), web_push.clone()));
+    let notifiers =
+        Arc::new(notify::Notifiers::from_env(push_notifier).context("Set up notifiers")?);
+    tokio::spawn(saved_searches::run_worker(pool.clone(), notifiers.clone()));
+    tokio::spawn(webhooks::run_fan_out_worker(pool.clone()));
+    tokio::spawn(webhooks::run_delivery_worker(pool.clone()));
+    let pii_policy = PiiPolicy::from_env().context("Load PII policy")?;
+    let rate_limiter = RateLimiter::from_env(&pool).context("Set up rate limits")?;
+    tokio::spawn(rate_limit::run_pruner(pool.clone()));
+    let challenges = Challenges::from_env().context("Set up challenges")?;
+    tokio::spawn(challenge::run_pruner(pool.clone()));
+    tokio::spawn(trust::run_worker(pool.clone()));
+    tokio::spawn(post_events::run_pruner(pool.clone()));
+    let post_stream = Arc::new(stream::PostStream::default());
+    tokio::spawn(stream::run_listener(pool.clone(), post_stream.clone()));
+
     let allowed_origins_str =
         std::env::var("CORS_ALLOWED_ORIGINS").context("Get CORS_ALLOWED_ORIGINS env var")?;
     let allowed_origins = allowed_origins_str
@@ -132,6 +198,7 @@ async fn main() -> Result<()> {
             "Authorization",
             "Accept",
             "Content-Type",
+            challenge::SOLUTION_HEADER,
         ]),
         allow_credentials: false,
         ..Default::default()
@@ -151,9 +218,36 @@ async fn main() -> Result<()> {
                 posts_update,
                 posts_delete,
                 post_single,
+                post_matches,
+                posts_confirm,
+                posts_status_update,
+                posts_bump,
             ],
         )
+        .mount("/", challenge::routes())
+        .mount("/", contact::routes())
+        .mount("/", content_filter::routes())
+        .mount("/", duplicates::routes())
+        .mount("/", saved_searches::routes())
+        .mount("/", push::routes())
+        .mount("/", webhooks::routes())
+        .mount("/", stream::routes())
+        .mount("/", donors::routes())
+        .mount("/", hospitals::routes())
+        .mount("/", refill_centres::routes())
+        .mount("/", trust::routes())
+        .mount("/", orgs::routes())
+        .mount("/", responses::routes())
+        .mount("/", pledges::routes())
+        .mount("/", messages::routes())
         .manage(pool)
+        .manage(notifiers)
+        .manage(web_push)
+        .manage(post_stream)
+        .manage(pii_policy)
+        .manage(rate_limiter)
+        .manage(challenges)
+        .register("/", rate_limit::catchers())
         .attach(cors)
         .launch()
         .await
@@ -175,12 +269,14 @@ struct LoginSuccess {
 #[derive(Serialize)]
 enum LoginErr {
     InvalidToken,
+    Challenge(ChallengeError),
 }
 
 impl HasStatusCode for LoginErr {
     fn get_status(&self) -> Status {
         match self {
             LoginErr::InvalidToken => Status::Unauthorized,
+            LoginErr::Challenge(e) => e.get_status(),
         }
     }
 }
@@ -192,7 +288,13 @@ impl HasStatusCode for () {
 }
 
 #[post("/login", data = "<data>")]
-async fn login(data: Json<Login>, db: State<'_, PgPool>) -> MyRes<LoginSuccess, LoginErr> {
+async fn login(
+    _limit: RateLimited,
+    solution: ChallengeSolution,
+    data: Json<Login>,
+    db: State<'_, PgPool>,
+    challenges: State<'_, Challenges>,
+) -> MyRes<LoginSuccess, LoginErr> {
     let keys = GOOGLE_JWK_KEYS.get().unwrap();
     let keys = fail!(keys.get_latest_keys().await);
     let jwt_verifier = JWT_VERIFIER.get().unwrap();
@@ -208,6 +310,11 @@ async fn login(data: Json<Login>, db: State<'_, PgPool>) -> MyRes<LoginSuccess,
     )
     .map(|u| u.id);
 
+    // Signing up costs a solved challenge, and so does logging in to an
+    // account that is still new.
+    let res = challenges.check(&db, userid, &solution).await;
+    bail!(fail!(res), LoginErr::Challenge);
+
     let userid = match userid {
         Some(userid) => userid,
         None => {
@@ -275,11 +382,22 @@ impl<'r> FromRequest<'r> for LoggedInUser {
     }
 }
 
+async fn is_admin(db: &PgPool, userid: Uuid) -> sqlx::Result<bool> {
+    let res = sqlx::query!("SELECT admin FROM users WHERE id = $1", userid)
+        .fetch_optional(db)
+        .await?;
+    Ok(res.map(|u| u.admin).unwrap_or(false))
+}
+
 #[get("/profile")]
 async fn profile(user: LoggedInUser, db: State<'_, PgPool>) -> MyRes<User, ()> {
-    let res = sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", &user.0)
-        .fetch_optional(&*db)
-        .await;
+    let res = sqlx::query_as!(
+        User,
+        "SELECT id, name, email, profile_pic_url, bio, verified, admin FROM users WHERE id = $1",
+        &user.0
+    )
+    .fetch_optional(&*db)
+    .await;
     let user = fail!(res);
     let user = fail!(user.ok_or_else(|| anyhow!("Logged in user not found in db")));
     MyRes::Ok(user)
@@ -298,7 +416,8 @@ async fn profile_update(
 ) -> MyRes<User, ()> {
     let res = sqlx::query_as!(
         User,
-        "UPDATE users SET bio=$2 WHERE id = $1 RETURNING *",
+        "UPDATE users SET bio=$2 WHERE id = $1
+        RETURNING id, name, email, profile_pic_url, bio, verified, admin",
         &user.0,
         &data.bio
     )
@@ -309,13 +428,24 @@ async fn profile_update(
     MyRes::Ok(user)
 }
 
-#[derive(Serialize, Deserialize, sqlx::Type, FromFormField)]
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type, FromFormField)]
 #[sqlx(rename_all = "lowercase")]
 pub enum PostType {
     Needs,
     Supplies,
 }
 
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type, FromFormField)]
+#[sqlx(rename_all = "lowercase")]
+pub enum PostStatus {
+    Open,
+    // Set by `pledges` as deliveries come in.
+    #[sqlx(rename = "partially_fulfilled")]
+    PartiallyFulfilled,
+    Fulfilled,
+    Closed,
+}
+
 #[derive(Serialize)]
 pub struct ProfilePublic {
     id: Uuid,
@@ -323,125 +453,347 @@ pub struct ProfilePublic {
     profile_pic_url: String,
     bio: String,
     verified: bool,
+    // 0 to 100, see `trust`.
+    trust_score: i16,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Post {
     id: Uuid,
+    // The author, even when the post belongs to an org.
     userid: Uuid,
+    org_id: Option<Uuid>,
     post_type: PostType,
+    status: PostStatus,
+    urgency: Option<Urgency>,
+    patient_age_bracket: Option<AgeBracket>,
+    patient_spo2: Option<i16>,
+    patient_blood_group: Option<BloodGroup>,
+    patient_hospital: Option<String>,
+    patient_public: bool,
+    // The details themselves are only given out by `reveal_contact`.
+    contact_visibility: ContactVisibility,
+    // Whether the author posted for someone else, see `beneficiary`.
+    on_behalf_of: bool,
+    beneficiary_name: Option<String>,
+    beneficiary_relationship: Option<String>,
     state: String,
     district: String,
     city: String,
     spot: String,
     created_at: chrono::DateTime<chrono::Utc>,
+    // Last edit of the content.
     updated_at: chrono::DateTime<chrono::Utc>,
+    // Last time the author moved the post to the top of the listing, see
+    // `posts_bump`.
+    bumped_at: chrono::DateTime<chrono::Utc>,
     item: String,
     quantity: String,
+    // What is left of a need's quantity once pledges are counted, see
+    // `pledges`.
+    quantity_remaining: Option<i32>,
     message: String,
 }
 
-#[get("/posts?<start>&<n>&<typ>&<location>&<item>")]
+impl Post {
+    // Patient details, and the name of whoever the post is for, are for
+    // logged in users only, unless the author chose to make them public.
+    fn hide_patient_details(&mut self) {
+        if !self.patient_public {
+            self.beneficiary_name = None;
+            self.patient_age_bracket = None;
+            self.patient_spo2 = None;
+            self.patient_blood_group = None;
+            self.patient_hospital = None;
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum PostsResponse {
+    // Plain list for clients still paging with `start`/`n`.
+    Legacy(Vec<Post>),
+    Page(PostsPage),
+}
+
+#[derive(Serialize)]
+struct PostsPage {
+    #[serde(flatten)]
+    page: Page<Post>,
+    // Searches for oxygen supplies also get refill centres in the area, on
+    // the first page.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    refill_centres: Vec<RefillCentre>,
+}
+
+// Number of refill centres shown next to oxygen supplies.
+const REFILL_CENTRES_WITH_POSTS: i64 = 5;
+
+#[derive(Serialize)]
+enum PostsError {
+    InvalidCursor,
+    InvalidParam(InvalidParam),
+}
+
+impl HasStatusCode for PostsError {
+    fn get_status(&self) -> Status {
+        match self {
+            PostsError::InvalidCursor => Status::BadRequest,
+            PostsError::InvalidParam(_) => Status::BadRequest,
+        }
+    }
+}
+
+#[derive(FromForm)]
+struct PostsParams {
+    start: Option<String>,
+    n: Option<String>,
+    typ: Vec<String>,
+    location: Option<String>,
+    item: Vec<String>,
+    cursor: Option<String>,
+    count: Option<String>,
+    sort: Option<String>,
+    status: Vec<String>,
+    urgency: Vec<String>,
+    author: Option<String>,
+    verified_only: Option<String>,
+    min_trust: Option<String>,
+    updated_since: Option<String>,
+}
+
+impl PostsParams {
+    fn parse(&self) -> Result<(PostFilter, PostSort, PageParams), InvalidParam> {
+        let page = PageParams::parse(self.start.as_deref(), self.n.as_deref())?;
+        let sort = params::parse_sort(self.sort.as_deref())?;
+        let location = self
+            .location
+            .as_ref()
+            .map(|l| l.trim().to_owned())
+            .filter(|l| !l.is_empty());
+        if sort == PostSort::Distance && location.is_none() {
+            return Err(InvalidParam::new("sort", "distance needs a location"));
+        }
+        if self.cursor.is_some() {
+            if self.start.is_some() {
+                return Err(InvalidParam::new("start", "can't be combined with cursor"));
+            }
+            if !matches!(sort, PostSort::Bumped | PostSort::Updated | PostSort::Created) {
+                return Err(InvalidParam::new(
+                    "cursor",
+                    "only works with sort=bumped, sort=updated or sort=created",
+                ));
+            }
+        }
+        let filter = PostFilter {
+            types: params::parse_post_types(&self.typ)?,
+            statuses: params::parse_statuses(&self.status)?,
+            location,
+            items: self
+                .item
+                .iter()
+                .map(|i| i.trim().to_owned())
+                .filter(|i| !i.is_empty())
+                .collect(),
+            urgencies: params::parse_urgencies(&self.urgency)?,
+            author: params::parse_uuid("author", self.author.as_deref())?,
+            verified_only: params::parse_flag("verified_only", self.verified_only.as_deref())?,
+            min_trust: params::parse_trust("min_trust", self.min_trust.as_deref())?,
+            updated_since: params::parse_timestamp("updated_since", self.updated_since.as_deref())?,
+        };
+        Ok((filter, sort, page))
+    }
+}
+
+// Passing `cursor` (empty for the first page) switches to keyset pagination
+// and the `Page` envelope. Without it, `start`/`n` page through a plain list.
+#[get("/posts?<query..>")]
 async fn posts(
-    start: Option<i64>,
-    n: Option<i64>,
-    typ: PostType,
-    mut location: Option<String>,
-    mut item: Option<String>,
+    query: PostsParams,
+    viewer: Option<LoggedInUser>,
     db: State<'_, PgPool>,
-) -> MyRes<Vec<Post>, ()> {
-    location.as_mut().map(|s| {
-        s.insert(0, '%');
-        s.push('%');
-    });
+) -> MyRes<PostsResponse, PostsError> {
+    let (filter, sort, page) = bail!(query.parse(), PostsError::InvalidParam);
+    let count = bail!(
+        params::parse_flag("count", query.count.as_deref()),
+        PostsError::InvalidParam
+    );
 
-    item.as_mut().map(|s| {
-        s.insert(0, '%');
-        s.push('%');
-    });
+    let after = match query.cursor.as_deref() {
+        None | Some("") => None,
+        Some(cursor) => Some(bail!(Cursor::decode(cursor).ok_or(()), |_| {
+            PostsError::InvalidCursor
+        })),
+    };
+    let paged = query.cursor.is_some();
+    let (offset, limit) = if paged {
+        // One extra row tells us whether there is a next page.
+        (0, page.n + 1)
+    } else {
+        (page.start, page.n)
+    };
 
-    let res = sqlx::query_as!(
+    let (sql, args) = filter
+        .select(sort, after.as_ref(), offset, limit)
+        .into_parts();
+    let res = sqlx::query_as_with::<_, Post, _>(&sql, args)
+        .fetch_all(&*db)
+        .await;
+    let mut posts = fail!(res);
+    if viewer.is_none() {
+        posts.iter_mut().for_each(Post::hide_patient_details);
+    }
+
+    if !paged {
+        return MyRes::Ok(PostsResponse::Legacy(posts));
+    }
+
+    let has_more = posts.len() as i64 > page.n;
+    posts.truncate(page.n as usize);
+    let next_cursor = if has_more {
+        posts
+            .last()
+            .and_then(|p| sort.cursor_for(p))
+            .map(|c| c.encode())
+    } else {
+        None
+    };
+
+    let approx_total = if count {
+        let (sql, args) = filter.count(pagination::COUNT_CAP).into_parts();
+        let res = sqlx::query_as_with::<_, (i64,), _>(&sql, args)
+            .fetch_one(&*db)
+            .await;
+        Some(fail!(res).0)
+    } else {
+        None
+    };
+
+    let wants_oxygen = filter.types.contains(&PostType::Supplies)
+        && filter
+            .items
+            .iter()
+            .any(|i| matching::normalize_item(i) == "oxygen");
+    let refill_centres = if wants_oxygen && after.is_none() {
+        let res = refill_centres::search(
+            &db,
+            filter.location.clone(),
+            None,
+            0,
+            REFILL_CENTRES_WITH_POSTS,
+        )
+        .await;
+        fail!(res)
+    } else {
+        vec![]
+    };
+
+    MyRes::Ok(PostsResponse::Page(PostsPage {
+        page: Page {
+            items: posts,
+            has_more,
+            next_cursor,
+            approx_total,
+        },
+        refill_centres,
+    }))
+}
+
+// Held posts are left out unless `include_held`, see `content_filter`.
+async fn fetch_post(db: &PgPool, id: Uuid, include_held: bool) -> sqlx::Result<Option<Post>> {
+    sqlx::query_as!(
         Post,
         r#"
         SELECT posts.id,
                userid,
+               org_id,
                post_type as "post_type: _",
+               status as "status: _",
+               urgency as "urgency: _",
+               patient_age_bracket as "patient_age_bracket: _",
+               patient_spo2,
+               patient_blood_group as "patient_blood_group: _",
+               patient_hospital,
+               patient_public,
+               contact_visibility as "contact_visibility: _",
+               beneficiary_name IS NOT NULL as "on_behalf_of!",
+               beneficiary_name,
+               beneficiary_relationship,
                state,
                district,
                city,
                spot,
                created_at,
                updated_at,
+               bumped_at,
                item,
                quantity,
+               quantity_remaining,
                message
         FROM posts 
-        WHERE post_type = $3 AND (
-            $4::text IS NULL OR
-            state ILIKE $4 OR 
-            district ILIKE $4 OR 
-            city ILIKE $4 
-            OR spot ILIKE $4
-        ) AND (
-            $5::text IS NULL OR
-            item ILIKE $5
-        )
-        ORDER BY updated_at DESC
-        OFFSET $1
-        LIMIT $2
-        "#,
-        start,
-        n,
-        typ: _,
-        location,
-        item
+        WHERE id = $1 AND (NOT held OR $2)"#,
+        id,
+        include_held
     )
-    .fetch_all(&*db)
-    .await;
-    let posts = fail!(res);
-
-    MyRes::Ok(posts)
+    .fetch_optional(db)
+    .await
 }
 
 #[derive(Serialize)]
 struct PostSingle {
     post: Post,
     user: Option<ProfilePublic>,
+    org: Option<OrgPublic>,
+    // Whether the viewer wrote the post or is an editor in its org.
+    can_edit: bool,
 }
 
 #[get("/posts/<id>")]
 async fn post_single(
     id: rocket_contrib::uuid::Uuid,
+    viewer: Option<LoggedInUser>,
     db: State<'_, PgPool>,
 ) -> MyRes<PostSingle, ()> {
-    let res = sqlx::query_as!(
-        Post,
-        r#"
-        SELECT posts.id,
-               userid,
-               post_type as "post_type: _",
-               state,
-               district,
-               city,
-               spot,
-               created_at,
-               updated_at,
-               item,
-               quantity,
-               message
-        FROM posts 
-        WHERE id = $1"#,
-        id.into_inner()
-    )
-    .fetch_optional(&*db)
-    .await;
+    let res = fetch_post(&db, id.into_inner(), true).await;
     let post = fail!(res);
 
-    if let Some(post) = post {
+    if let Some(mut post) = post {
+        let res = sqlx::query!(
+            "SELECT message_raw, held FROM posts WHERE id = $1",
+            post.id
+        )
+        .fetch_one(&*db)
+        .await;
+        let private = fail!(res);
+        let viewer = viewer.map(|v| v.0);
+        let can_edit = match viewer {
+            Some(viewer) => {
+                fail!(orgs::can_edit_post(&db, post.userid, post.org_id, viewer).await)
+            }
+            None => false,
+        };
+        if private.held && !can_edit {
+            let admin = match viewer {
+                Some(viewer) => fail!(is_admin(&db, viewer).await),
+                None => false,
+            };
+            if !admin {
+                return MyRes::Err(());
+            }
+        }
+        if viewer.is_none() {
+            post.hide_patient_details();
+        }
+        if can_edit {
+            if let Some(raw) = private.message_raw {
+                post.message = raw;
+            }
+        }
         let res = sqlx::query_as!(
             ProfilePublic,
             r#"
-            SELECT id, name, profile_pic_url, bio, verified
+            SELECT id, name, profile_pic_url, bio, verified, trust_score
             FROM users 
             WHERE id = $1 AND verified = TRUE"#,
             post.userid
@@ -449,34 +801,144 @@ async fn post_single(
         .fetch_optional(&*db)
         .await;
         let user = fail!(res);
-        MyRes::Ok(PostSingle { post, user })
+        let org = match post.org_id {
+            Some(org_id) => fail!(orgs::public(&db, org_id).await),
+            None => None,
+        };
+        MyRes::Ok(PostSingle {
+            post,
+            user,
+            org,
+            can_edit,
+        })
     } else {
         MyRes::Err(())
     }
 }
 
-#[get("/my_posts")]
-async fn my_posts(user: LoggedInUser, db: State<'_, PgPool>) -> MyRes<Vec<Post>, ()> {
+#[derive(Serialize)]
+struct PostMatch {
+    post: Post,
+    score: f64,
+    #[serde(skip)]
+    supplies_updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl<'r> sqlx::FromRow<'r, sqlx::postgres::PgRow> for PostMatch {
+    fn from_row(row: &'r sqlx::postgres::PgRow) -> sqlx::Result<Self> {
+        use sqlx::Row;
+        Ok(PostMatch {
+            post: Post::from_row(row)?,
+            score: row.try_get("score")?,
+            supplies_updated_at: row.try_get("supplies_updated_at")?,
+        })
+    }
+}
+
+#[get("/posts/<id>/matches")]
+async fn post_matches(
+    id: rocket_contrib::uuid::Uuid,
+    viewer: Option<LoggedInUser>,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<PostMatch>, ()> {
+    let sql = format!(
+        r#"
+        SELECT {}, score, supplies.updated_at as supplies_updated_at
+        FROM post_matches
+        JOIN posts ON posts.id = CASE
+            WHEN needs_id = $1 THEN supplies_id
+            ELSE needs_id
+        END
+        JOIN posts supplies ON supplies.id = supplies_id
+        WHERE (needs_id = $1 OR supplies_id = $1) AND NOT posts.held
+          AND posts.status IN ('open', 'partially_fulfilled')
+        "#,
+        post_query::POST_COLUMNS
+    );
+    let res = sqlx::query_as::<_, PostMatch>(&sql)
+        .bind(id.into_inner())
+        .fetch_all(&*db)
+        .await;
+    let matches = fail!(res);
+    // Freshness is added now rather than stored, so that matches fade as
+    // the supplies post gets older.
+    let now = chrono::Utc::now();
+    let mut matches = matches
+        .into_iter()
+        .filter_map(|mut m| {
+            m.score = matching::score_at(m.score, m.supplies_updated_at, now)?;
+            Some(m)
+        })
+        .collect::<Vec<_>>();
+    matches.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    if viewer.is_none() {
+        for m in &mut matches {
+            m.post.hide_patient_details();
+        }
+    }
+
+    MyRes::Ok(matches)
+}
+
+// Also lists the posts of orgs the user is an editor in. With
+// `on_behalf_of`, only the ones posted for someone else, for volunteers
+// keeping track of the people they post for.
+#[get("/my_posts?<start>&<n>&<on_behalf_of>")]
+async fn my_posts(
+    start: Option<String>,
+    n: Option<String>,
+    on_behalf_of: Option<String>,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<Post>, PostsError> {
+    let page = bail!(
+        PageParams::parse(start.as_deref(), n.as_deref()),
+        PostsError::InvalidParam
+    );
+    let on_behalf_of = bail!(
+        params::parse_flag("on_behalf_of", on_behalf_of.as_deref()),
+        PostsError::InvalidParam
+    );
     let res = sqlx::query_as!(
         Post,
         r#"
         SELECT posts.id,
                userid,
+               org_id,
                post_type as "post_type: _",
+               status as "status: _",
+               urgency as "urgency: _",
+               patient_age_bracket as "patient_age_bracket: _",
+               patient_spo2,
+               patient_blood_group as "patient_blood_group: _",
+               patient_hospital,
+               patient_public,
+               contact_visibility as "contact_visibility: _",
+               beneficiary_name IS NOT NULL as "on_behalf_of!",
+               beneficiary_name,
+               beneficiary_relationship,
                state,
                district,
                city,
                spot,
                created_at,
                updated_at,
+               bumped_at,
                item,
                quantity,
+               quantity_remaining,
                message
         FROM posts 
-        WHERE userid = $1
-        ORDER BY updated_at DESC
+        WHERE id IN (SELECT post_id FROM post_editors WHERE userid = $1)
+        AND (NOT $4 OR beneficiary_name IS NOT NULL)
+        ORDER BY updated_at DESC, id DESC
+        OFFSET $2
+        LIMIT $3
         "#,
-        user.0
+        user.0,
+        page.start,
+        page.n,
+        on_behalf_of
     )
     .fetch_all(&*db)
     .await;
@@ -495,14 +957,184 @@ pub struct PostNew {
     message: String,
     item: String,
     quantity: String,
+    // Only for needs posts.
+    urgency: Option<Urgency>,
+    patient_age_bracket: Option<AgeBracket>,
+    patient_spo2: Option<i16>,
+    patient_blood_group: Option<BloodGroup>,
+    patient_hospital: Option<String>,
+    #[serde(default)]
+    patient_public: bool,
+    // Left as is on update when missing.
+    contact: Option<ContactNew>,
+    // Keeps the message as written, for the author only, when personal
+    // data in it is masked.
+    #[serde(default)]
+    keep_raw_message: bool,
+    // Saves the post even if it looks like a duplicate.
+    #[serde(default)]
+    allow_duplicate: bool,
+    // Posts for an org the author is an editor in. Only used on create,
+    // after that the post stays with the org.
+    #[serde(default)]
+    org_id: Option<Uuid>,
+    // Set when posting for someone else.
+    #[serde(default)]
+    on_behalf_of: Option<BeneficiaryNew>,
+}
+
+#[derive(Serialize)]
+enum PostInvalid {
+    NeedsOnlyFields,
+    InvalidSpo2,
+    Contact(ContactInvalid),
+    Beneficiary(BeneficiaryInvalid),
+    ProbableDuplicates(Vec<ProbableDuplicate>),
+    Challenge(ChallengeError),
+    // Matched a content filter rule that turns posts away.
+    Rejected,
+    NotOrgEditor,
+}
+
+impl HasStatusCode for PostInvalid {
+    fn get_status(&self) -> Status {
+        match self {
+            PostInvalid::ProbableDuplicates(_) => Status::Conflict,
+            PostInvalid::Challenge(e) => e.get_status(),
+            PostInvalid::NotOrgEditor => Status::Forbidden,
+            _ => Status::BadRequest,
+        }
+    }
+}
+
+impl PostNew {
+    fn validate(&mut self) -> Result<(), PostInvalid> {
+        self.patient_hospital = self
+            .patient_hospital
+            .as_ref()
+            .map(|h| h.trim().to_owned())
+            .filter(|h| !h.is_empty());
+        let has_needs_fields = self.urgency.is_some()
+            || self.patient_age_bracket.is_some()
+            || self.patient_spo2.is_some()
+            || self.patient_blood_group.is_some()
+            || self.patient_hospital.is_some();
+        if let PostType::Supplies = self.post_type {
+            if has_needs_fields {
+                return Err(PostInvalid::NeedsOnlyFields);
+            }
+        }
+        if let Some(spo2) = self.patient_spo2 {
+            if !(1..=100).contains(&spo2) {
+                return Err(PostInvalid::InvalidSpo2);
+            }
+        }
+        if let Some(contact) = &mut self.contact {
+            contact.validate().map_err(PostInvalid::Contact)?;
+        }
+        if let Some(beneficiary) = &mut self.on_behalf_of {
+            beneficiary.validate().map_err(PostInvalid::Beneficiary)?;
+        }
+        Ok(())
+    }
+
+    // Recent posts this one looks like, unless the author said it isn't a
+    // duplicate. Must run before the message is scrubbed, since phone
+    // numbers in it count.
+    async fn duplicates(
+        &self,
+        db: &PgPool,
+        exclude: Option<Uuid>,
+    ) -> sqlx::Result<Vec<ProbableDuplicate>> {
+        if self.allow_duplicate {
+            return Ok(vec![]);
+        }
+        let phones = self.contact.as_ref().map(|c| &c.phones[..]).unwrap_or(&[]);
+        let location = matching::Location {
+            state: &self.state,
+            district: &self.district,
+            city: &self.city,
+            spot: &self.spot,
+        };
+        let fingerprint = Fingerprint::new(&self.item, location, phones, &self.message);
+        duplicates::find(db, self.post_type, &fingerprint, exclude).await
+    }
+
+    // Runs the content filter over everything the author wrote.
+    async fn filter(&self, db: &PgPool) -> anyhow::Result<Verdict> {
+        let mut text = vec![
+            &self.item[..],
+            &self.quantity,
+            &self.state,
+            &self.district,
+            &self.city,
+            &self.spot,
+            &self.message,
+        ];
+        text.extend(self.patient_hospital.as_deref());
+        if let Some(beneficiary) = &self.on_behalf_of {
+            text.push(&beneficiary.name);
+            text.push(&beneficiary.relationship);
+        }
+        content_filter::evaluate(db, &text.join("\n")).await
+    }
+
+    // Masks personal data in the message. Returns the message as written if
+    // anything was masked and the author asked to keep it.
+    fn scrub(&mut self, policy: &PiiPolicy) -> (Option<String>, Vec<PiiFinding>) {
+        let (message, pii) = policy.apply(&self.message);
+        let raw = std::mem::replace(&mut self.message, message);
+        let raw = if self.keep_raw_message && raw != self.message {
+            Some(raw)
+        } else {
+            None
+        };
+        (raw, pii)
+    }
+}
+
+#[derive(Serialize)]
+struct PostSaved {
+    #[serde(flatten)]
+    post: Post,
+    // Personal data found in the message.
+    pii: Vec<PiiFinding>,
+    // Waiting for an admin to review it before others can see it.
+    held: bool,
 }
 
 #[post("/posts", data = "<data>")]
 async fn posts_create(
+    _limit: RateLimited,
+    solution: ChallengeSolution,
     user: LoggedInUser,
     db: State<'_, PgPool>,
-    data: Json<PostNew>,
-) -> MyRes<Post, ()> {
+    pii_policy: State<'_, PiiPolicy>,
+    challenges: State<'_, Challenges>,
+    mut data: Json<PostNew>,
+) -> MyRes<PostSaved, PostInvalid> {
+    bail!(data.validate(), |e| e);
+    if let Some(org_id) = data.org_id {
+        let role = fail!(orgs::role(&db, org_id, user.0).await);
+        if !role.map(OrgRole::can_edit_posts).unwrap_or(false) {
+            return MyRes::Err(PostInvalid::NotOrgEditor);
+        }
+    }
+    let verdict = fail!(data.filter(&db).await);
+    if verdict.action() == Some(FilterAction::Reject) {
+        fail!(verdict.log(&db, user.0, None).await);
+        return MyRes::Err(PostInvalid::Rejected);
+    }
+    let duplicates = fail!(data.duplicates(&db, None).await);
+    if !duplicates.is_empty() {
+        return MyRes::Err(PostInvalid::ProbableDuplicates(duplicates));
+    }
+    // Last, so a post that is turned away anyway doesn't use up the solution.
+    let res = challenges.check(&db, Some(user.0), &solution).await;
+    bail!(fail!(res), PostInvalid::Challenge);
+    let (message_raw, pii) = data.scrub(&pii_policy);
+    let contact = data.contact.take().unwrap_or_default();
+    let beneficiary = data.on_behalf_of.as_ref();
     let res = sqlx::query_as!(
         Post,
         r#"INSERT INTO posts(
@@ -514,20 +1146,53 @@ async fn posts_create(
             spot,
             item, 
             quantity,
-            message
-        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING 
+            message,
+            urgency,
+            patient_age_bracket,
+            patient_spo2,
+            patient_blood_group,
+            patient_hospital,
+            patient_public,
+            contact_phones,
+            contact_whatsapp,
+            contact_email,
+            contact_visibility,
+            message_raw,
+            held,
+            org_id,
+            beneficiary_name,
+            beneficiary_relationship,
+            beneficiary_phone,
+            beneficiary_consent,
+            beneficiary_consent_at,
+            quantity_remaining
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28) RETURNING 
                id,
                userid,
+               org_id,
                post_type as "post_type: _",
+               status as "status: _",
+               urgency as "urgency: _",
+               patient_age_bracket as "patient_age_bracket: _",
+               patient_spo2,
+               patient_blood_group as "patient_blood_group: _",
+               patient_hospital,
+               patient_public,
+               contact_visibility as "contact_visibility: _",
+               beneficiary_name IS NOT NULL as "on_behalf_of!",
+               beneficiary_name,
+               beneficiary_relationship,
                state,
                district,
                city,
                spot,
                item,
                quantity,
+               quantity_remaining,
                message,
                created_at,
-               updated_at
+               updated_at,
+               bumped_at
         "#,
         user.0,
         data.post_type: _,
@@ -537,37 +1202,83 @@ async fn posts_create(
         data.spot,
         data.item,
         data.quantity,
-        data.message
+        data.message,
+        data.urgency: _,
+        data.patient_age_bracket: _,
+        data.patient_spo2,
+        data.patient_blood_group: _,
+        data.patient_hospital,
+        data.patient_public,
+        &contact.phones[..],
+        contact.whatsapp,
+        contact.email,
+        contact.visibility: _,
+        message_raw,
+        verdict.held(),
+        data.org_id,
+        beneficiary.map(|b| &b.name),
+        beneficiary.map(|b| &b.relationship),
+        beneficiary.and_then(|b| b.phone.as_ref()),
+        beneficiary.and_then(|b| b.consent): _,
+        beneficiary.map(|_| chrono::Utc::now()),
+        pledges::amount(data.post_type, &data.quantity)
     )
     .fetch_one(&*db)
     .await;
 
     let post = fail!(res);
+    fail!(verdict.log(&db, user.0, Some(post.id)).await);
+    let held = verdict.held();
+    if !held {
+        spawn_refresh_matches(&db, &post);
+    }
 
-    MyRes::Ok(post)
+    MyRes::Ok(PostSaved { post, pii, held })
 }
 
 #[derive(Serialize)]
 enum PostUpdateError {
     NotFound,
+    Invalid(PostInvalid),
 }
 
 impl HasStatusCode for PostUpdateError {
     fn get_status(&self) -> Status {
         match self {
             PostUpdateError::NotFound => Status::NotFound,
+            PostUpdateError::Invalid(e) => e.get_status(),
         }
     }
 }
 
 #[patch("/posts/<id>", data = "<data>")]
 async fn posts_update(
+    _limit: RateLimited,
     id: rocket_contrib::uuid::Uuid,
     user: LoggedInUser,
     db: State<'_, PgPool>,
-    data: Json<PostNew>,
-) -> MyRes<Post, PostUpdateError> {
+    pii_policy: State<'_, PiiPolicy>,
+    mut data: Json<PostNew>,
+) -> MyRes<PostSaved, PostUpdateError> {
+    bail!(data.validate(), PostUpdateError::Invalid);
     let id: Uuid = id.into_inner();
+    let verdict = fail!(data.filter(&db).await);
+    if verdict.action() == Some(FilterAction::Reject) {
+        // Not tied to the post, which may not even be theirs.
+        fail!(verdict.log(&db, user.0, None).await);
+        return MyRes::Err(PostUpdateError::Invalid(PostInvalid::Rejected));
+    }
+    let duplicates = fail!(data.duplicates(&db, Some(id)).await);
+    if !duplicates.is_empty() {
+        let e = PostInvalid::ProbableDuplicates(duplicates);
+        return MyRes::Err(PostUpdateError::Invalid(e));
+    }
+    let (message_raw, pii) = data.scrub(&pii_policy);
+    let set_contact = data.contact.is_some();
+    let contact = data.contact.take().unwrap_or_default();
+    let beneficiary = data.on_behalf_of.as_ref();
+    // The consent time only moves when the consent is given again or is for
+    // someone else.
     let res = sqlx::query_as!(
         Post,
         r#"UPDATE posts SET
@@ -579,20 +1290,57 @@ async fn posts_update(
             message = $8,
             item = $9,
             quantity = $10,
-            updated_at = $11
-         WHERE id = $1 AND userid = $2
+            updated_at = $11,
+            urgency = $12,
+            patient_age_bracket = $13,
+            patient_spo2 = $14,
+            patient_blood_group = $15,
+            patient_hospital = $16,
+            patient_public = $17,
+            contact_phones = CASE WHEN $18 THEN $19 ELSE contact_phones END,
+            contact_whatsapp = CASE WHEN $18 THEN $20 ELSE contact_whatsapp END,
+            contact_email = CASE WHEN $18 THEN $21 ELSE contact_email END,
+            contact_visibility = CASE WHEN $18 THEN $22 ELSE contact_visibility END,
+            message_raw = $23,
+            held = $24,
+            beneficiary_name = $25,
+            beneficiary_relationship = $26,
+            beneficiary_phone = $27,
+            beneficiary_consent = $28,
+            beneficiary_consent_at = CASE
+                WHEN beneficiary_consent IS NOT DISTINCT FROM $28
+                    AND beneficiary_name IS NOT DISTINCT FROM $25
+                    AND beneficiary_phone IS NOT DISTINCT FROM $27
+                THEN beneficiary_consent_at
+                ELSE $29
+            END
+         WHERE id = $1 AND id IN (SELECT post_id FROM post_editors WHERE userid = $2)
          RETURNING 
                id,
                userid,
+               org_id,
                post_type as "post_type: _",
+               status as "status: _",
+               urgency as "urgency: _",
+               patient_age_bracket as "patient_age_bracket: _",
+               patient_spo2,
+               patient_blood_group as "patient_blood_group: _",
+               patient_hospital,
+               patient_public,
+               contact_visibility as "contact_visibility: _",
+               beneficiary_name IS NOT NULL as "on_behalf_of!",
+               beneficiary_name,
+               beneficiary_relationship,
                state,
                district,
                city,
                spot,
                item,
                quantity,
+               quantity_remaining,
                created_at,
                updated_at,
+               bumped_at,
                message
         "#,
         id,
@@ -605,15 +1353,47 @@ async fn posts_update(
         data.message,
         data.item,
         data.quantity,
-        chrono::Utc::now()
+        chrono::Utc::now(),
+        data.urgency: _,
+        data.patient_age_bracket: _,
+        data.patient_spo2,
+        data.patient_blood_group: _,
+        data.patient_hospital,
+        data.patient_public,
+        set_contact,
+        &contact.phones[..],
+        contact.whatsapp,
+        contact.email,
+        contact.visibility: _,
+        message_raw,
+        verdict.held(),
+        beneficiary.map(|b| &b.name),
+        beneficiary.map(|b| &b.relationship),
+        beneficiary.and_then(|b| b.phone.as_ref()),
+        beneficiary.and_then(|b| b.consent): _,
+        beneficiary.map(|_| chrono::Utc::now())
     )
     .fetch_optional(&*db)
     .await;
 
     let post = fail!(res);
-    let post = bail!(post.ok_or(()), |_| PostUpdateError::NotFound);
+    let mut post = bail!(post.ok_or(()), |_| PostUpdateError::NotFound);
+    fail!(verdict.log(&db, user.0, Some(post.id)).await);
+    // The quantity or type may have changed under the pledges against it.
+    let mut tx = fail!(db.begin().await);
+    let refreshed = fail!(pledges::refresh(&mut tx, post.id).await);
+    fail!(tx.commit().await);
+    if let Some(refreshed) = refreshed {
+        post.quantity_remaining = refreshed.quantity_remaining;
+        post.status = refreshed.status;
+    }
+    // Each edit is filtered again, so held posts go up once they are clean.
+    let held = verdict.held();
+    if !held {
+        spawn_refresh_matches(&db, &post);
+    }
 
-    MyRes::Ok(post)
+    MyRes::Ok(PostSaved { post, pii, held })
 }
 
 #[delete("/posts/<id>")]
@@ -625,10 +1405,176 @@ async fn posts_delete(
     let id: Uuid = id.into_inner();
     let res = sqlx::query!(
         r#"DELETE FROM posts
-        WHERE id = $1 AND userid = $2"#,
+        WHERE id = $1 AND id IN (SELECT post_id FROM post_editors WHERE userid = $2)"#,
+        id,
+        user.0,
+    )
+    .execute(&*db)
+    .await;
+
+    let res = fail!(res);
+    if res.rows_affected() == 0 {
+        return MyRes::Err(PostUpdateError::NotFound);
+    }
+    MyRes::Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct PostStatusUpdate {
+    status: PostStatus,
+}
+
+// Marking a post fulfilled or closed is not an edit of its content, so it
+// doesn't touch updated_at.
+#[post("/posts/<id>/status", data = "<data>")]
+async fn posts_status_update(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<PostStatusUpdate>,
+) -> MyRes<(), PostUpdateError> {
+    let res = sqlx::query!(
+        r#"UPDATE posts SET status = $3
+        WHERE id = $1 AND id IN (SELECT post_id FROM post_editors WHERE userid = $2)"#,
+        id.into_inner(),
+        user.0,
+        data.status: _
+    )
+    .execute(&*db)
+    .await;
+
+    let res = fail!(res);
+    if res.rows_affected() == 0 {
+        return MyRes::Err(PostUpdateError::NotFound);
+    }
+    MyRes::Ok(())
+}
+
+// How often an author can move a post back to the top of the listing.
+const BUMP_INTERVAL_HOURS: i64 = 6;
+
+#[derive(Serialize)]
+enum BumpError {
+    NotFound,
+    NotOpen,
+    TooSoon {
+        next_bump_at: chrono::DateTime<chrono::Utc>,
+    },
+}
+
+impl HasStatusCode for BumpError {
+    fn get_status(&self) -> Status {
+        match self {
+            BumpError::NotFound => Status::NotFound,
+            BumpError::NotOpen => Status::BadRequest,
+            BumpError::TooSoon { .. } => Status::TooManyRequests,
+        }
+    }
+}
+
+// Moves an open post to the top of the default listing, for leads that are
+// still good but have sunk below newer posts.
+#[post("/posts/<id>/bump")]
+async fn posts_bump(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Post, BumpError> {
+    let id: Uuid = id.into_inner();
+    let res = sqlx::query!(
+        r#"SELECT status as "status: PostStatus", bumped_at
+        FROM posts
+        WHERE id = $1 AND id IN (SELECT post_id FROM post_editors WHERE userid = $2)"#,
         id,
         user.0,
     )
+    .fetch_optional(&*db)
+    .await;
+    let current = fail!(res);
+    let current = bail!(current.ok_or(()), |_| BumpError::NotFound);
+    if !matches!(
+        current.status,
+        PostStatus::Open | PostStatus::PartiallyFulfilled
+    ) {
+        return MyRes::Err(BumpError::NotOpen);
+    }
+    let next_bump_at = current.bumped_at + chrono::Duration::hours(BUMP_INTERVAL_HOURS);
+    if next_bump_at > chrono::Utc::now() {
+        return MyRes::Err(BumpError::TooSoon { next_bump_at });
+    }
+
+    // Only if no other request bumped it in the meantime.
+    let res = sqlx::query_as!(
+        Post,
+        r#"UPDATE posts SET bumped_at = NOW()
+        WHERE id = $1 AND bumped_at = $3
+          AND id IN (SELECT post_id FROM post_editors WHERE userid = $2)
+        RETURNING
+               id,
+               userid,
+               org_id,
+               post_type as "post_type: _",
+               status as "status: _",
+               urgency as "urgency: _",
+               patient_age_bracket as "patient_age_bracket: _",
+               patient_spo2,
+               patient_blood_group as "patient_blood_group: _",
+               patient_hospital,
+               patient_public,
+               contact_visibility as "contact_visibility: _",
+               beneficiary_name IS NOT NULL as "on_behalf_of!",
+               beneficiary_name,
+               beneficiary_relationship,
+               state,
+               district,
+               city,
+               spot,
+               item,
+               quantity,
+               quantity_remaining,
+               created_at,
+               updated_at,
+               bumped_at,
+               message
+        "#,
+        id,
+        user.0,
+        current.bumped_at,
+    )
+    .fetch_optional(&*db)
+    .await;
+    let post = fail!(res);
+    let post = bail!(post.ok_or(()), |_| BumpError::TooSoon { next_bump_at });
+    MyRes::Ok(post)
+}
+
+#[derive(Deserialize)]
+pub struct Confirmation {
+    available: bool,
+}
+
+// Someone who called up the contact in a post, saying whether the supply
+// or need is still there.
+#[post("/posts/<id>/confirm", data = "<data>")]
+async fn posts_confirm(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    notifiers: State<'_, Arc<Notifiers>>,
+    data: Json<Confirmation>,
+) -> MyRes<(), PostUpdateError> {
+    let id: Uuid = id.into_inner();
+    let res = sqlx::query!(
+        r#"INSERT INTO post_confirmations(post_id, userid, available)
+        SELECT id, $2, $3 FROM posts WHERE id = $1
+        ON CONFLICT (post_id, userid) DO UPDATE SET
+            available = EXCLUDED.available,
+            created_at = NOW()
+        "#,
+        id,
+        user.0,
+        data.available
+    )
     .execute(&*db)
     .await;
 
@@ -636,5 +1582,43 @@ async fn posts_delete(
     if res.rows_affected() == 0 {
         return MyRes::Err(PostUpdateError::NotFound);
     }
+    spawn_notify_post_author(
+        &db,
+        &notifiers,
+        id,
+        user.0,
+        PostActivity::Confirmed {
+            available: data.available,
+        },
+    );
     MyRes::Ok(())
 }
+
+fn spawn_notify_post_author(
+    db: &PgPool,
+    notifiers: &Arc<Notifiers>,
+    post_id: Uuid,
+    actor: Uuid,
+    activity: PostActivity,
+) {
+    let db = db.clone();
+    let notifiers = notifiers.clone();
+    tokio::spawn(async move {
+        let res = notify::notify_post_author(&db, &notifiers, post_id, actor, activity).await;
+        if let Err(e) = res {
+            log_background_error("notify_post_author", &e);
+        }
+    });
+}
+
+// Matching runs after the response is sent, so a slow match doesn't hold up
+// the author.
+fn spawn_refresh_matches(db: &PgPool, post: &Post) {
+    let db = db.clone();
+    let post = post.clone();
+    tokio::spawn(async move {
+        if let Err(e) = matching::refresh_matches(&db, &post).await {
+            log_background_error("refresh_matches", &e);
+        }
+    });
+}
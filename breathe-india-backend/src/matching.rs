@@ -0,0 +1,339 @@
+// Scores supplies posts against needs posts, so that both sides of the app
+// can see each other without searching by hand.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{Post, PostStatus, PostType};
+
+// Items that people write in different ways but mean the same thing.
+// The first entry of each group is the canonical name.
+const SYNONYMS: &[&[&str]] = &[
+    &["oxygen", "o2", "oxygen cylinder", "o2 cylinder", "cylinder", "oxygen refill"],
+    &["oxygen concentrator", "concentrator", "o2 concentrator"],
+    &["plasma", "convalescent plasma", "covid plasma"],
+    &["blood", "blood donor", "whole blood"],
+    &["bed", "beds", "hospital bed", "general bed"],
+    &["icu", "icu bed", "icu beds"],
+    &["ventilator", "ventilator bed", "ventilators"],
+    &["remdesivir", "remdisivir", "remdesvir", "remdesivir injection"],
+    &["tocilizumab", "toci", "actemra"],
+    &["ambulance", "ambulances"],
+    &["food", "meals", "tiffin", "home cooked food"],
+];
+
+const WEIGHT_ITEM: f64 = 0.4;
+const WEIGHT_LOCATION: f64 = 0.3;
+const WEIGHT_QUANTITY: f64 = 0.1;
+const WEIGHT_FRESHNESS: f64 = 0.2;
+
+// Matches scoring below this are not worth showing.
+const MIN_SCORE: f64 = 0.5;
+
+// A supplies post loses half its freshness score every this many hours.
+const FRESHNESS_HALF_LIFE_HOURS: f64 = 24.0;
+
+// Only posts touched within this window are considered as candidates.
+const CANDIDATE_MAX_AGE_DAYS: i64 = 14;
+
+pub fn normalize_item(item: &str) -> String {
+    let item = item
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    for group in SYNONYMS {
+        if group.contains(&item.as_str()) {
+            return group[0].to_owned();
+        }
+    }
+    item
+}
+
+pub fn item_score(needs: &str, supplies: &str) -> f64 {
+    let needs = normalize_item(needs);
+    let supplies = normalize_item(supplies);
+    if needs.is_empty() || supplies.is_empty() {
+        return 0.0;
+    }
+    if needs == supplies {
+        return 1.0;
+    }
+    let needs_words = needs.split(' ').map(normalize_item).collect::<Vec<_>>();
+    let supplies_words = supplies.split(' ').map(normalize_item).collect::<Vec<_>>();
+    if needs_words.iter().any(|w| supplies_words.contains(w)) {
+        0.6
+    } else {
+        0.0
+    }
+}
+
+pub struct Location<'a> {
+    pub state: &'a str,
+    pub district: &'a str,
+    pub city: &'a str,
+    pub spot: &'a str,
+}
+
+impl<'a> Location<'a> {
+    pub fn of(post: &'a Post) -> Self {
+        Location {
+            state: &post.state,
+            district: &post.district,
+            city: &post.city,
+            spot: &post.spot,
+        }
+    }
+}
+
+fn same_place(a: &str, b: &str) -> bool {
+    let a = a.trim();
+    let b = b.trim();
+    !a.is_empty() && a.eq_ignore_ascii_case(b)
+}
+
+// We don't have coordinates for posts, so distance is measured by how far up
+// the state > district > city > spot hierarchy we have to go to find a
+// common place.
+pub fn location_score(a: &Location, b: &Location) -> f64 {
+    if !same_place(a.state, b.state) {
+        return 0.0;
+    }
+    if !same_place(a.district, b.district) {
+        return 0.3;
+    }
+    if !same_place(a.city, b.city) {
+        return 0.6;
+    }
+    if !same_place(a.spot, b.spot) {
+        return 0.8;
+    }
+    1.0
+}
+
+// Quantities are free text like "2 cylinders" or "10L".
+// Picks out the first number, if any.
+pub fn parse_quantity(quantity: &str) -> Option<f64> {
+    let start = quantity.find(|c: char| c.is_ascii_digit())?;
+    let rest = &quantity[start..];
+    let end = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or_else(|| rest.len());
+    rest[..end].parse().ok()
+}
+
+pub fn quantity_score(needs: &str, supplies: &str) -> f64 {
+    match (parse_quantity(needs), parse_quantity(supplies)) {
+        (Some(needs), Some(supplies)) if needs > 0.0 => (supplies / needs).min(1.0),
+        // Can't tell, so neither reward nor punish it.
+        _ => 0.5,
+    }
+}
+
+pub fn freshness_score(updated_at: DateTime<Utc>, now: DateTime<Utc>) -> f64 {
+    let hours = (now - updated_at).num_minutes().max(0) as f64 / 60.0;
+    0.5f64.powf(hours / FRESHNESS_HALF_LIFE_HOURS)
+}
+
+// Everything but freshness, which keeps changing after the match is stored.
+// Returns None if the supplies post is not a match for the needs post at all,
+// or could never score enough to show, however fresh.
+pub fn score(needs: &Post, supplies: &Post) -> Option<f64> {
+    let item = item_score(&needs.item, &supplies.item);
+    let location = location_score(&Location::of(needs), &Location::of(supplies));
+    if item == 0.0 || location == 0.0 {
+        return None;
+    }
+    let score = WEIGHT_ITEM * item
+        + WEIGHT_LOCATION * location
+        + WEIGHT_QUANTITY * quantity_score(&needs.quantity, &supplies.quantity);
+    if score + WEIGHT_FRESHNESS < MIN_SCORE {
+        None
+    } else {
+        Some(score)
+    }
+}
+
+// The score of a stored match as of `now`. None once it has gone stale
+// enough not to be worth showing.
+pub fn score_at(
+    stored: f64,
+    supplies_updated_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Option<f64> {
+    let score = stored + WEIGHT_FRESHNESS * freshness_score(supplies_updated_at, now);
+    if score < MIN_SCORE {
+        None
+    } else {
+        Some(score)
+    }
+}
+
+// Recomputes all matches for a post that was just created or updated.
+// Matches are stored in `post_matches`, so reading them is a simple lookup.
+// Posts that are fulfilled or closed match nothing.
+pub async fn refresh_matches(db: &PgPool, post: &Post) -> Result<()> {
+    let other_type = match post.post_type {
+        PostType::Needs => PostType::Supplies,
+        PostType::Supplies => PostType::Needs,
+    };
+    let since = Utc::now() - chrono::Duration::days(CANDIDATE_MAX_AGE_DAYS);
+    let candidates = sqlx::query_as!(
+        Post,
+        r#"
+        SELECT posts.id,
+               userid,
+               org_id,
+               post_type as "post_type: _",
+               status as "status: _",
+               urgency as "urgency: _",
+               patient_age_bracket as "patient_age_bracket: _",
+               patient_spo2,
+               patient_blood_group as "patient_blood_group: _",
+               patient_hospital,
+               patient_public,
+               contact_visibility as "contact_visibility: _",
+               beneficiary_name IS NOT NULL as "on_behalf_of!",
+               beneficiary_name,
+               beneficiary_relationship,
+               state,
+               district,
+               city,
+               spot,
+               created_at,
+               updated_at,
+               bumped_at,
+               item,
+               quantity,
+               quantity_remaining,
+               message
+        FROM posts
+        WHERE post_type = $1 AND state ILIKE $2 AND updated_at > $3 AND NOT held
+          AND status IN ('open', 'partially_fulfilled')
+        "#,
+        other_type: _,
+        post.state.trim(),
+        since
+    )
+    .fetch_all(db)
+    .await
+    .context("Fetch match candidates")?;
+
+    let open = matches!(
+        post.status,
+        PostStatus::Open | PostStatus::PartiallyFulfilled
+    );
+    let matches = candidates.iter().filter(|_| open).filter_map(|other| {
+        let (needs, supplies) = match post.post_type {
+            PostType::Needs => (post, other),
+            PostType::Supplies => (other, post),
+        };
+        score(needs, supplies).map(|score| (needs.id, supplies.id, score))
+    });
+
+    let mut tx = db.begin().await?;
+    sqlx::query!(
+        "DELETE FROM post_matches WHERE needs_id = $1 OR supplies_id = $1",
+        post.id
+    )
+    .execute(&mut tx)
+    .await
+    .context("Delete old matches")?;
+    for (needs_id, supplies_id, score) in matches {
+        insert_match(&mut tx, needs_id, supplies_id, score).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn insert_match(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    needs_id: Uuid,
+    supplies_id: Uuid,
+    score: f64,
+) -> Result<()> {
+    sqlx::query!(
+        r#"INSERT INTO post_matches(needs_id, supplies_id, score)
+        VALUES ($1, $2, $3)"#,
+        needs_id,
+        supplies_id,
+        score
+    )
+    .execute(tx)
+    .await
+    .context("Insert match")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_item_score() {
+        let cases = &[
+            ("Oxygen", "O2 Cylinder", 1.0),
+            ("  oxygen   cylinder ", "oxygen", 1.0),
+            ("remdisivir", "Remdesivir", 1.0),
+            ("ICU bed", "icu", 1.0),
+            ("A+ blood", "blood", 0.6),
+            ("plasma", "oxygen", 0.0),
+            ("", "oxygen", 0.0),
+        ];
+        for (needs, supplies, expected) in cases {
+            assert_eq!(item_score(needs, supplies), *expected, "{} / {}", needs, supplies);
+        }
+    }
+
+    #[test]
+    fn test_location_score() {
+        let loc = |state, district, city, spot| Location {
+            state,
+            district,
+            city,
+            spot,
+        };
+        let needs = loc("Kerala", "Ernakulam", "Kochi", "Edappally");
+        let cases = &[
+            (loc("kerala", "ernakulam", "kochi", "edappally"), 1.0),
+            (loc("Kerala", "Ernakulam", "Kochi", "Kakkanad"), 0.8),
+            (loc("Kerala", "Ernakulam", "Aluva", ""), 0.6),
+            (loc("Kerala", "Thrissur", "", ""), 0.3),
+            (loc("Karnataka", "", "", ""), 0.0),
+        ];
+        for (supplies, expected) in cases {
+            assert_eq!(location_score(&needs, supplies), *expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_quantity() {
+        let cases = &[
+            ("2 cylinders", Some(2.0)),
+            ("10L", Some(10.0)),
+            ("approx 1.5 units", Some(1.5)),
+            ("many", None),
+        ];
+        for (quantity, expected) in cases {
+            assert_eq!(parse_quantity(quantity), *expected);
+        }
+        assert_eq!(quantity_score("4", "2"), 0.5);
+        assert_eq!(quantity_score("2", "4"), 1.0);
+        assert_eq!(quantity_score("lots", "4"), 0.5);
+    }
+
+    #[test]
+    fn test_freshness_score() {
+        let now = Utc::now();
+        assert_eq!(freshness_score(now, now), 1.0);
+        assert_eq!(freshness_score(now - chrono::Duration::hours(24), now), 0.5);
+        assert_eq!(freshness_score(now - chrono::Duration::hours(48), now), 0.25);
+
+        // Stored matches fade as the supplies post gets older.
+        assert_eq!(score_at(0.3, now, now), Some(0.5));
+        assert_eq!(score_at(0.4, now - chrono::Duration::hours(24), now), Some(0.5));
+        assert_eq!(score_at(0.4, now - chrono::Duration::hours(48), now), None);
+    }
+}
@@ -0,0 +1,623 @@
+// Private messages between a post's author and someone answering it, so
+// neither has to put a phone number in public. Each conversation belongs to
+// a post and one responder. Moderators can only read a conversation once
+// either side reports it.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::content_filter::{self, FilterAction};
+use crate::myres::{log_background_error, HasStatusCode, MyRes};
+use crate::notify::{self, Notification, Notifiers};
+use crate::rate_limit::RateLimited;
+use crate::{bail, fail, is_admin, LoggedInUser};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        conversations_mine,
+        conversations_reported,
+        posts_conversations_create,
+        conversations_messages,
+        conversations_messages_create,
+        conversations_read,
+        conversations_report,
+        users_block,
+        users_unblock,
+    ]
+}
+
+const MAX_BODY_LEN: usize = 2000;
+const MAX_REASON_LEN: usize = 1000;
+
+#[derive(Serialize)]
+pub struct Conversation {
+    id: Uuid,
+    // None once the post is deleted.
+    post_id: Option<Uuid>,
+    item: Option<String>,
+    other_userid: Uuid,
+    other_name: String,
+    last_message_at: DateTime<Utc>,
+    // Messages from the other side since the user last read the
+    // conversation.
+    unread: i64,
+    // Either side blocked the other, so no more messages.
+    blocked: bool,
+    reported: bool,
+}
+
+#[derive(Serialize)]
+pub struct Message {
+    id: Uuid,
+    conversation_id: Uuid,
+    sender: Uuid,
+    sender_name: String,
+    body: String,
+    created_at: DateTime<Utc>,
+    // Whether whoever it was sent to has read it.
+    read: bool,
+}
+
+#[derive(Serialize)]
+pub struct ReportedConversation {
+    id: Uuid,
+    post_id: Option<Uuid>,
+    author_id: Uuid,
+    author_name: String,
+    responder_id: Uuid,
+    responder_name: String,
+    reported_by: Option<Uuid>,
+    report_reason: String,
+    reported_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct MessageNew {
+    body: String,
+}
+
+#[derive(Deserialize)]
+pub struct ConversationReport {
+    reason: String,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+enum MessageError {
+    NotFound,
+    OwnPost,
+    EmptyBody,
+    TooLong,
+    // Matched a content filter rule that holds or turns away posts.
+    Rejected,
+    Blocked,
+    CannotBlockSelf,
+    ReasonTooLong,
+    NotAdmin,
+}
+
+impl HasStatusCode for MessageError {
+    fn get_status(&self) -> Status {
+        match self {
+            MessageError::NotFound => Status::NotFound,
+            MessageError::OwnPost => Status::BadRequest,
+            MessageError::EmptyBody => Status::BadRequest,
+            MessageError::TooLong => Status::BadRequest,
+            MessageError::Rejected => Status::BadRequest,
+            MessageError::Blocked => Status::Forbidden,
+            MessageError::CannotBlockSelf => Status::BadRequest,
+            MessageError::ReasonTooLong => Status::BadRequest,
+            MessageError::NotAdmin => Status::Forbidden,
+        }
+    }
+}
+
+struct Participants {
+    author_id: Uuid,
+    responder_id: Uuid,
+    reported: bool,
+}
+
+impl Participants {
+    // The other side, if `userid` is one of them.
+    fn other(&self, userid: Uuid) -> Option<Uuid> {
+        if userid == self.author_id {
+            Some(self.responder_id)
+        } else if userid == self.responder_id {
+            Some(self.author_id)
+        } else {
+            None
+        }
+    }
+}
+
+fn validate_body(body: &str) -> Result<&str, MessageError> {
+    let body = body.trim();
+    if body.is_empty() {
+        return Err(MessageError::EmptyBody);
+    }
+    if body.chars().count() > MAX_BODY_LEN {
+        return Err(MessageError::TooLong);
+    }
+    Ok(body)
+}
+
+async fn participants(db: &PgPool, id: Uuid) -> sqlx::Result<Option<Participants>> {
+    sqlx::query_as!(
+        Participants,
+        r#"SELECT author_id, responder_id, reported_at IS NOT NULL as "reported!"
+        FROM conversations WHERE id = $1"#,
+        id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+// Either way round.
+async fn blocked(db: &PgPool, a: Uuid, b: Uuid) -> sqlx::Result<bool> {
+    let res = sqlx::query!(
+        r#"SELECT EXISTS(
+            SELECT 1 FROM user_blocks
+            WHERE (userid = $1 AND blocked_userid = $2) OR (userid = $2 AND blocked_userid = $1)
+        ) as "blocked!""#,
+        a,
+        b
+    )
+    .fetch_one(db)
+    .await?;
+    Ok(res.blocked)
+}
+
+// Saves the message and lets the other side know. Sending a message also
+// counts as having read the conversation up to it.
+async fn send(
+    db: &PgPool,
+    notifiers: &Arc<Notifiers>,
+    conversation_id: Uuid,
+    sender: Uuid,
+    recipient: Uuid,
+    body: &str,
+) -> Result<Result<Message, MessageError>> {
+    if blocked(db, sender, recipient).await? {
+        return Ok(Err(MessageError::Blocked));
+    }
+    // Like responses, messages can't wait for a moderator.
+    let verdict = content_filter::evaluate(db, body).await?;
+    verdict.log(db, sender, None).await?;
+    if verdict.action() >= Some(FilterAction::Hold) {
+        return Ok(Err(MessageError::Rejected));
+    }
+
+    let mut tx = db.begin().await?;
+    let saved = sqlx::query!(
+        r#"
+        INSERT INTO messages(conversation_id, sender, body)
+        VALUES ($1, $2, $3)
+        RETURNING id,
+                  created_at,
+                  (SELECT name FROM users WHERE id = $2) as "sender_name!"
+        "#,
+        conversation_id,
+        sender,
+        body
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    sqlx::query!(
+        r#"UPDATE conversations SET
+            last_message_at = $2,
+            author_read_at = CASE WHEN author_id = $3 THEN $2 ELSE author_read_at END,
+            responder_read_at = CASE WHEN responder_id = $3 THEN $2 ELSE responder_read_at END
+        WHERE id = $1"#,
+        conversation_id,
+        saved.created_at,
+        sender
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    spawn_notify_message(
+        db,
+        notifiers,
+        conversation_id,
+        saved.sender_name.clone(),
+        recipient,
+    );
+    Ok(Ok(Message {
+        id: saved.id,
+        conversation_id,
+        sender,
+        sender_name: saved.sender_name,
+        body: body.to_owned(),
+        created_at: saved.created_at,
+        read: false,
+    }))
+}
+
+// Most recently active first.
+#[get("/conversations")]
+async fn conversations_mine(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<Conversation>, MessageError> {
+    let res = sqlx::query_as!(
+        Conversation,
+        r#"
+        SELECT c.id,
+               c.post_id,
+               posts.item as "item?",
+               other.id as other_userid,
+               other.name as other_name,
+               c.last_message_at,
+               (SELECT COUNT(*) FROM messages m
+                WHERE m.conversation_id = c.id AND m.sender <> $1 AND m.created_at > COALESCE(
+                    CASE WHEN c.author_id = $1 THEN c.author_read_at ELSE c.responder_read_at END,
+                    '-infinity'
+                )) as "unread!",
+               EXISTS(
+                   SELECT 1 FROM user_blocks b
+                   WHERE (b.userid = $1 AND b.blocked_userid = other.id)
+                      OR (b.userid = other.id AND b.blocked_userid = $1)
+               ) as "blocked!",
+               c.reported_at IS NOT NULL as "reported!"
+        FROM conversations c
+        LEFT JOIN posts ON posts.id = c.post_id
+        JOIN users other
+          ON other.id = CASE WHEN c.author_id = $1 THEN c.responder_id ELSE c.author_id END
+        WHERE (c.author_id = $1 OR c.responder_id = $1)
+        ORDER BY c.last_message_at DESC, c.id
+        "#,
+        user.0
+    )
+    .fetch_all(&*db)
+    .await;
+    let conversations = fail!(res);
+
+    MyRes::Ok(conversations)
+}
+
+#[get("/conversations/reported")]
+async fn conversations_reported(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<ReportedConversation>, MessageError> {
+    if !fail!(is_admin(&db, user.0).await) {
+        return MyRes::Err(MessageError::NotAdmin);
+    }
+    let res = sqlx::query_as!(
+        ReportedConversation,
+        r#"
+        SELECT c.id,
+               c.post_id,
+               c.author_id,
+               author.name as author_name,
+               c.responder_id,
+               responder.name as responder_name,
+               c.reported_by,
+               c.report_reason as "report_reason!",
+               c.reported_at as "reported_at!"
+        FROM conversations c
+        JOIN users author ON author.id = c.author_id
+        JOIN users responder ON responder.id = c.responder_id
+        WHERE c.reported_at IS NOT NULL
+        ORDER BY c.reported_at DESC
+        "#
+    )
+    .fetch_all(&*db)
+    .await;
+    let conversations = fail!(res);
+
+    MyRes::Ok(conversations)
+}
+
+// Starts a conversation with the post's author, or carries on the one the
+// user already has about the post.
+#[post("/posts/<id>/conversations", data = "<data>")]
+async fn posts_conversations_create(
+    _limit: RateLimited,
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    notifiers: State<'_, Arc<Notifiers>>,
+    data: Json<MessageNew>,
+) -> MyRes<Message, MessageError> {
+    let id: Uuid = id.into_inner();
+    let body = bail!(validate_body(&data.body), |e| e);
+    let res = sqlx::query!("SELECT userid FROM posts WHERE id = $1 AND NOT held", id)
+        .fetch_optional(&*db)
+        .await;
+    let post = fail!(res);
+    let post = bail!(post.ok_or(()), |_| MessageError::NotFound);
+    if post.userid == user.0 {
+        return MyRes::Err(MessageError::OwnPost);
+    }
+    if fail!(blocked(&db, user.0, post.userid).await) {
+        return MyRes::Err(MessageError::Blocked);
+    }
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO conversations(post_id, author_id, responder_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (post_id, responder_id) DO UPDATE SET post_id = EXCLUDED.post_id
+        RETURNING id, author_id
+        "#,
+        id,
+        post.userid,
+        user.0
+    )
+    .fetch_one(&*db)
+    .await;
+    let conversation = fail!(res);
+
+    let res = send(
+        &db,
+        &notifiers,
+        conversation.id,
+        user.0,
+        conversation.author_id,
+        body,
+    )
+    .await;
+    let message = bail!(fail!(res), |e| e);
+    MyRes::Ok(message)
+}
+
+// For either side, or for moderators once the conversation is reported.
+// Reading doesn't mark anything read, see `conversations_read`.
+#[get("/conversations/<id>/messages")]
+async fn conversations_messages(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<Message>, MessageError> {
+    let id: Uuid = id.into_inner();
+    let participants = fail!(participants(&db, id).await);
+    let participants = bail!(participants.ok_or(()), |_| MessageError::NotFound);
+    if participants.other(user.0).is_none() {
+        // Not saying whether it exists to anyone else.
+        if !participants.reported || !fail!(is_admin(&db, user.0).await) {
+            return MyRes::Err(MessageError::NotFound);
+        }
+    }
+
+    let res = sqlx::query_as!(
+        Message,
+        r#"
+        SELECT m.id,
+               m.conversation_id,
+               m.sender,
+               users.name as sender_name,
+               m.body,
+               m.created_at,
+               m.created_at <= COALESCE(
+                   CASE WHEN m.sender = c.author_id THEN c.responder_read_at ELSE c.author_read_at END,
+                   '-infinity'
+               ) as "read!"
+        FROM messages m
+        JOIN conversations c ON c.id = m.conversation_id
+        JOIN users ON users.id = m.sender
+        WHERE m.conversation_id = $1
+        ORDER BY m.created_at, m.id
+        "#,
+        id
+    )
+    .fetch_all(&*db)
+    .await;
+    let messages = fail!(res);
+
+    MyRes::Ok(messages)
+}
+
+#[post("/conversations/<id>/messages", data = "<data>")]
+async fn conversations_messages_create(
+    _limit: RateLimited,
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    notifiers: State<'_, Arc<Notifiers>>,
+    data: Json<MessageNew>,
+) -> MyRes<Message, MessageError> {
+    let id: Uuid = id.into_inner();
+    let body = bail!(validate_body(&data.body), |e| e);
+    let participants = fail!(participants(&db, id).await);
+    let participants = bail!(participants.ok_or(()), |_| MessageError::NotFound);
+    let recipient = bail!(participants.other(user.0).ok_or(()), |_| {
+        MessageError::NotFound
+    });
+
+    let res = send(&db, &notifiers, id, user.0, recipient, body).await;
+    let message = bail!(fail!(res), |e| e);
+    MyRes::Ok(message)
+}
+
+// Marks everything sent so far as read by the user, for read receipts on
+// the other side.
+#[post("/conversations/<id>/read")]
+async fn conversations_read(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<(), MessageError> {
+    let res = sqlx::query!(
+        r#"UPDATE conversations SET
+            author_read_at = CASE WHEN author_id = $2 THEN NOW() ELSE author_read_at END,
+            responder_read_at = CASE WHEN responder_id = $2 THEN NOW() ELSE responder_read_at END
+        WHERE id = $1 AND (author_id = $2 OR responder_id = $2)"#,
+        id.into_inner(),
+        user.0
+    )
+    .execute(&*db)
+    .await;
+    if fail!(res).rows_affected() == 0 {
+        return MyRes::Err(MessageError::NotFound);
+    }
+    MyRes::Ok(())
+}
+
+// Lets moderators read the conversation. A later report replaces the
+// reason of an earlier one.
+#[post("/conversations/<id>/report", data = "<data>")]
+async fn conversations_report(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<ConversationReport>,
+) -> MyRes<(), MessageError> {
+    let reason = data.reason.trim();
+    if reason.chars().count() > MAX_REASON_LEN {
+        return MyRes::Err(MessageError::ReasonTooLong);
+    }
+    let res = sqlx::query!(
+        r#"UPDATE conversations SET reported_at = NOW(), reported_by = $2, report_reason = $3
+        WHERE id = $1 AND (author_id = $2 OR responder_id = $2)"#,
+        id.into_inner(),
+        user.0,
+        reason
+    )
+    .execute(&*db)
+    .await;
+    if fail!(res).rows_affected() == 0 {
+        return MyRes::Err(MessageError::NotFound);
+    }
+    MyRes::Ok(())
+}
+
+#[post("/users/<id>/block")]
+async fn users_block(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<(), MessageError> {
+    let id: Uuid = id.into_inner();
+    if id == user.0 {
+        return MyRes::Err(MessageError::CannotBlockSelf);
+    }
+    let res = sqlx::query!("SELECT id FROM users WHERE id = $1", id)
+        .fetch_optional(&*db)
+        .await;
+    bail!(fail!(res).ok_or(()), |_| MessageError::NotFound);
+
+    let res = sqlx::query!(
+        r#"INSERT INTO user_blocks(userid, blocked_userid) VALUES ($1, $2)
+        ON CONFLICT DO NOTHING"#,
+        user.0,
+        id
+    )
+    .execute(&*db)
+    .await;
+    fail!(res);
+    MyRes::Ok(())
+}
+
+#[delete("/users/<id>/block")]
+async fn users_unblock(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<(), MessageError> {
+    let res = sqlx::query!(
+        "DELETE FROM user_blocks WHERE userid = $1 AND blocked_userid = $2",
+        user.0,
+        id.into_inner()
+    )
+    .execute(&*db)
+    .await;
+    fail!(res);
+    MyRes::Ok(())
+}
+
+// The message itself stays out of the notification, which may end up in
+// an email or on a lock screen.
+async fn notify_message(
+    db: &PgPool,
+    notifiers: &Notifiers,
+    conversation_id: Uuid,
+    sender_name: &str,
+    recipient: Uuid,
+) -> Result<()> {
+    let row = sqlx::query!(
+        r#"
+        SELECT users.email, posts.item as "item?"
+        FROM conversations c
+        JOIN users ON users.id = $2
+        LEFT JOIN posts ON posts.id = c.post_id
+        WHERE c.id = $1
+        "#,
+        conversation_id,
+        recipient
+    )
+    .fetch_optional(db)
+    .await
+    .context("Fetch message recipient")?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(()),
+    };
+    let notification = Notification {
+        title: format!("{} sent you a message", sender_name),
+        body: match row.item {
+            Some(item) => format!("About the post for {}.", item),
+            None => "About a post that has since been deleted.".to_owned(),
+        },
+        url: notify::app_url(&format!("/messages/{}", conversation_id)),
+    };
+    notify::notify_user(notifiers, recipient, row.email, &notification).await
+}
+
+fn spawn_notify_message(
+    db: &PgPool,
+    notifiers: &Arc<Notifiers>,
+    conversation_id: Uuid,
+    sender_name: String,
+    recipient: Uuid,
+) {
+    let db = db.clone();
+    let notifiers = notifiers.clone();
+    tokio::spawn(async move {
+        let res = notify_message(&db, &notifiers, conversation_id, &sender_name, recipient).await;
+        if let Err(e) = res {
+            log_background_error("notify_message", &e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_body() {
+        assert_eq!(
+            validate_body("  Is it still there?\n"),
+            Ok("Is it still there?")
+        );
+        assert_eq!(validate_body(" \n "), Err(MessageError::EmptyBody));
+        let long = "x".repeat(MAX_BODY_LEN + 1);
+        assert_eq!(validate_body(&long), Err(MessageError::TooLong));
+    }
+
+    #[test]
+    fn test_other() {
+        let participants = Participants {
+            author_id: Uuid::from_u128(1),
+            responder_id: Uuid::from_u128(2),
+            reported: false,
+        };
+        assert_eq!(
+            participants.other(Uuid::from_u128(1)),
+            Some(Uuid::from_u128(2))
+        );
+        assert_eq!(
+            participants.other(Uuid::from_u128(2)),
+            Some(Uuid::from_u128(1))
+        );
+        assert_eq!(participants.other(Uuid::from_u128(3)), None);
+    }
+}
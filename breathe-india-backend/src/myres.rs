@@ -74,3 +74,10 @@ macro_rules! bail {
         }
     };
 }
+
+// Background tasks have no request to fail, so their errors only get logged.
+pub fn log_background_error(task: &str, e: &anyhow::Error) {
+    let logger = crate::LOGGER.get().unwrap();
+    let error_chain = WrapSerde(e.chain().map(|e| e.to_string()).collect::<Vec<_>>());
+    slog::error!(logger, "Background task failed"; "task" => task.to_owned(), "error_chain" => error_chain);
+}
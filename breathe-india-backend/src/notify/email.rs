@@ -0,0 +1,37 @@
+use super::{Notification, Notifier, Recipient};
+use anyhow::{Context, Result};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+pub struct EmailNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: String,
+}
+
+impl EmailNotifier {
+    pub fn new(smtp_url: &str, from: &str) -> Result<Self> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(smtp_url)
+            .context("Parse SMTP_URL")?
+            .build();
+        Ok(EmailNotifier {
+            transport,
+            from: from.to_owned(),
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl Notifier for EmailNotifier {
+    async fn notify(&self, recipient: &Recipient, notification: &Notification) -> Result<()> {
+        let message = Message::builder()
+            .from(self.from.parse().context("Parse SMTP_FROM")?)
+            .to(recipient.email.parse().context("Parse recipient email")?)
+            .subject(&notification.title)
+            .body(format!("{}\n\n{}\n", notification.body, notification.url))
+            .context("Build email")?;
+        self.transport
+            .send(message)
+            .await
+            .context("Send email")?;
+        Ok(())
+    }
+}
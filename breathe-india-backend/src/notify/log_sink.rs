@@ -0,0 +1,37 @@
+use super::{Notification, Notifier, Recipient};
+use anyhow::Result;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+// Logs notifications and keeps them in memory instead of sending them.
+// Meant for local dev and tests.
+#[derive(Default)]
+pub struct LogNotifier {
+    sent: Mutex<Vec<(Uuid, Notification)>>,
+}
+
+impl LogNotifier {
+    #[cfg(test)]
+    pub fn sent(&self) -> Vec<(Uuid, Notification)> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[rocket::async_trait]
+impl Notifier for LogNotifier {
+    async fn notify(&self, recipient: &Recipient, notification: &Notification) -> Result<()> {
+        // Not set in tests, where `sent` is all that matters.
+        if let Some(logger) = crate::LOGGER.get() {
+            slog::info!(logger, "Notify";
+                "userid" => recipient.userid.to_string(),
+                "title" => notification.title.clone(),
+                "body" => notification.body.clone(),
+                "url" => notification.url.clone());
+        }
+        self.sent
+            .lock()
+            .unwrap()
+            .push((recipient.userid, notification.clone()));
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+// Delivers notifications to users over whichever channel they picked.
+
+mod email;
+mod log_sink;
+mod webhook;
+
+pub use email::EmailNotifier;
+pub use log_sink::LogNotifier;
+pub use webhook::WebhookNotifier;
+
+use anyhow::{Context, Result};
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::responses::ResponseKind;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum NotifyChannel {
+    Email,
+    Webhook,
+    Push,
+}
+
+pub struct Recipient {
+    pub userid: Uuid,
+    pub email: String,
+    pub webhook_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+    pub url: String,
+}
+
+#[rocket::async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, recipient: &Recipient, notification: &Notification) -> Result<()>;
+}
+
+pub struct Notifiers {
+    email: Option<Arc<dyn Notifier>>,
+    webhook: Option<Arc<dyn Notifier>>,
+    push: Option<Arc<dyn Notifier>>,
+}
+
+impl Notifiers {
+    // Email is only available if SMTP_URL is set.
+    // NOTIFY_LOG_ONLY sends everything to stdout instead, for local dev.
+    pub fn from_env(push: Arc<dyn Notifier>) -> Result<Self> {
+        if std::env::var("NOTIFY_LOG_ONLY").is_ok() {
+            return Ok(Self::log_only(Arc::new(LogNotifier::default())));
+        }
+        let email = match std::env::var("SMTP_URL") {
+            Ok(smtp_url) => {
+                let from = std::env::var("SMTP_FROM").context("Get SMTP_FROM env var")?;
+                let email = EmailNotifier::new(&smtp_url, &from).context("Set up SMTP")?;
+                Some(Arc::new(email) as Arc<dyn Notifier>)
+            }
+            Err(_) => None,
+        };
+        Ok(Notifiers {
+            email,
+            webhook: Some(Arc::new(WebhookNotifier::new())),
+            push: Some(push),
+        })
+    }
+
+    // Sends everything to the given log, whatever the channel.
+    pub fn log_only(log: Arc<LogNotifier>) -> Self {
+        Notifiers {
+            email: Some(log.clone()),
+            webhook: Some(log.clone()),
+            push: Some(log),
+        }
+    }
+
+    pub fn get(&self, channel: NotifyChannel) -> Option<&dyn Notifier> {
+        let notifier = match channel {
+            NotifyChannel::Email => &self.email,
+            NotifyChannel::Webhook => &self.webhook,
+            NotifyChannel::Push => &self.push,
+        };
+        notifier.as_deref()
+    }
+}
+
+// Something someone else did on your post.
+pub enum PostActivity {
+    Confirmed { available: bool },
+    Responded(ResponseKind),
+    Pledged { quantity: i32 },
+    Delivered { quantity: i32 },
+}
+
+impl Notification {
+    pub fn post_activity(post_id: Uuid, item: &str, actor: &str, activity: PostActivity) -> Self {
+        let (title, body) = match activity {
+            PostActivity::Confirmed { available: true } => (
+                format!("{} confirmed your post", actor),
+                format!("{} says {} is still available.", actor, item),
+            ),
+            PostActivity::Confirmed { available: false } => (
+                format!("{} confirmed your post", actor),
+                format!("{} says {} is no longer available.", actor, item),
+            ),
+            PostActivity::Responded(ResponseKind::Offer) => (
+                format!("{} offered to help", actor),
+                format!("{} offered help with your post about {}.", actor, item),
+            ),
+            PostActivity::Responded(ResponseKind::Question) => (
+                format!("{} asked about your post", actor),
+                format!("{} has a question about your post about {}.", actor, item),
+            ),
+            PostActivity::Responded(ResponseKind::Update) => (
+                format!("{} posted an update", actor),
+                format!("{} posted an update on your post about {}.", actor, item),
+            ),
+            PostActivity::Pledged { quantity } => (
+                format!("{} pledged {}", actor, quantity),
+                format!("{} pledged {} towards your {} need.", actor, quantity, item),
+            ),
+            PostActivity::Delivered { quantity } => (
+                format!("{} delivered {}", actor, quantity),
+                format!("{} says they delivered {} of {}.", actor, quantity, item),
+            ),
+        };
+        Notification {
+            title,
+            body,
+            url: post_url(post_id),
+        }
+    }
+}
+
+// Lets the author of a post know, by push, that someone else acted on it.
+pub async fn notify_post_author(
+    db: &PgPool,
+    notifiers: &Notifiers,
+    post_id: Uuid,
+    actor: Uuid,
+    activity: PostActivity,
+) -> Result<()> {
+    let row = sqlx::query!(
+        r#"
+        SELECT posts.item, posts.userid, author.email, actor.name AS actor_name
+        FROM posts
+        JOIN users author ON author.id = posts.userid
+        JOIN users actor ON actor.id = $2
+        WHERE posts.id = $1
+        "#,
+        post_id,
+        actor
+    )
+    .fetch_optional(db)
+    .await
+    .context("Fetch post author")?;
+    let row = match row {
+        Some(row) if row.userid != actor => row,
+        _ => return Ok(()),
+    };
+    let notification = Notification::post_activity(post_id, &row.item, &row.actor_name, activity);
+    notify_user(notifiers, row.userid, row.email, &notification).await
+}
+
+// Things that happen to a user, rather than to a search they saved, go out
+// by push.
+pub async fn notify_user(
+    notifiers: &Notifiers,
+    userid: Uuid,
+    email: String,
+    notification: &Notification,
+) -> Result<()> {
+    let notifier = match notifiers.get(NotifyChannel::Push) {
+        Some(notifier) => notifier,
+        None => return Ok(()),
+    };
+    let recipient = Recipient {
+        userid,
+        email,
+        webhook_url: None,
+    };
+    notifier.notify(&recipient, notification).await
+}
+
+// Link to a page on the frontend.
+pub fn app_url(path: &str) -> String {
+    let base = std::env::var("PUBLIC_URL").unwrap_or_default();
+    format!("{}{}", base.trim_end_matches('/'), path)
+}
+
+pub fn post_url(post_id: Uuid) -> String {
+    app_url(&format!("/post/{}", post_id))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn test_log_only_takes_every_channel() {
+        let log = Arc::new(LogNotifier::default());
+        let notifiers = Notifiers::log_only(log.clone());
+        let recipient = Recipient {
+            userid: Uuid::new_v4(),
+            email: "someone@example.com".to_owned(),
+            webhook_url: None,
+        };
+        let notification = Notification {
+            title: "title".to_owned(),
+            body: "body".to_owned(),
+            url: post_url(Uuid::new_v4()),
+        };
+        for channel in &[
+            NotifyChannel::Email,
+            NotifyChannel::Webhook,
+            NotifyChannel::Push,
+        ] {
+            let notifier = notifiers.get(*channel).unwrap();
+            notifier.notify(&recipient, &notification).await.unwrap();
+        }
+        let sent = log.sent();
+        assert_eq!(sent.len(), 3);
+        assert!(sent.iter().all(|(userid, _)| *userid == recipient.userid));
+    }
+}
@@ -0,0 +1,40 @@
+use super::{Notification, Notifier, Recipient};
+use crate::public_url;
+use anyhow::{anyhow, Context, Result};
+
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new() -> Self {
+        WebhookNotifier {
+            client: public_url::client_builder()
+                .build()
+                .expect("Build webhook HTTP client"),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, recipient: &Recipient, notification: &Notification) -> Result<()> {
+        let url = recipient
+            .webhook_url
+            .as_ref()
+            .ok_or_else(|| anyhow!("Recipient has no webhook url"))?;
+        // Checked again, the host may resolve somewhere else by now.
+        public_url::check(url)
+            .await
+            .with_context(|| format!("POST {}", url))?;
+        self.client
+            .post(url)
+            .json(notification)
+            .send()
+            .await
+            .with_context(|| format!("POST {}", url))?
+            .error_for_status()
+            .with_context(|| format!("POST {}", url))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,757 @@
+// Organisations, so that a hospital or NGO can post as one while everyone
+// in it signs in with their own account. Owners manage who is in it,
+// editors can also edit and delete any of its posts, and viewers can only
+// see who else is in it. People are invited by email and join once they
+// sign in with that address.
+
+use chrono::{DateTime, Utc};
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::myres::{log_background_error, HasStatusCode, MyRes};
+use crate::notify::{self, Notification, Notifiers, NotifyChannel, Recipient};
+use crate::rate_limit::RateLimited;
+use crate::{bail, fail, LoggedInUser};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        orgs_mine,
+        orgs_create,
+        orgs_single,
+        orgs_delete,
+        orgs_verify,
+        orgs_invite,
+        orgs_invitation_delete,
+        orgs_member_update,
+        orgs_member_remove,
+        invitations_mine,
+        invitations_respond,
+    ]
+}
+
+const MAX_NAME_LEN: usize = 200;
+// Each invitation emails someone who may never have heard of the org.
+const MAX_INVITATIONS_PER_DAY: i64 = 50;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum OrgRole {
+    Owner,
+    Editor,
+    Viewer,
+}
+
+impl OrgRole {
+    pub fn can_edit_posts(self) -> bool {
+        matches!(self, OrgRole::Owner | OrgRole::Editor)
+    }
+}
+
+// An org as one of its members sees it.
+#[derive(Serialize)]
+pub struct Org {
+    id: Uuid,
+    name: String,
+    role: OrgRole,
+    created_at: DateTime<Utc>,
+}
+
+// What everyone sees on the org's posts.
+#[derive(Serialize)]
+pub struct OrgPublic {
+    id: Uuid,
+    name: String,
+    verified: bool,
+}
+
+#[derive(Serialize)]
+pub struct OrgMember {
+    userid: Uuid,
+    name: String,
+    profile_pic_url: String,
+    role: OrgRole,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct OrgInvitation {
+    id: Uuid,
+    email: String,
+    role: OrgRole,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct OrgDetails {
+    #[serde(flatten)]
+    org: Org,
+    members: Vec<OrgMember>,
+    // Only shown to owners.
+    invitations: Vec<OrgInvitation>,
+}
+
+// An invitation as the person invited sees it.
+#[derive(Serialize)]
+pub struct InvitationReceived {
+    id: Uuid,
+    org_id: Uuid,
+    org_name: String,
+    role: OrgRole,
+    invited_by_name: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct OrgNew {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct Verification {
+    verified: bool,
+}
+
+#[derive(Deserialize)]
+pub struct InvitationNew {
+    email: String,
+    role: OrgRole,
+}
+
+#[derive(Deserialize)]
+pub struct MemberUpdate {
+    role: OrgRole,
+}
+
+#[derive(Deserialize)]
+pub struct InvitationResponse {
+    accept: bool,
+}
+
+#[derive(Serialize)]
+enum OrgError {
+    NotFound,
+    NotOwner,
+    NotAdmin,
+    InvalidName,
+    InvalidEmail,
+    AlreadyMember,
+    // Every org needs an owner, so the last one can't leave or step down.
+    LastOwner,
+    // More than MAX_INVITATIONS_PER_DAY sent by the org.
+    TooManyInvitations,
+}
+
+impl HasStatusCode for OrgError {
+    fn get_status(&self) -> Status {
+        match self {
+            OrgError::NotFound => Status::NotFound,
+            OrgError::NotOwner => Status::Forbidden,
+            OrgError::NotAdmin => Status::Forbidden,
+            OrgError::InvalidName => Status::BadRequest,
+            OrgError::InvalidEmail => Status::BadRequest,
+            OrgError::AlreadyMember => Status::Conflict,
+            OrgError::LastOwner => Status::BadRequest,
+            OrgError::TooManyInvitations => Status::TooManyRequests,
+        }
+    }
+}
+
+fn normalize_name(name: &str) -> Option<&str> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return None;
+    }
+    Some(name)
+}
+
+// Only catches typos. Whether the address is real shows when someone signs
+// in with it.
+fn normalize_email(email: &str) -> Option<String> {
+    let email = email.trim().to_lowercase();
+    let (local, domain) = email.split_once('@')?;
+    if local.is_empty()
+        || domain.contains('@')
+        || !domain.contains('.')
+        || domain.starts_with('.')
+        || domain.ends_with('.')
+        || email.chars().any(char::is_whitespace)
+    {
+        return None;
+    }
+    Some(email)
+}
+
+// None if they aren't a member.
+pub async fn role(db: &PgPool, org_id: Uuid, userid: Uuid) -> sqlx::Result<Option<OrgRole>> {
+    let row = sqlx::query!(
+        r#"SELECT role as "role: OrgRole" FROM org_members WHERE org_id = $1 AND userid = $2"#,
+        org_id,
+        userid
+    )
+    .fetch_optional(db)
+    .await?;
+    Ok(row.map(|r| r.role))
+}
+
+// Whether `userid` wrote the post or is an editor in the org it belongs to.
+// Authors of org posts have to still be members. Same as the `post_editors`
+// view, for when the post is already loaded.
+pub async fn can_edit_post(
+    db: &PgPool,
+    author: Uuid,
+    org_id: Option<Uuid>,
+    userid: Uuid,
+) -> sqlx::Result<bool> {
+    let org_id = match org_id {
+        Some(org_id) => org_id,
+        None => return Ok(author == userid),
+    };
+    let role = role(db, org_id, userid).await?;
+    Ok(role.map_or(false, |role| role.can_edit_posts() || author == userid))
+}
+
+pub async fn public(db: &PgPool, id: Uuid) -> sqlx::Result<Option<OrgPublic>> {
+    sqlx::query_as!(
+        OrgPublic,
+        "SELECT id, name, verified FROM orgs WHERE id = $1",
+        id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+async fn require_owner(
+    db: &PgPool,
+    org_id: Uuid,
+    userid: Uuid,
+) -> sqlx::Result<Result<(), OrgError>> {
+    Ok(match role(db, org_id, userid).await? {
+        Some(OrgRole::Owner) => Ok(()),
+        Some(_) => Err(OrgError::NotOwner),
+        None => Err(OrgError::NotFound),
+    })
+}
+
+// The member's role, and whether they are the only owner. Locks the org for
+// the rest of `tx`, so two owners can't both step down at once.
+async fn locked_member(
+    tx: &mut Transaction<'_, Postgres>,
+    org_id: Uuid,
+    userid: Uuid,
+) -> sqlx::Result<Option<(OrgRole, bool)>> {
+    sqlx::query!("SELECT id FROM orgs WHERE id = $1 FOR UPDATE", org_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let row = sqlx::query!(
+        r#"
+        SELECT role as "role: OrgRole",
+               (SELECT COUNT(*) FROM org_members WHERE org_id = $1 AND role = 'owner') as "owners!"
+        FROM org_members
+        WHERE org_id = $1 AND userid = $2
+        "#,
+        org_id,
+        userid
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    Ok(row.map(|r| (r.role, r.role == OrgRole::Owner && r.owners == 1)))
+}
+
+#[get("/orgs")]
+async fn orgs_mine(user: LoggedInUser, db: State<'_, PgPool>) -> MyRes<Vec<Org>, OrgError> {
+    let res = sqlx::query_as!(
+        Org,
+        r#"
+        SELECT orgs.id, orgs.name, m.role as "role: _", orgs.created_at
+        FROM org_members m
+        JOIN orgs ON orgs.id = m.org_id
+        WHERE m.userid = $1
+        ORDER BY orgs.name, orgs.id
+        "#,
+        user.0
+    )
+    .fetch_all(&*db)
+    .await;
+    let orgs = fail!(res);
+
+    MyRes::Ok(orgs)
+}
+
+// Whoever creates an org is its first owner.
+#[post("/orgs", data = "<data>")]
+async fn orgs_create(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<OrgNew>,
+) -> MyRes<Org, OrgError> {
+    let name = bail!(normalize_name(&data.name).ok_or(()), |_| {
+        OrgError::InvalidName
+    });
+
+    let mut tx = fail!(db.begin().await);
+    let res = sqlx::query!(
+        "INSERT INTO orgs(name) VALUES ($1) RETURNING id, created_at",
+        name
+    )
+    .fetch_one(&mut tx)
+    .await;
+    let org = fail!(res);
+    let res = sqlx::query!(
+        "INSERT INTO org_members(org_id, userid, role) VALUES ($1, $2, 'owner')",
+        org.id,
+        user.0
+    )
+    .execute(&mut tx)
+    .await;
+    fail!(res);
+    fail!(tx.commit().await);
+
+    MyRes::Ok(Org {
+        id: org.id,
+        name: name.to_owned(),
+        role: OrgRole::Owner,
+        created_at: org.created_at,
+    })
+}
+
+#[get("/orgs/<id>")]
+async fn orgs_single(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<OrgDetails, OrgError> {
+    let id: Uuid = id.into_inner();
+    let role = fail!(role(&db, id, user.0).await);
+    let role = bail!(role.ok_or(()), |_| OrgError::NotFound);
+
+    let res = sqlx::query!("SELECT name, created_at FROM orgs WHERE id = $1", id)
+        .fetch_one(&*db)
+        .await;
+    let org = fail!(res);
+    let res = sqlx::query_as!(
+        OrgMember,
+        r#"
+        SELECT m.userid,
+               users.name,
+               users.profile_pic_url,
+               m.role as "role: _",
+               m.created_at
+        FROM org_members m
+        JOIN users ON users.id = m.userid
+        WHERE m.org_id = $1
+        ORDER BY m.role, users.name, m.userid
+        "#,
+        id
+    )
+    .fetch_all(&*db)
+    .await;
+    let members = fail!(res);
+    let invitations = if role == OrgRole::Owner {
+        let res = sqlx::query_as!(
+            OrgInvitation,
+            r#"
+            SELECT id, email, role as "role: _", created_at
+            FROM org_invitations
+            WHERE org_id = $1
+            ORDER BY created_at DESC
+            "#,
+            id
+        )
+        .fetch_all(&*db)
+        .await;
+        fail!(res)
+    } else {
+        vec![]
+    };
+
+    MyRes::Ok(OrgDetails {
+        org: Org {
+            id,
+            name: org.name,
+            role,
+            created_at: org.created_at,
+        },
+        members,
+        invitations,
+    })
+}
+
+// The org's posts stay up, as their authors' own.
+#[delete("/orgs/<id>")]
+async fn orgs_delete(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<(), OrgError> {
+    let id: Uuid = id.into_inner();
+    bail!(fail!(require_owner(&db, id, user.0).await), |e| e);
+    let res = sqlx::query!("DELETE FROM orgs WHERE id = $1", id)
+        .execute(&*db)
+        .await;
+    fail!(res);
+
+    MyRes::Ok(())
+}
+
+// Marks the org as checked by an admin, or takes that back.
+#[put("/orgs/<id>/verified", data = "<data>")]
+async fn orgs_verify(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<Verification>,
+) -> MyRes<(), OrgError> {
+    let id: Uuid = id.into_inner();
+    if !fail!(crate::is_admin(&db, user.0).await) {
+        return MyRes::Err(OrgError::NotAdmin);
+    }
+    let res = sqlx::query!(
+        "UPDATE orgs SET verified = $2 WHERE id = $1",
+        id,
+        data.verified
+    )
+    .execute(&*db)
+    .await;
+    if fail!(res).rows_affected() == 0 {
+        return MyRes::Err(OrgError::NotFound);
+    }
+
+    MyRes::Ok(())
+}
+
+// Inviting the same address again changes the role it is invited as, and
+// sends it again.
+#[post("/orgs/<id>/invitations", data = "<data>")]
+async fn orgs_invite(
+    _limit: RateLimited,
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    notifiers: State<'_, Arc<Notifiers>>,
+    data: Json<InvitationNew>,
+) -> MyRes<OrgInvitation, OrgError> {
+    let id: Uuid = id.into_inner();
+    bail!(fail!(require_owner(&db, id, user.0).await), |e| e);
+    let email = bail!(normalize_email(&data.email).ok_or(()), |_| {
+        OrgError::InvalidEmail
+    });
+    let res = sqlx::query!(
+        r#"
+        SELECT 1 as one
+        FROM org_members m
+        JOIN users ON users.id = m.userid
+        WHERE m.org_id = $1 AND lower(users.email) = $2
+        "#,
+        id,
+        email
+    )
+    .fetch_optional(&*db)
+    .await;
+    if fail!(res).is_some() {
+        return MyRes::Err(OrgError::AlreadyMember);
+    }
+
+    // The org is locked so that invitations sent at once can't all slip
+    // under the cap.
+    let mut tx = fail!(db.begin().await);
+    let res = sqlx::query!("SELECT id FROM orgs WHERE id = $1 FOR UPDATE", id)
+        .fetch_optional(&mut tx)
+        .await;
+    fail!(res);
+    let res = sqlx::query!(
+        r#"
+        SELECT COUNT(*) as "sent!" FROM org_invitations_sent
+        WHERE org_id = $1 AND created_at > NOW() - INTERVAL '1 day'
+        "#,
+        id
+    )
+    .fetch_one(&mut tx)
+    .await;
+    if fail!(res).sent >= MAX_INVITATIONS_PER_DAY {
+        return MyRes::Err(OrgError::TooManyInvitations);
+    }
+
+    let res = sqlx::query_as!(
+        OrgInvitation,
+        r#"
+        INSERT INTO org_invitations(org_id, email, role, invited_by)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (org_id, email) DO UPDATE SET
+            role = EXCLUDED.role,
+            invited_by = EXCLUDED.invited_by,
+            created_at = NOW()
+        RETURNING id, email, role as "role: _", created_at
+        "#,
+        id,
+        email,
+        data.role: _,
+        user.0
+    )
+    .fetch_one(&mut tx)
+    .await;
+    let invitation = fail!(res);
+    let res = sqlx::query!(
+        r#"
+        DELETE FROM org_invitations_sent
+        WHERE org_id = $1 AND created_at < NOW() - INTERVAL '1 day'
+        "#,
+        id
+    )
+    .execute(&mut tx)
+    .await;
+    fail!(res);
+    let res = sqlx::query!(
+        "INSERT INTO org_invitations_sent(org_id, email) VALUES ($1, $2)",
+        id,
+        email
+    )
+    .execute(&mut tx)
+    .await;
+    fail!(res);
+    fail!(tx.commit().await);
+
+    let res = sqlx::query!(
+        r#"
+        SELECT orgs.name as org_name, users.name as inviter_name
+        FROM orgs, users
+        WHERE orgs.id = $1 AND users.id = $2
+        "#,
+        id,
+        user.0
+    )
+    .fetch_one(&*db)
+    .await;
+    let names = fail!(res);
+    let notification = Notification {
+        title: format!("{} invited you to {}", names.inviter_name, names.org_name),
+        body: format!(
+            "Sign in with this email address to accept, and post for {} together with the rest of your team.",
+            names.org_name
+        ),
+        url: notify::app_url("/me"),
+    };
+    spawn_send_invitation(&notifiers, invitation.email.clone(), notification);
+
+    MyRes::Ok(invitation)
+}
+
+#[delete("/orgs/<id>/invitations/<invitation_id>")]
+async fn orgs_invitation_delete(
+    id: rocket_contrib::uuid::Uuid,
+    invitation_id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<(), OrgError> {
+    let id: Uuid = id.into_inner();
+    bail!(fail!(require_owner(&db, id, user.0).await), |e| e);
+    let res = sqlx::query!(
+        "DELETE FROM org_invitations WHERE id = $1 AND org_id = $2",
+        invitation_id.into_inner(),
+        id
+    )
+    .execute(&*db)
+    .await;
+
+    let res = fail!(res);
+    if res.rows_affected() == 0 {
+        return MyRes::Err(OrgError::NotFound);
+    }
+    MyRes::Ok(())
+}
+
+#[put("/orgs/<id>/members/<member>", data = "<data>")]
+async fn orgs_member_update(
+    id: rocket_contrib::uuid::Uuid,
+    member: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<MemberUpdate>,
+) -> MyRes<(), OrgError> {
+    let id: Uuid = id.into_inner();
+    let member: Uuid = member.into_inner();
+    bail!(fail!(require_owner(&db, id, user.0).await), |e| e);
+
+    let mut tx = fail!(db.begin().await);
+    let current = fail!(locked_member(&mut tx, id, member).await);
+    let (_, only_owner) = bail!(current.ok_or(()), |_| OrgError::NotFound);
+    if only_owner && data.role != OrgRole::Owner {
+        return MyRes::Err(OrgError::LastOwner);
+    }
+    let res = sqlx::query!(
+        "UPDATE org_members SET role = $3 WHERE org_id = $1 AND userid = $2",
+        id,
+        member,
+        data.role: _
+    )
+    .execute(&mut tx)
+    .await;
+    fail!(res);
+    fail!(tx.commit().await);
+
+    MyRes::Ok(())
+}
+
+// Owners can remove anyone, and everyone can leave.
+#[delete("/orgs/<id>/members/<member>")]
+async fn orgs_member_remove(
+    id: rocket_contrib::uuid::Uuid,
+    member: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<(), OrgError> {
+    let id: Uuid = id.into_inner();
+    let member: Uuid = member.into_inner();
+    if member != user.0 {
+        bail!(fail!(require_owner(&db, id, user.0).await), |e| e);
+    }
+
+    let mut tx = fail!(db.begin().await);
+    let current = fail!(locked_member(&mut tx, id, member).await);
+    let (_, only_owner) = bail!(current.ok_or(()), |_| OrgError::NotFound);
+    if only_owner {
+        return MyRes::Err(OrgError::LastOwner);
+    }
+    let res = sqlx::query!(
+        "DELETE FROM org_members WHERE org_id = $1 AND userid = $2",
+        id,
+        member
+    )
+    .execute(&mut tx)
+    .await;
+    fail!(res);
+    fail!(tx.commit().await);
+
+    MyRes::Ok(())
+}
+
+// Invitations to the email address the user signed in with.
+#[get("/org_invitations")]
+async fn invitations_mine(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<InvitationReceived>, OrgError> {
+    let res = sqlx::query_as!(
+        InvitationReceived,
+        r#"
+        SELECT i.id,
+               i.org_id,
+               orgs.name as org_name,
+               i.role as "role: _",
+               inviter.name as "invited_by_name?",
+               i.created_at
+        FROM org_invitations i
+        JOIN orgs ON orgs.id = i.org_id
+        JOIN users me ON me.id = $1
+        LEFT JOIN users inviter ON inviter.id = i.invited_by
+        WHERE i.email = lower(me.email)
+        ORDER BY i.created_at DESC
+        "#,
+        user.0
+    )
+    .fetch_all(&*db)
+    .await;
+    let invitations = fail!(res);
+
+    MyRes::Ok(invitations)
+}
+
+// Either way the invitation is used up.
+#[post("/org_invitations/<id>", data = "<data>")]
+async fn invitations_respond(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<InvitationResponse>,
+) -> MyRes<(), OrgError> {
+    let mut tx = fail!(db.begin().await);
+    let res = sqlx::query!(
+        r#"
+        DELETE FROM org_invitations i
+        USING users me
+        WHERE i.id = $1 AND me.id = $2 AND i.email = lower(me.email)
+        RETURNING i.org_id, i.role as "role: OrgRole"
+        "#,
+        id.into_inner(),
+        user.0
+    )
+    .fetch_optional(&mut tx)
+    .await;
+    let invitation = fail!(res);
+    let invitation = bail!(invitation.ok_or(()), |_| OrgError::NotFound);
+    if data.accept {
+        let res = sqlx::query!(
+            r#"
+            INSERT INTO org_members(org_id, userid, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (org_id, userid) DO NOTHING
+            "#,
+            invitation.org_id,
+            user.0,
+            invitation.role: _
+        )
+        .execute(&mut tx)
+        .await;
+        fail!(res);
+    }
+    fail!(tx.commit().await);
+
+    MyRes::Ok(())
+}
+
+// Invitations go out by email, since the person invited may not have
+// signed in yet.
+fn spawn_send_invitation(notifiers: &Arc<Notifiers>, email: String, notification: Notification) {
+    let notifiers = notifiers.clone();
+    tokio::spawn(async move {
+        let notifier = match notifiers.get(NotifyChannel::Email) {
+            Some(notifier) => notifier,
+            None => return,
+        };
+        // There may be no user yet to go with the address.
+        let recipient = Recipient {
+            userid: Uuid::nil(),
+            email,
+            webhook_url: None,
+        };
+        if let Err(e) = notifier.notify(&recipient, &notification).await {
+            log_background_error("org_invitation", &e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_email() {
+        assert_eq!(
+            normalize_email(" Admin@Hospital.org ").as_deref(),
+            Some("admin@hospital.org")
+        );
+        assert_eq!(normalize_email("admin"), None);
+        assert_eq!(normalize_email("@hospital.org"), None);
+        assert_eq!(normalize_email("admin@localhost"), None);
+        assert_eq!(normalize_email("admin@hospital.org."), None);
+        assert_eq!(normalize_email("a@b@hospital.org"), None);
+        assert_eq!(normalize_email("ad min@hospital.org"), None);
+    }
+
+    #[test]
+    fn test_normalize_name() {
+        assert_eq!(normalize_name("  City Hospital "), Some("City Hospital"));
+        assert_eq!(normalize_name("   "), None);
+        assert_eq!(normalize_name(&"x".repeat(MAX_NAME_LEN + 1)), None);
+    }
+
+    #[test]
+    fn test_can_edit_posts() {
+        assert!(OrgRole::Owner.can_edit_posts());
+        assert!(OrgRole::Editor.can_edit_posts());
+        assert!(!OrgRole::Viewer.can_edit_posts());
+    }
+}
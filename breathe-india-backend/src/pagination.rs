@@ -0,0 +1,81 @@
+// Keyset pagination over (timestamp, id). Unlike OFFSET, a page doesn't
+// shift when posts above it are updated, and deep pages are as fast as the
+// first one.
+
+use chrono::{DateTime, TimeZone, Utc};
+use uuid::Uuid;
+
+// Counting stops here, since an exact count of a large result is slow and
+// nobody reads past the first few pages anyway.
+pub const COUNT_CAP: i64 = 1000;
+
+#[derive(Debug, PartialEq)]
+pub struct Cursor {
+    // The timestamp the listing is sorted by.
+    pub at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    // Opaque to clients. Postgres timestamps have microsecond precision, so
+    // nothing is lost.
+    pub fn encode(&self) -> String {
+        let micros = self.at.timestamp() * 1_000_000 + self.at.timestamp_subsec_micros() as i64;
+        let raw = format!("{}.{}", micros, self.id);
+        base64::encode_config(raw, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let raw = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+        let raw = String::from_utf8(raw).ok()?;
+        let (micros, id) = raw.split_once('.')?;
+        let micros: i64 = micros.parse().ok()?;
+        let at = Utc
+            .timestamp_opt(
+                micros.div_euclid(1_000_000),
+                (micros.rem_euclid(1_000_000) * 1000) as u32,
+            )
+            .single()?;
+        let id = Uuid::parse_str(id).ok()?;
+        Some(Cursor { at, id })
+    }
+}
+
+#[derive(Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub has_more: bool,
+    pub next_cursor: Option<String>,
+    // Only when asked for, and at most COUNT_CAP.
+    pub approx_total: Option<i64>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        let cursors = &[
+            Cursor {
+                at: Utc.timestamp_opt(1620000000, 123456000).unwrap(),
+                id: Uuid::new_v4(),
+            },
+            Cursor {
+                at: Utc.timestamp_opt(-1, 999999000).unwrap(),
+                id: Uuid::nil(),
+            },
+        ];
+        for cursor in cursors {
+            assert_eq!(Cursor::decode(&cursor.encode()).as_ref(), Some(cursor));
+        }
+    }
+
+    #[test]
+    fn test_cursor_decode_invalid() {
+        let invalid = &["", "not base64!", "MTIz", "YWJjLmRlZg"];
+        for cursor in invalid {
+            assert_eq!(Cursor::decode(cursor), None);
+        }
+    }
+}
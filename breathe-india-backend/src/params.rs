@@ -0,0 +1,227 @@
+// Query parameters are taken as plain strings and checked here, so that bad
+// values get a 400 saying what was wrong, instead of silently falling back
+// to a default or never reaching a route at all.
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::patient::Urgency;
+use crate::post_query::PostSort;
+use crate::trust;
+use crate::{PostStatus, PostType};
+
+pub const DEFAULT_PAGE_SIZE: i64 = 20;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct InvalidParam {
+    pub param: &'static str,
+    pub reason: &'static str,
+}
+
+impl InvalidParam {
+    pub fn new(param: &'static str, reason: &'static str) -> Self {
+        InvalidParam { param, reason }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PageParams {
+    pub start: i64,
+    pub n: i64,
+}
+
+impl PageParams {
+    pub fn parse(start: Option<&str>, n: Option<&str>) -> Result<Self, InvalidParam> {
+        let start = match start {
+            None => 0,
+            Some(start) => match start.trim().parse() {
+                Ok(start) if start >= 0 => start,
+                Ok(_) => return Err(InvalidParam::new("start", "must not be negative")),
+                Err(_) => return Err(InvalidParam::new("start", "must be an integer")),
+            },
+        };
+        let n = match n {
+            None => DEFAULT_PAGE_SIZE,
+            Some(n) => match n.trim().parse() {
+                Ok(n) if (1..=MAX_PAGE_SIZE).contains(&n) => n,
+                Ok(_) => return Err(InvalidParam::new("n", "must be between 1 and 100")),
+                Err(_) => return Err(InvalidParam::new("n", "must be an integer")),
+            },
+        };
+        Ok(PageParams { start, n })
+    }
+}
+
+pub fn parse_post_type(typ: Option<&str>) -> Result<PostType, InvalidParam> {
+    match typ.map(|t| t.trim().to_lowercase()).as_deref() {
+        Some("needs") => Ok(PostType::Needs),
+        Some("supplies") => Ok(PostType::Supplies),
+        Some(_) => Err(InvalidParam::new("typ", "must be needs or supplies")),
+        None => Err(InvalidParam::new("typ", "is required")),
+    }
+}
+
+// Repeated parameters, like `typ=needs&typ=supplies`.
+pub fn parse_post_types(types: &[String]) -> Result<Vec<PostType>, InvalidParam> {
+    if types.is_empty() {
+        return Err(InvalidParam::new("typ", "is required"));
+    }
+    types.iter().map(|t| parse_post_type(Some(t))).collect()
+}
+
+pub fn parse_statuses(statuses: &[String]) -> Result<Vec<PostStatus>, InvalidParam> {
+    statuses
+        .iter()
+        .map(|s| match s.trim().to_lowercase().as_str() {
+            "open" => Ok(PostStatus::Open),
+            "partially_fulfilled" => Ok(PostStatus::PartiallyFulfilled),
+            "fulfilled" => Ok(PostStatus::Fulfilled),
+            "closed" => Ok(PostStatus::Closed),
+            _ => Err(InvalidParam::new(
+                "status",
+                "must be open, partially_fulfilled, fulfilled or closed",
+            )),
+        })
+        .collect()
+}
+
+pub fn parse_urgencies(urgencies: &[String]) -> Result<Vec<Urgency>, InvalidParam> {
+    urgencies
+        .iter()
+        .map(|u| match u.trim().to_lowercase().as_str() {
+            "critical" => Ok(Urgency::Critical),
+            "high" => Ok(Urgency::High),
+            "normal" => Ok(Urgency::Normal),
+            _ => Err(InvalidParam::new(
+                "urgency",
+                "must be critical, high or normal",
+            )),
+        })
+        .collect()
+}
+
+pub fn parse_sort(sort: Option<&str>) -> Result<PostSort, InvalidParam> {
+    match sort.map(|s| s.trim().to_lowercase()).as_deref() {
+        None | Some("bumped") => Ok(PostSort::Bumped),
+        Some("updated") => Ok(PostSort::Updated),
+        Some("created") => Ok(PostSort::Created),
+        Some("distance") => Ok(PostSort::Distance),
+        Some("confirmed") => Ok(PostSort::Confirmed),
+        Some("urgency") => Ok(PostSort::Urgency),
+        Some("trust") => Ok(PostSort::Trust),
+        Some(_) => Err(InvalidParam::new(
+            "sort",
+            "must be bumped, updated, created, distance, confirmed, urgency or trust",
+        )),
+    }
+}
+
+pub fn parse_flag(param: &'static str, value: Option<&str>) -> Result<bool, InvalidParam> {
+    match value.map(|v| v.trim().to_lowercase()).as_deref() {
+        None | Some("false") | Some("0") => Ok(false),
+        // A bare `?verified_only` means yes.
+        Some("") | Some("true") | Some("1") => Ok(true),
+        Some(_) => Err(InvalidParam::new(param, "must be true or false")),
+    }
+}
+
+pub fn parse_trust(param: &'static str, value: Option<&str>) -> Result<Option<i16>, InvalidParam> {
+    value
+        .map(|v| match v.trim().parse() {
+            Ok(score) if (0..=trust::MAX_SCORE).contains(&score) => Ok(score),
+            _ => Err(InvalidParam::new(param, "must be between 0 and 100")),
+        })
+        .transpose()
+}
+
+pub fn parse_uuid(param: &'static str, value: Option<&str>) -> Result<Option<Uuid>, InvalidParam> {
+    value
+        .map(|v| Uuid::parse_str(v.trim()).map_err(|_| InvalidParam::new(param, "must be a UUID")))
+        .transpose()
+}
+
+pub fn parse_timestamp(
+    param: &'static str,
+    value: Option<&str>,
+) -> Result<Option<DateTime<Utc>>, InvalidParam> {
+    value
+        .map(|v| {
+            DateTime::parse_from_rfc3339(v.trim())
+                .map(|t| t.with_timezone(&Utc))
+                .map_err(|_| InvalidParam::new(param, "must be an RFC 3339 timestamp"))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_page_params() {
+        let ok = |start, n| Ok(PageParams { start, n });
+        assert_eq!(PageParams::parse(None, None), ok(0, DEFAULT_PAGE_SIZE));
+        assert_eq!(PageParams::parse(Some("40"), Some("100")), ok(40, 100));
+        let cases = &[
+            (Some("-1"), None, "start"),
+            (Some("abc"), None, "start"),
+            (None, Some("0"), "n"),
+            (None, Some("10000000"), "n"),
+            (None, Some("1.5"), "n"),
+        ];
+        for (start, n, param) in cases {
+            let err = PageParams::parse(*start, *n).unwrap_err();
+            assert_eq!(err.param, *param);
+        }
+    }
+
+    #[test]
+    fn test_parse_post_type() {
+        assert!(matches!(
+            parse_post_type(Some("Needs")),
+            Ok(PostType::Needs)
+        ));
+        assert!(matches!(
+            parse_post_type(Some("supplies")),
+            Ok(PostType::Supplies)
+        ));
+        for typ in &[Some("offers"), None] {
+            let res = parse_post_type(*typ);
+            assert!(matches!(res, Err(InvalidParam { param: "typ", .. })));
+        }
+        let types = parse_post_types(&["needs".to_owned(), "Supplies".to_owned()]);
+        assert!(matches!(
+            types.as_deref(),
+            Ok([PostType::Needs, PostType::Supplies])
+        ));
+        assert!(parse_post_types(&[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_filters() {
+        assert_eq!(parse_sort(None), Ok(PostSort::Bumped));
+        assert_eq!(parse_sort(Some("updated")), Ok(PostSort::Updated));
+        assert_eq!(parse_sort(Some("Distance")), Ok(PostSort::Distance));
+        assert_eq!(parse_sort(Some("urgency")), Ok(PostSort::Urgency));
+        assert_eq!(parse_sort(Some("trust")), Ok(PostSort::Trust));
+        assert!(parse_sort(Some("random")).is_err());
+        let urgencies = parse_urgencies(&["Critical".to_owned(), "high".to_owned()]);
+        assert_eq!(urgencies, Ok(vec![Urgency::Critical, Urgency::High]));
+        assert!(parse_urgencies(&["urgent".to_owned()]).is_err());
+        assert_eq!(parse_flag("verified_only", Some("")), Ok(true));
+        assert_eq!(parse_flag("verified_only", None), Ok(false));
+        assert!(parse_flag("verified_only", Some("maybe")).is_err());
+        assert_eq!(parse_trust("min_trust", Some("60")), Ok(Some(60)));
+        assert_eq!(parse_trust("min_trust", None), Ok(None));
+        assert!(parse_trust("min_trust", Some("101")).is_err());
+        assert_eq!(parse_uuid("author", None), Ok(None));
+        assert!(parse_uuid("author", Some("me")).is_err());
+        let since = parse_timestamp("updated_since", Some("2021-05-01T10:00:00+05:30"));
+        assert_eq!(
+            since.unwrap().unwrap().to_rfc3339(),
+            "2021-05-01T04:30:00+00:00"
+        );
+        assert!(parse_timestamp("updated_since", Some("yesterday")).is_err());
+    }
+}
@@ -0,0 +1,23 @@
+// Details a needs post can carry about the patient, so that helpers can
+// tell a critical case from a routine request.
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum Urgency {
+    Critical,
+    High,
+    Normal,
+}
+
+// The brackets used by the vaccination drive, which people already know.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, sqlx::Type)]
+pub enum AgeBracket {
+    #[sqlx(rename = "under_18")]
+    Under18,
+    #[sqlx(rename = "18_44")]
+    From18To44,
+    #[sqlx(rename = "45_59")]
+    From45To59,
+    #[sqlx(rename = "60_plus")]
+    From60,
+}
@@ -0,0 +1,405 @@
+// Finds personal data people type into free text: phone numbers, Aadhaar
+// numbers, emails and PANs. Depending on the policy, each kind is either
+// masked before the text is stored, or stored as is with a warning in the
+// response.
+
+use anyhow::{anyhow, Result};
+
+use crate::contact;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum PiiKind {
+    Phone,
+    Aadhaar,
+    Email,
+    Pan,
+}
+
+impl PiiKind {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "phone" => Some(PiiKind::Phone),
+            "aadhaar" => Some(PiiKind::Aadhaar),
+            "email" => Some(PiiKind::Email),
+            "pan" => Some(PiiKind::Pan),
+            _ => None,
+        }
+    }
+
+    fn mask(&self) -> &'static str {
+        match self {
+            PiiKind::Phone => "[phone hidden]",
+            PiiKind::Aadhaar => "[Aadhaar hidden]",
+            PiiKind::Email => "[email hidden]",
+            PiiKind::Pan => "[PAN hidden]",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum PiiAction {
+    Warn,
+    Mask,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PiiPolicy {
+    phone: PiiAction,
+    aadhaar: PiiAction,
+    email: PiiAction,
+    pan: PiiAction,
+}
+
+impl Default for PiiPolicy {
+    fn default() -> Self {
+        PiiPolicy {
+            phone: PiiAction::Mask,
+            aadhaar: PiiAction::Mask,
+            email: PiiAction::Mask,
+            pan: PiiAction::Mask,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PiiFinding {
+    pub kind: PiiKind,
+    pub action: PiiAction,
+}
+
+impl PiiPolicy {
+    // PII_POLICY overrides the default of masking everything, kind by kind,
+    // e.g. "phone=warn,email=warn".
+    pub fn from_env() -> Result<Self> {
+        match std::env::var("PII_POLICY") {
+            Ok(policy) => Self::parse(&policy).ok_or_else(|| anyhow!("Invalid PII_POLICY")),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    fn parse(policy: &str) -> Option<Self> {
+        let mut parsed = Self::default();
+        for rule in policy.split(',').filter(|r| !r.trim().is_empty()) {
+            let (kind, action) = rule.split_once('=')?;
+            let action = match action.trim().to_lowercase().as_str() {
+                "warn" => PiiAction::Warn,
+                "mask" => PiiAction::Mask,
+                _ => return None,
+            };
+            *parsed.action_mut(PiiKind::parse(kind)?) = action;
+        }
+        Some(parsed)
+    }
+
+    fn action(&self, kind: PiiKind) -> PiiAction {
+        match kind {
+            PiiKind::Phone => self.phone,
+            PiiKind::Aadhaar => self.aadhaar,
+            PiiKind::Email => self.email,
+            PiiKind::Pan => self.pan,
+        }
+    }
+
+    fn action_mut(&mut self, kind: PiiKind) -> &mut PiiAction {
+        match kind {
+            PiiKind::Phone => &mut self.phone,
+            PiiKind::Aadhaar => &mut self.aadhaar,
+            PiiKind::Email => &mut self.email,
+            PiiKind::Pan => &mut self.pan,
+        }
+    }
+
+    // Returns `text` with the kinds to mask masked, and what was found.
+    pub fn apply(&self, text: &str) -> (String, Vec<PiiFinding>) {
+        let mut out = String::with_capacity(text.len());
+        let mut findings = vec![];
+        let mut last = 0;
+        for m in scan(text) {
+            let action = self.action(m.kind);
+            if action == PiiAction::Mask {
+                out.push_str(&text[last..m.start]);
+                out.push_str(m.kind.mask());
+                last = m.end;
+            }
+            findings.push(PiiFinding {
+                kind: m.kind,
+                action,
+            });
+        }
+        out.push_str(&text[last..]);
+        (out, findings)
+    }
+}
+
+// The phone numbers in `text`, normalized like contact details.
+pub fn phones(text: &str) -> Vec<String> {
+    scan(text)
+        .into_iter()
+        .filter(|m| m.kind == PiiKind::Phone)
+        .filter_map(|m| contact::normalize_phone(&text[m.start..m.end]))
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+struct Match {
+    kind: PiiKind,
+    // Byte offsets. Everything matched is ASCII, so these are always on char
+    // boundaries.
+    start: usize,
+    end: usize,
+}
+
+// Matches in order, without overlaps.
+fn scan(text: &str) -> Vec<Match> {
+    let mut matches = scan_emails(text);
+    matches.extend(scan_pans(text));
+    matches.extend(scan_numbers(text));
+    matches.sort_by_key(|m| (m.start, std::cmp::Reverse(m.end)));
+    let mut end = 0;
+    matches.retain(|m| {
+        let keep = m.start >= end;
+        if keep {
+            end = m.end;
+        }
+        keep
+    });
+    matches
+}
+
+fn is_word(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b >= 0x80
+}
+
+fn scan_emails(text: &str) -> Vec<Match> {
+    let bytes = text.as_bytes();
+    let is_local = |b: u8| b.is_ascii_alphanumeric() || b"._%+-".contains(&b);
+    let is_domain = |b: u8| b.is_ascii_alphanumeric() || b == b'.' || b == b'-';
+    let mut matches = vec![];
+    for (at, _) in text.match_indices('@') {
+        let mut start = at;
+        while start > 0 && is_local(bytes[start - 1]) {
+            start -= 1;
+        }
+        let mut end = at + 1;
+        while end < bytes.len() && is_domain(bytes[end]) {
+            end += 1;
+        }
+        // A full stop after an email ends the sentence.
+        while end > at + 1 && (bytes[end - 1] == b'.' || bytes[end - 1] == b'-') {
+            end -= 1;
+        }
+        if contact::is_email(&text[start..end]) {
+            matches.push(Match {
+                kind: PiiKind::Email,
+                start,
+                end,
+            });
+        }
+    }
+    matches
+}
+
+// Five letters, four digits and a letter, where the fourth letter is the
+// kind of holder.
+fn is_pan(word: &[u8]) -> bool {
+    word.len() == 10
+        && word[..5].iter().all(u8::is_ascii_alphabetic)
+        && b"ABCFGHJLPT".contains(&word[3].to_ascii_uppercase())
+        && word[5..9].iter().all(u8::is_ascii_digit)
+        && word[9].is_ascii_alphabetic()
+}
+
+fn scan_pans(text: &str) -> Vec<Match> {
+    let bytes = text.as_bytes();
+    let mut matches = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if !is_word(bytes[i]) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && is_word(bytes[i]) {
+            i += 1;
+        }
+        if is_pan(&bytes[start..i]) {
+            matches.push(Match {
+                kind: PiiKind::Pan,
+                start,
+                end: i,
+            });
+        }
+    }
+    matches
+}
+
+fn classify_number(number: &str) -> Option<PiiKind> {
+    let digits: String = number.chars().filter(char::is_ascii_digit).collect();
+    // Without a prefix, only mobile numbers, since plain ten digit numbers
+    // are often something else.
+    if let Some(phone) = contact::normalize_phone(number) {
+        if digits.len() > 10 || phone.starts_with(|c: char| ('6'..='9').contains(&c)) {
+            return Some(PiiKind::Phone);
+        }
+    }
+    // Aadhaar numbers never start with 0 or 1.
+    if digits.len() == 12 && !digits.starts_with(['0', '1']) {
+        return Some(PiiKind::Aadhaar);
+    }
+    None
+}
+
+// Numbers are written in groups, like "98765 43210" or "1234-5678-9012".
+// Runs of groups are split into the longest numbers that look like PII.
+fn scan_numbers(text: &str) -> Vec<Match> {
+    let bytes = text.as_bytes();
+    let mut matches = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        // Groups of digits separated by single spaces or dashes, the first
+        // possibly with a '+'.
+        let mut groups: Vec<(usize, usize)> = vec![];
+        let at_boundary = i == 0 || !is_word(bytes[i - 1]);
+        let starts_group = bytes[i].is_ascii_digit()
+            || (bytes[i] == b'+' && matches!(bytes.get(i + 1), Some(b) if b.is_ascii_digit()));
+        if !at_boundary || !starts_group {
+            i += 1;
+            continue;
+        }
+        let mut j = i;
+        loop {
+            let start = j;
+            if bytes[j] == b'+' {
+                j += 1;
+            }
+            while j < bytes.len() && bytes[j].is_ascii_digit() {
+                j += 1;
+            }
+            if j < bytes.len() && is_word(bytes[j]) {
+                // Part of a word, like "2nd" or "A123".
+                break;
+            }
+            groups.push((start, j));
+            let separated = j + 1 < bytes.len()
+                && (bytes[j] == b' ' || bytes[j] == b'-')
+                && bytes[j + 1].is_ascii_digit();
+            if !separated {
+                break;
+            }
+            j += 1;
+        }
+        if groups.is_empty() {
+            i = j.max(i + 1);
+            continue;
+        }
+
+        let mut g = 0;
+        while g < groups.len() {
+            let found = (g..groups.len()).rev().find_map(|k| {
+                let (start, end) = (groups[g].0, groups[k].1);
+                classify_number(&text[start..end]).map(|kind| Match { kind, start, end })
+            });
+            match found {
+                Some(m) => {
+                    let end = m.end;
+                    matches.push(m);
+                    g = groups
+                        .iter()
+                        .position(|&(s, _)| s > end)
+                        .unwrap_or(groups.len());
+                }
+                None => g += 1,
+            }
+        }
+        i = groups.last().map(|&(_, end)| end).unwrap_or(j).max(i + 1);
+    }
+    matches
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds(text: &str) -> Vec<(PiiKind, &str)> {
+        scan(text)
+            .into_iter()
+            .map(|m| (m.kind, &text[m.start..m.end]))
+            .collect()
+    }
+
+    #[test]
+    fn test_scan() {
+        use PiiKind::*;
+        assert_eq!(
+            kinds("Call 98765 43210 or +91-9123456789, or mail ravi.k@example.co.in."),
+            vec![
+                (Phone, "98765 43210"),
+                (Phone, "+91-9123456789"),
+                (Email, "ravi.k@example.co.in"),
+            ]
+        );
+        assert_eq!(
+            kinds("Aadhaar 2345 6789 0123, PAN abcpe1234f"),
+            vec![(Aadhaar, "2345 6789 0123"), (Pan, "abcpe1234f")]
+        );
+        assert_eq!(
+            kinds("Landline 0484 2345678 or 9876543210 2 cylinders"),
+            vec![(Phone, "0484 2345678"), (Phone, "9876543210")]
+        );
+    }
+
+    #[test]
+    fn test_scan_ignores() {
+        let texts = &[
+            "Need 2 cylinders of 10L, pin 682001",
+            "Order 1234567890, ward 12",
+            "Token 123456789012",
+            "Room A9876543210",
+            "ABCDE1234F is not a PAN",
+            "email me @ the hospital",
+        ];
+        for text in texts {
+            assert_eq!(kinds(text), vec![], "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_phones() {
+        assert_eq!(
+            phones("Call 98765 43210 or +91-9123456789, Aadhaar 2345 6789 0123"),
+            vec!["9876543210", "9123456789"]
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let policy = PiiPolicy::parse("email=warn").unwrap();
+        let (text, findings) = policy.apply("Call 9876543210 or ravi@example.com");
+        assert_eq!(text, "Call [phone hidden] or ravi@example.com");
+        assert_eq!(
+            findings,
+            vec![
+                PiiFinding {
+                    kind: PiiKind::Phone,
+                    action: PiiAction::Mask
+                },
+                PiiFinding {
+                    kind: PiiKind::Email,
+                    action: PiiAction::Warn
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(PiiPolicy::parse(""), Some(PiiPolicy::default()));
+        let policy = PiiPolicy::parse("phone=warn, PAN=Warn").unwrap();
+        assert_eq!(policy.action(PiiKind::Phone), PiiAction::Warn);
+        assert_eq!(policy.action(PiiKind::Pan), PiiAction::Warn);
+        assert_eq!(policy.action(PiiKind::Aadhaar), PiiAction::Mask);
+        assert_eq!(PiiPolicy::parse("phone"), None);
+        assert_eq!(PiiPolicy::parse("phone=hide"), None);
+        assert_eq!(PiiPolicy::parse("name=warn"), None);
+    }
+}
@@ -0,0 +1,582 @@
+// Pledges against needs posts, so that donors answering the same need can
+// see how much of it is already covered. Each pledge counts in whatever
+// unit the need's quantity does. Pledges bring down the need's remaining
+// figure straight away, but the need only moves to partially fulfilled or
+// fulfilled as pledges are delivered, since a pledge may never arrive.
+
+use chrono::{DateTime, Utc};
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::content_filter::{self, FilterAction};
+use crate::matching::parse_quantity;
+use crate::myres::{HasStatusCode, MyRes};
+use crate::notify::{Notifiers, PostActivity};
+use crate::{bail, fail, orgs, spawn_notify_post_author, LoggedInUser, PostStatus, PostType};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        posts_pledges,
+        posts_pledges_create,
+        pledges_state_update,
+        pledges_mine,
+    ]
+}
+
+const MAX_NOTE_LEN: usize = 500;
+// Far more than any one donor gives. Keeps the totals from overflowing for
+// needs whose quantity isn't a number, which nothing else caps.
+const MAX_QUANTITY: i32 = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum PledgeState {
+    Pledged,
+    Delivered,
+    Cancelled,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Pledge {
+    id: Uuid,
+    needs_id: Uuid,
+    // The need's item, for listing someone's pledges.
+    item: String,
+    supplies_id: Option<Uuid>,
+    userid: Uuid,
+    pledger_name: String,
+    quantity: i32,
+    eta: Option<DateTime<Utc>>,
+    note: String,
+    state: PledgeState,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct PledgeSummary {
+    // What the need asks for, if its quantity is a number.
+    amount: Option<i32>,
+    pledged: i64,
+    delivered: i64,
+    remaining: Option<i32>,
+    pledges: Vec<Pledge>,
+}
+
+#[derive(Deserialize)]
+pub struct PledgeNew {
+    quantity: i32,
+    eta: Option<DateTime<Utc>>,
+    #[serde(default)]
+    note: String,
+    // One of the pledger's supplies posts the pledge comes out of.
+    supplies_id: Option<Uuid>,
+}
+
+#[derive(Deserialize)]
+pub struct PledgeStateUpdate {
+    state: PledgeState,
+}
+
+#[derive(Serialize)]
+enum PledgeError {
+    NotFound,
+    NotNeeds,
+    NotOpen,
+    OwnPost,
+    // Not positive, or more than the need asks for.
+    InvalidQuantity,
+    EtaInPast,
+    TooLong,
+    // Matched a content filter rule that holds or turns away posts.
+    Rejected,
+    SuppliesNotFound,
+    // Someone can only have one pledge waiting per need.
+    AlreadyPledged,
+    // Delivered and cancelled pledges are done with.
+    NotPledged,
+    NotAllowed,
+}
+
+impl HasStatusCode for PledgeError {
+    fn get_status(&self) -> Status {
+        match self {
+            PledgeError::NotFound => Status::NotFound,
+            PledgeError::NotNeeds => Status::BadRequest,
+            PledgeError::NotOpen => Status::BadRequest,
+            PledgeError::OwnPost => Status::BadRequest,
+            PledgeError::InvalidQuantity => Status::BadRequest,
+            PledgeError::EtaInPast => Status::BadRequest,
+            PledgeError::TooLong => Status::BadRequest,
+            PledgeError::Rejected => Status::BadRequest,
+            PledgeError::SuppliesNotFound => Status::BadRequest,
+            PledgeError::AlreadyPledged => Status::Conflict,
+            PledgeError::NotPledged => Status::Conflict,
+            PledgeError::NotAllowed => Status::Forbidden,
+        }
+    }
+}
+
+// What a need asks for, rounded up to whole units. New needs start out
+// with all of it remaining.
+pub fn amount(post_type: PostType, quantity: &str) -> Option<i32> {
+    match post_type {
+        PostType::Needs => parse_amount(quantity),
+        PostType::Supplies => None,
+    }
+}
+
+fn parse_amount(quantity: &str) -> Option<i32> {
+    parse_quantity(quantity)
+        .filter(|amount| *amount > 0.0)
+        .map(|amount| amount.ceil() as i32)
+}
+
+#[derive(Debug)]
+struct Coverage {
+    amount: Option<i32>,
+    // Promised but not delivered yet.
+    pledged: i64,
+    delivered: i64,
+}
+
+impl Coverage {
+    fn remaining(&self) -> Option<i32> {
+        let amount = self.amount?;
+        let remaining = (i64::from(amount) - self.pledged - self.delivered).max(0);
+        // Never more than the amount, so it fits.
+        Some(remaining as i32)
+    }
+
+    // Needs whose quantity isn't a number never count as fulfilled, as
+    // nobody can tell when they are.
+    fn status(&self) -> PostStatus {
+        match self.amount {
+            Some(amount) if self.delivered >= i64::from(amount) => PostStatus::Fulfilled,
+            _ if self.delivered > 0 => PostStatus::PartiallyFulfilled,
+            _ => PostStatus::Open,
+        }
+    }
+}
+
+pub struct Refreshed {
+    pub quantity_remaining: Option<i32>,
+    pub status: PostStatus,
+}
+
+// Works out the post's remaining quantity again, and moves open needs
+// along as deliveries cover them. Posts the author fulfilled or closed
+// themselves keep their status. The post stays locked until the
+// transaction ends, so pledges made meanwhile wait for it.
+pub async fn refresh(
+    tx: &mut Transaction<'_, Postgres>,
+    post_id: Uuid,
+) -> sqlx::Result<Option<Refreshed>> {
+    let post = sqlx::query!(
+        r#"SELECT post_type as "post_type: PostType", status as "status: PostStatus", quantity
+        FROM posts WHERE id = $1 FOR UPDATE"#,
+        post_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let post = match post {
+        Some(post) => post,
+        None => return Ok(None),
+    };
+    let totals = sqlx::query!(
+        r#"
+        SELECT COALESCE(SUM(quantity) FILTER (WHERE state = 'pledged'), 0) as "pledged!",
+               COALESCE(SUM(quantity) FILTER (WHERE state = 'delivered'), 0) as "delivered!"
+        FROM pledges WHERE needs_id = $1
+        "#,
+        post_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let needs = post.post_type == PostType::Needs;
+    let coverage = Coverage {
+        amount: amount(post.post_type, &post.quantity),
+        pledged: totals.pledged,
+        delivered: totals.delivered,
+    };
+    let status = match post.status {
+        PostStatus::Open | PostStatus::PartiallyFulfilled if needs => coverage.status(),
+        status => status,
+    };
+    let quantity_remaining = coverage.remaining();
+    // Left alone when nothing changed, so there is no event for it.
+    sqlx::query!(
+        r#"UPDATE posts SET quantity_remaining = $2, status = $3
+        WHERE id = $1 AND (quantity_remaining IS DISTINCT FROM $2 OR status <> $3)"#,
+        post_id,
+        quantity_remaining,
+        status: _
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(Some(Refreshed {
+        quantity_remaining,
+        status,
+    }))
+}
+
+async fn fetch_pledge(db: &PgPool, id: Uuid) -> sqlx::Result<Option<Pledge>> {
+    sqlx::query_as!(
+        Pledge,
+        r#"
+        SELECT p.id,
+               p.needs_id,
+               posts.item,
+               p.supplies_id,
+               p.userid,
+               users.name as pledger_name,
+               p.quantity,
+               p.eta,
+               p.note,
+               p.state as "state: _",
+               p.created_at,
+               p.updated_at
+        FROM pledges p
+        JOIN posts ON posts.id = p.needs_id
+        JOIN users ON users.id = p.userid
+        WHERE p.id = $1
+        "#,
+        id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+#[get("/posts/<id>/pledges")]
+async fn posts_pledges(
+    id: rocket_contrib::uuid::Uuid,
+    _user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<PledgeSummary, PledgeError> {
+    let id: Uuid = id.into_inner();
+    let res = sqlx::query!(
+        r#"SELECT post_type as "post_type: PostType", quantity FROM posts
+        WHERE id = $1 AND NOT held"#,
+        id
+    )
+    .fetch_optional(&*db)
+    .await;
+    let post = fail!(res);
+    let post = bail!(post.ok_or(()), |_| PledgeError::NotFound);
+    if post.post_type != PostType::Needs {
+        return MyRes::Err(PledgeError::NotNeeds);
+    }
+
+    let res = sqlx::query_as!(
+        Pledge,
+        r#"
+        SELECT p.id,
+               p.needs_id,
+               posts.item,
+               p.supplies_id,
+               p.userid,
+               users.name as pledger_name,
+               p.quantity,
+               p.eta,
+               p.note,
+               p.state as "state: _",
+               p.created_at,
+               p.updated_at
+        FROM pledges p
+        JOIN posts ON posts.id = p.needs_id
+        JOIN users ON users.id = p.userid
+        WHERE p.needs_id = $1
+        ORDER BY p.created_at, p.id
+        "#,
+        id
+    )
+    .fetch_all(&*db)
+    .await;
+    let pledges = fail!(res);
+
+    let sum = |state: PledgeState| -> i64 {
+        pledges
+            .iter()
+            .filter(|p| p.state == state)
+            .map(|p| i64::from(p.quantity))
+            .sum()
+    };
+    let coverage = Coverage {
+        amount: parse_amount(&post.quantity),
+        pledged: sum(PledgeState::Pledged),
+        delivered: sum(PledgeState::Delivered),
+    };
+    MyRes::Ok(PledgeSummary {
+        amount: coverage.amount,
+        pledged: coverage.pledged,
+        delivered: coverage.delivered,
+        remaining: coverage.remaining(),
+        pledges,
+    })
+}
+
+// The need's author gets notified of the pledge.
+#[post("/posts/<id>/pledges", data = "<data>")]
+async fn posts_pledges_create(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    notifiers: State<'_, Arc<Notifiers>>,
+    data: Json<PledgeNew>,
+) -> MyRes<Pledge, PledgeError> {
+    let id: Uuid = id.into_inner();
+    if data.quantity <= 0 || data.quantity > MAX_QUANTITY {
+        return MyRes::Err(PledgeError::InvalidQuantity);
+    }
+    if matches!(data.eta, Some(eta) if eta < Utc::now()) {
+        return MyRes::Err(PledgeError::EtaInPast);
+    }
+    let note = data.note.trim();
+    if note.chars().count() > MAX_NOTE_LEN {
+        return MyRes::Err(PledgeError::TooLong);
+    }
+    if !note.is_empty() {
+        // Like responses, notes can't wait for a moderator.
+        let verdict = fail!(content_filter::evaluate(&db, note).await);
+        fail!(verdict.log(&db, user.0, None).await);
+        if verdict.action() >= Some(FilterAction::Hold) {
+            return MyRes::Err(PledgeError::Rejected);
+        }
+    }
+    if let Some(supplies_id) = data.supplies_id {
+        let res = sqlx::query!(
+            r#"SELECT userid, org_id FROM posts
+            WHERE id = $1 AND post_type = 'supplies' AND NOT held"#,
+            supplies_id
+        )
+        .fetch_optional(&*db)
+        .await;
+        let supplies = fail!(res);
+        let supplies = bail!(supplies.ok_or(()), |_| PledgeError::SuppliesNotFound);
+        let res = orgs::can_edit_post(&db, supplies.userid, supplies.org_id, user.0).await;
+        if !fail!(res) {
+            return MyRes::Err(PledgeError::SuppliesNotFound);
+        }
+    }
+
+    let mut tx = fail!(db.begin().await);
+    let res = sqlx::query!(
+        r#"SELECT userid, post_type as "post_type: PostType", status as "status: PostStatus",
+                  quantity
+        FROM posts WHERE id = $1 AND NOT held FOR UPDATE"#,
+        id
+    )
+    .fetch_optional(&mut tx)
+    .await;
+    let need = fail!(res);
+    let need = bail!(need.ok_or(()), |_| PledgeError::NotFound);
+    if need.post_type != PostType::Needs {
+        return MyRes::Err(PledgeError::NotNeeds);
+    }
+    if need.userid == user.0 {
+        return MyRes::Err(PledgeError::OwnPost);
+    }
+    if !matches!(
+        need.status,
+        PostStatus::Open | PostStatus::PartiallyFulfilled
+    ) {
+        return MyRes::Err(PledgeError::NotOpen);
+    }
+    if matches!(parse_amount(&need.quantity), Some(amount) if data.quantity > amount) {
+        return MyRes::Err(PledgeError::InvalidQuantity);
+    }
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO pledges(needs_id, supplies_id, userid, quantity, eta, note)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (needs_id, userid) WHERE state = 'pledged' DO NOTHING
+        RETURNING id
+        "#,
+        id,
+        data.supplies_id,
+        user.0,
+        data.quantity,
+        data.eta,
+        note
+    )
+    .fetch_optional(&mut tx)
+    .await;
+    let saved = fail!(res);
+    let saved = bail!(saved.ok_or(()), |_| PledgeError::AlreadyPledged);
+    fail!(refresh(&mut tx, id).await);
+    fail!(tx.commit().await);
+
+    let pledge = fail!(fetch_pledge(&db, saved.id).await);
+    let pledge = bail!(pledge.ok_or(()), |_| PledgeError::NotFound);
+    spawn_notify_post_author(
+        &db,
+        &notifiers,
+        id,
+        user.0,
+        PostActivity::Pledged {
+            quantity: pledge.quantity,
+        },
+    );
+    MyRes::Ok(pledge)
+}
+
+// Pledges get delivered or cancelled once. Whoever can edit the need marks
+// them delivered, and either they or whoever pledged can cancel.
+#[post("/pledges/<id>/state", data = "<data>")]
+async fn pledges_state_update(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    notifiers: State<'_, Arc<Notifiers>>,
+    data: Json<PledgeStateUpdate>,
+) -> MyRes<Pledge, PledgeError> {
+    let id: Uuid = id.into_inner();
+    if data.state == PledgeState::Pledged {
+        return MyRes::Err(PledgeError::NotPledged);
+    }
+    let res = sqlx::query!(
+        r#"SELECT pledges.userid, pledges.needs_id, pledges.quantity,
+                  posts.userid as author, posts.org_id
+        FROM pledges JOIN posts ON posts.id = pledges.needs_id
+        WHERE pledges.id = $1"#,
+        id
+    )
+    .fetch_optional(&*db)
+    .await;
+    let current = fail!(res);
+    let current = bail!(current.ok_or(()), |_| PledgeError::NotFound);
+    // Only the need's side can say what arrived, pledgers can just back out.
+    if data.state == PledgeState::Delivered || current.userid != user.0 {
+        let res = orgs::can_edit_post(&db, current.author, current.org_id, user.0).await;
+        if !fail!(res) {
+            return MyRes::Err(PledgeError::NotAllowed);
+        }
+    }
+
+    let mut tx = fail!(db.begin().await);
+    let res = sqlx::query!(
+        "UPDATE pledges SET state = $2, updated_at = NOW() WHERE id = $1 AND state = 'pledged'",
+        id,
+        data.state: _
+    )
+    .execute(&mut tx)
+    .await;
+    if fail!(res).rows_affected() == 0 {
+        return MyRes::Err(PledgeError::NotPledged);
+    }
+    fail!(refresh(&mut tx, current.needs_id).await);
+    fail!(tx.commit().await);
+
+    if data.state == PledgeState::Delivered {
+        spawn_notify_post_author(
+            &db,
+            &notifiers,
+            current.needs_id,
+            user.0,
+            PostActivity::Delivered {
+                quantity: current.quantity,
+            },
+        );
+    }
+    let pledge = fail!(fetch_pledge(&db, id).await);
+    let pledge = bail!(pledge.ok_or(()), |_| PledgeError::NotFound);
+    MyRes::Ok(pledge)
+}
+
+#[get("/my_pledges")]
+async fn pledges_mine(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<Pledge>, PledgeError> {
+    let res = sqlx::query_as!(
+        Pledge,
+        r#"
+        SELECT p.id,
+               p.needs_id,
+               posts.item,
+               p.supplies_id,
+               p.userid,
+               users.name as pledger_name,
+               p.quantity,
+               p.eta,
+               p.note,
+               p.state as "state: _",
+               p.created_at,
+               p.updated_at
+        FROM pledges p
+        JOIN posts ON posts.id = p.needs_id
+        JOIN users ON users.id = p.userid
+        WHERE p.userid = $1
+        ORDER BY p.created_at DESC, p.id
+        "#,
+        user.0
+    )
+    .fetch_all(&*db)
+    .await;
+    let pledges = fail!(res);
+
+    MyRes::Ok(pledges)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("20L"), Some(20));
+        assert_eq!(parse_amount("1.5 cylinders"), Some(2));
+        assert_eq!(parse_amount("0 units"), None);
+        assert_eq!(parse_amount("as many as possible"), None);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let coverage = Coverage {
+            amount: Some(10),
+            pledged: 4,
+            delivered: 0,
+        };
+        assert_eq!(coverage.remaining(), Some(6));
+        assert!(coverage.status() == PostStatus::Open);
+
+        let coverage = Coverage {
+            amount: Some(10),
+            pledged: 8,
+            delivered: 3,
+        };
+        // Over-pledged needs have nothing left, but aren't fulfilled yet.
+        assert_eq!(coverage.remaining(), Some(0));
+        assert!(coverage.status() == PostStatus::PartiallyFulfilled);
+
+        let coverage = Coverage {
+            amount: Some(10),
+            pledged: 0,
+            delivered: 12,
+        };
+        assert!(coverage.status() == PostStatus::Fulfilled);
+
+        let coverage = Coverage {
+            amount: None,
+            pledged: 5,
+            delivered: 50,
+        };
+        assert_eq!(coverage.remaining(), None);
+        assert!(coverage.status() == PostStatus::PartiallyFulfilled);
+
+        // Totals past what an i32 holds still work out.
+        let coverage = Coverage {
+            amount: Some(10),
+            pledged: 3_000_000_000,
+            delivered: 3_000_000_000,
+        };
+        assert_eq!(coverage.remaining(), Some(0));
+        assert!(coverage.status() == PostStatus::Fulfilled);
+    }
+}
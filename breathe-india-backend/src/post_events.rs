@@ -0,0 +1,101 @@
+// Reading the `post_events` log, which a trigger on `posts` appends to.
+// Each background worker keeps its own cursor into the log. Events are
+// only kept for RETENTION_DAYS, which is plenty for workers and stream
+// clients to catch up.
+
+use anyhow::{Context, Result};
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::myres::log_background_error;
+
+const RETENTION_DAYS: i64 = 30;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum PostEventKind {
+    Created,
+    Updated,
+    Deleted,
+    StatusChanged,
+}
+
+pub struct PostEvent {
+    pub id: i64,
+    pub post_id: Uuid,
+    pub kind: PostEventKind,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    // Where the post was and what it was for, right after the change or
+    // right before a delete. Only missing for events recorded before
+    // snapshots were added.
+    pub post: Option<serde_json::Value>,
+}
+
+pub async fn fetch_after(db: &PgPool, after: i64, limit: i64) -> Result<Vec<PostEvent>> {
+    sqlx::query_as!(
+        PostEvent,
+        r#"
+        SELECT id, post_id, kind as "kind: _", created_at, post
+        FROM post_events
+        WHERE id > $1
+        ORDER BY id
+        LIMIT $2
+        "#,
+        after,
+        limit
+    )
+    .fetch_all(db)
+    .await
+    .context("Fetch post events")
+}
+
+// A worker that has never run starts from the end of the log, so it doesn't
+// act on the whole history the first time it's deployed.
+pub async fn load_cursor(db: &PgPool, worker: &str) -> Result<i64> {
+    sqlx::query!(
+        r#"
+        INSERT INTO worker_cursors(name, last_event_id)
+        SELECT $1, COALESCE(MAX(id), 0) FROM post_events
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        worker
+    )
+    .execute(db)
+    .await
+    .context("Init worker cursor")?;
+    let cursor = sqlx::query!(
+        "SELECT last_event_id FROM worker_cursors WHERE name = $1",
+        worker
+    )
+    .fetch_one(db)
+    .await
+    .context("Load worker cursor")?;
+    Ok(cursor.last_event_id)
+}
+
+pub async fn save_cursor(db: &PgPool, worker: &str, last_event_id: i64) -> Result<()> {
+    sqlx::query!(
+        "UPDATE worker_cursors SET last_event_id = $2 WHERE name = $1",
+        worker,
+        last_event_id
+    )
+    .execute(db)
+    .await
+    .context("Save worker cursor")?;
+    Ok(())
+}
+
+pub async fn run_pruner(db: PgPool) {
+    loop {
+        let before = chrono::Utc::now() - chrono::Duration::days(RETENTION_DAYS);
+        let res = sqlx::query!("DELETE FROM post_events WHERE created_at < $1", before)
+            .execute(&db)
+            .await;
+        if let Err(e) = res {
+            log_background_error("post_events_pruner", &anyhow::Error::from(e));
+        }
+        tokio::time::sleep(PRUNE_INTERVAL).await;
+    }
+}
@@ -0,0 +1,499 @@
+// Builds the SQL for the `posts` listing. Filters and sort orders combine
+// freely, which would take far too many hand-written `query_as!`s, so the
+// query is put together at runtime instead. Values always go in as bind
+// parameters, never into the SQL text.
+
+use chrono::{DateTime, Utc};
+use sqlx::postgres::{PgArguments, Postgres};
+use sqlx::{Arguments, Encode, Type};
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::pagination::Cursor;
+use crate::patient::Urgency;
+use crate::{Post, PostStatus, PostType};
+
+pub const POST_COLUMNS: &str = "posts.id, posts.userid, posts.org_id, posts.post_type, \
+    posts.status, posts.urgency, posts.patient_age_bracket, posts.patient_spo2, \
+    posts.patient_blood_group, posts.patient_hospital, posts.patient_public, \
+    posts.contact_visibility, posts.beneficiary_name IS NOT NULL as on_behalf_of, \
+    posts.beneficiary_name, posts.beneficiary_relationship, posts.state, posts.district, \
+    posts.city, posts.spot, posts.created_at, posts.updated_at, posts.bumped_at, posts.item, \
+    posts.quantity, posts.quantity_remaining, posts.message";
+
+pub struct QueryBuilder {
+    sql: String,
+    args: PgArguments,
+    params: usize,
+}
+
+impl QueryBuilder {
+    pub fn new(sql: &str) -> Self {
+        QueryBuilder {
+            sql: sql.to_owned(),
+            args: PgArguments::default(),
+            params: 0,
+        }
+    }
+
+    pub fn push(&mut self, sql: &str) {
+        self.sql.push_str(sql);
+    }
+
+    // Binds `value` and returns its placeholder, which can be used more
+    // than once.
+    pub fn param<T>(&mut self, value: T) -> String
+    where
+        T: 'static + Send + Encode<'static, Postgres> + Type<Postgres>,
+    {
+        self.args.add(value);
+        self.params += 1;
+        format!("${}", self.params)
+    }
+
+    // `column IN (...)` over all of `values`.
+    fn push_in<T>(&mut self, column: &str, values: &[T])
+    where
+        T: 'static + Send + Copy + Encode<'static, Postgres> + Type<Postgres>,
+    {
+        let placeholders = values
+            .iter()
+            .map(|v| self.param(*v))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let _ = write!(self.sql, " AND {} IN ({})", column, placeholders);
+    }
+
+    #[cfg(test)]
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    pub fn into_parts(self) -> (String, PgArguments) {
+        (self.sql, self.args)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PostSort {
+    // Most recently bumped first. Edits don't reorder it.
+    Bumped,
+    Updated,
+    Created,
+    // Closest place first, see `push_order_by`.
+    Distance,
+    // Most recently confirmed available first.
+    Confirmed,
+    // Most urgent first, then most recently bumped.
+    Urgency,
+    // Most trusted authors first, see `trust`.
+    Trust,
+}
+
+impl PostSort {
+    // Cursors only work for orders that are a plain (timestamp, id) pair.
+    pub fn cursor_for(&self, post: &Post) -> Option<Cursor> {
+        let at = match self {
+            PostSort::Bumped => post.bumped_at,
+            PostSort::Updated => post.updated_at,
+            PostSort::Created => post.created_at,
+            PostSort::Distance | PostSort::Confirmed | PostSort::Urgency | PostSort::Trust => {
+                return None
+            }
+        };
+        Some(Cursor { at, id: post.id })
+    }
+
+    fn keyset_column(&self) -> Option<&'static str> {
+        match self {
+            PostSort::Bumped => Some("bumped_at"),
+            PostSort::Updated => Some("updated_at"),
+            PostSort::Created => Some("created_at"),
+            PostSort::Distance | PostSort::Confirmed | PostSort::Urgency | PostSort::Trust => None,
+        }
+    }
+}
+
+pub struct PostFilter {
+    // At least one.
+    pub types: Vec<PostType>,
+    // Empty means any.
+    pub statuses: Vec<PostStatus>,
+    pub location: Option<String>,
+    // Matches posts with any of these.
+    pub items: Vec<String>,
+    // Empty means any. Posts without an urgency count as normal, like they
+    // do for `PostSort::Urgency`.
+    pub urgencies: Vec<Urgency>,
+    pub author: Option<Uuid>,
+    pub verified_only: bool,
+    pub min_trust: Option<i16>,
+    pub updated_since: Option<DateTime<Utc>>,
+}
+
+fn like(s: &str) -> String {
+    format!("%{}%", s)
+}
+
+fn contains(s: &str, part: &str) -> bool {
+    s.to_lowercase().contains(&part.to_lowercase())
+}
+
+// What `PostFilter::matches` needs to know about a post's author.
+pub struct Author {
+    pub verified: bool,
+    pub trust_score: i16,
+}
+
+impl PostFilter {
+    fn push_where(&self, qb: &mut QueryBuilder) {
+        // Held posts wait for an admin, see `content_filter`.
+        qb.push(" WHERE NOT held");
+        qb.push_in("post_type", &self.types);
+        if !self.statuses.is_empty() {
+            qb.push_in("status", &self.statuses);
+        }
+        if let Some(location) = &self.location {
+            let p = qb.param(like(location));
+            qb.push(&format!(
+                " AND (state ILIKE {0} OR district ILIKE {0} OR city ILIKE {0} OR spot ILIKE {0})",
+                p
+            ));
+        }
+        if !self.items.is_empty() {
+            let items = self
+                .items
+                .iter()
+                .map(|item| format!("item ILIKE {}", qb.param(like(item))))
+                .collect::<Vec<_>>()
+                .join(" OR ");
+            qb.push(&format!(" AND ({})", items));
+        }
+        if !self.urgencies.is_empty() {
+            qb.push_in("COALESCE(urgency, 'normal')", &self.urgencies);
+        }
+        if let Some(author) = self.author {
+            let p = qb.param(author);
+            qb.push(&format!(" AND userid = {}", p));
+        }
+        if self.verified_only {
+            qb.push(" AND EXISTS (SELECT 1 FROM users WHERE users.id = posts.userid AND verified)");
+        }
+        if let Some(min_trust) = self.min_trust {
+            let p = qb.param(min_trust);
+            qb.push(&format!(
+                " AND EXISTS (SELECT 1 FROM users WHERE users.id = posts.userid AND trust_score >= {})",
+                p
+            ));
+        }
+        if let Some(since) = self.updated_since {
+            let p = qb.param(since);
+            qb.push(&format!(" AND updated_at >= {}", p));
+        }
+    }
+
+    // The same filter, for a post that is already loaded, like those on the
+    // live stream. Held posts never get this far.
+    pub fn matches(&self, post: &Post, author: &Author) -> bool {
+        let places = [&post.state[..], &post.district, &post.city, &post.spot];
+        self.matches_snapshot(post.post_type, places, &post.item)
+            && (self.statuses.is_empty() || self.statuses.contains(&post.status))
+            && (self.urgencies.is_empty()
+                || self
+                    .urgencies
+                    .contains(&post.urgency.unwrap_or(Urgency::Normal)))
+            && self.author.map_or(true, |a| a == post.userid)
+            && (!self.verified_only || author.verified)
+            && self.min_trust.map_or(true, |t| author.trust_score >= t)
+            && self.updated_since.map_or(true, |t| post.updated_at >= t)
+    }
+
+    // Only the filters on what post event snapshots keep, for posts that
+    // are gone.
+    pub fn matches_snapshot(&self, post_type: PostType, places: [&str; 4], item: &str) -> bool {
+        self.types.contains(&post_type)
+            && self
+                .location
+                .as_ref()
+                .map_or(true, |l| places.iter().any(|p| contains(p, l)))
+            && (self.items.is_empty() || self.items.iter().any(|i| contains(item, i)))
+    }
+
+    fn push_order_by(&self, qb: &mut QueryBuilder, sort: PostSort) {
+        qb.push(" ORDER BY ");
+        match sort {
+            PostSort::Bumped | PostSort::Updated | PostSort::Created => {}
+            // There are no coordinates, so like `matching`, closeness is how
+            // far down the state > district > city > spot hierarchy the
+            // searched place matched.
+            PostSort::Distance => {
+                let p = qb.param(like(self.location.as_deref().unwrap_or("")));
+                qb.push(&format!(
+                    "CASE WHEN spot ILIKE {0} THEN 0 \
+                     WHEN city ILIKE {0} THEN 1 \
+                     WHEN district ILIKE {0} THEN 2 \
+                     ELSE 3 END, ",
+                    p
+                ));
+            }
+            PostSort::Confirmed => qb.push(
+                "(SELECT MAX(created_at) FROM post_confirmations \
+                 WHERE post_id = posts.id AND available) DESC NULLS LAST, ",
+            ),
+            // Posts without an urgency are as urgent as normal ones.
+            PostSort::Urgency => qb.push("COALESCE(urgency, 'normal') ASC, "),
+            PostSort::Trust => {
+                qb.push("(SELECT trust_score FROM users WHERE users.id = posts.userid) DESC, ")
+            }
+        }
+        let column = sort.keyset_column().unwrap_or("bumped_at");
+        qb.push(&format!("{} DESC, id DESC", column));
+    }
+
+    pub fn select(
+        &self,
+        sort: PostSort,
+        after: Option<&Cursor>,
+        offset: i64,
+        limit: i64,
+    ) -> QueryBuilder {
+        let mut qb = QueryBuilder::new(&format!("SELECT {} FROM posts", POST_COLUMNS));
+        self.push_where(&mut qb);
+        if let (Some(after), Some(column)) = (after, sort.keyset_column()) {
+            let at = qb.param(after.at);
+            let id = qb.param(after.id);
+            qb.push(&format!(" AND ({}, id) < ({}, {})", column, at, id));
+        }
+        self.push_order_by(&mut qb, sort);
+        let offset = qb.param(offset);
+        let limit = qb.param(limit);
+        qb.push(&format!(" OFFSET {} LIMIT {}", offset, limit));
+        qb
+    }
+
+    // Counts matching posts, stopping at `cap`.
+    pub fn count(&self, cap: i64) -> QueryBuilder {
+        let mut qb = QueryBuilder::new("SELECT COUNT(*) FROM (SELECT 1 FROM posts");
+        self.push_where(&mut qb);
+        let cap = qb.param(cap);
+        qb.push(&format!(" LIMIT {}) capped", cap));
+        qb
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filter() -> PostFilter {
+        PostFilter {
+            types: vec![PostType::Needs],
+            statuses: vec![],
+            location: None,
+            items: vec![],
+            urgencies: vec![],
+            author: None,
+            verified_only: false,
+            min_trust: None,
+            updated_since: None,
+        }
+    }
+
+    fn post() -> Post {
+        serde_json::from_value(serde_json::json!({
+            "id": Uuid::nil(),
+            "userid": Uuid::nil(),
+            "post_type": "Needs",
+            "status": "Open",
+            "urgency": "High",
+            "patient_public": false,
+            "contact_visibility": "LoggedIn",
+            "on_behalf_of": false,
+            "state": "Kerala",
+            "district": "Ernakulam",
+            "city": "Kochi",
+            "spot": "Edappally",
+            "created_at": "2021-05-01T10:00:00Z",
+            "updated_at": "2021-05-02T10:00:00Z",
+            "bumped_at": "2021-05-02T10:00:00Z",
+            "item": "Oxygen cylinder",
+            "quantity": "2",
+            "message": "",
+        }))
+        .unwrap()
+    }
+
+    fn where_clause(sql: &str) -> &str {
+        let start = sql.find(" WHERE").unwrap();
+        let end = sql.find(" ORDER BY").unwrap_or_else(|| sql.len());
+        &sql[start..end]
+    }
+
+    #[test]
+    fn test_select_default() {
+        let qb = filter().select(PostSort::Bumped, None, 0, 20);
+        assert_eq!(
+            &qb.sql()[qb.sql().find(" WHERE").unwrap()..],
+            " WHERE NOT held AND post_type IN ($1) ORDER BY bumped_at DESC, id DESC OFFSET $2 LIMIT $3"
+        );
+        let qb = filter().select(PostSort::Updated, None, 0, 20);
+        assert!(qb.sql().contains(" ORDER BY updated_at DESC, id DESC"));
+    }
+
+    #[test]
+    fn test_select_filters() {
+        let filter = PostFilter {
+            types: vec![PostType::Needs, PostType::Supplies],
+            statuses: vec![PostStatus::Open],
+            location: Some("kochi".to_owned()),
+            items: vec!["oxygen".to_owned(), "plasma".to_owned()],
+            urgencies: vec![Urgency::Critical, Urgency::High],
+            author: Some(Uuid::nil()),
+            verified_only: true,
+            min_trust: Some(60),
+            updated_since: Some(Utc::now()),
+        };
+        let qb = filter.select(PostSort::Updated, None, 0, 20);
+        assert_eq!(
+            where_clause(qb.sql()),
+            " WHERE NOT held AND post_type IN ($1, $2) AND status IN ($3) \
+             AND (state ILIKE $4 OR district ILIKE $4 OR city ILIKE $4 OR spot ILIKE $4) \
+             AND (item ILIKE $5 OR item ILIKE $6) \
+             AND COALESCE(urgency, 'normal') IN ($7, $8) AND userid = $9 \
+             AND EXISTS (SELECT 1 FROM users WHERE users.id = posts.userid AND verified) \
+             AND EXISTS (SELECT 1 FROM users WHERE users.id = posts.userid AND trust_score >= $10) \
+             AND updated_at >= $11"
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        let post = post();
+        let author = Author {
+            verified: false,
+            trust_score: 50,
+        };
+        assert!(filter().matches(&post, &author));
+
+        let matching = PostFilter {
+            types: vec![PostType::Supplies, PostType::Needs],
+            statuses: vec![PostStatus::Open],
+            location: Some("kochi".to_owned()),
+            items: vec!["plasma".to_owned(), "OXYGEN".to_owned()],
+            urgencies: vec![Urgency::High],
+            author: Some(Uuid::nil()),
+            min_trust: Some(50),
+            updated_since: Some("2021-05-02T10:00:00Z".parse().unwrap()),
+            ..filter()
+        };
+        assert!(matching.matches(&post, &author));
+
+        let misses = vec![
+            PostFilter {
+                types: vec![PostType::Supplies],
+                ..filter()
+            },
+            PostFilter {
+                statuses: vec![PostStatus::Closed],
+                ..filter()
+            },
+            PostFilter {
+                location: Some("thrissur".to_owned()),
+                ..filter()
+            },
+            PostFilter {
+                items: vec!["plasma".to_owned()],
+                ..filter()
+            },
+            PostFilter {
+                urgencies: vec![Urgency::Critical],
+                ..filter()
+            },
+            PostFilter {
+                author: Some(Uuid::from_u128(1)),
+                ..filter()
+            },
+            PostFilter {
+                verified_only: true,
+                ..filter()
+            },
+            PostFilter {
+                min_trust: Some(51),
+                ..filter()
+            },
+            PostFilter {
+                updated_since: Some("2021-05-03T00:00:00Z".parse().unwrap()),
+                ..filter()
+            },
+        ];
+        for filter in &misses {
+            assert!(!filter.matches(&post, &author));
+        }
+
+        // Posts without an urgency are normal ones.
+        let post = Post {
+            urgency: None,
+            ..post
+        };
+        let normal = PostFilter {
+            urgencies: vec![Urgency::Normal],
+            ..filter()
+        };
+        assert!(normal.matches(&post, &author));
+    }
+
+    #[test]
+    fn test_matches_snapshot() {
+        let places = ["kerala", "ernakulam", "kochi", "edappally"];
+        let filter = PostFilter {
+            location: Some("Erna".to_owned()),
+            items: vec!["oxygen".to_owned()],
+            ..filter()
+        };
+        assert!(filter.matches_snapshot(PostType::Needs, places, "oxygen cylinder"));
+        assert!(!filter.matches_snapshot(PostType::Supplies, places, "oxygen cylinder"));
+        assert!(!filter.matches_snapshot(PostType::Needs, places, "plasma"));
+    }
+
+    #[test]
+    fn test_select_cursor() {
+        let cursor = Cursor {
+            at: Utc::now(),
+            id: Uuid::nil(),
+        };
+        let qb = filter().select(PostSort::Created, Some(&cursor), 0, 21);
+        assert!(qb
+            .sql()
+            .contains(" AND (created_at, id) < ($2, $3) ORDER BY created_at DESC, id DESC"));
+
+        // Ignored for orders that can't be paged by cursor.
+        let qb = filter().select(PostSort::Confirmed, Some(&cursor), 0, 21);
+        assert!(!qb.sql().contains("created_at, id"));
+    }
+
+    #[test]
+    fn test_select_urgency() {
+        let qb = filter().select(PostSort::Urgency, None, 0, 20);
+        assert!(qb
+            .sql()
+            .contains(" ORDER BY COALESCE(urgency, 'normal') ASC, bumped_at DESC, id DESC"));
+    }
+
+    #[test]
+    fn test_select_trust() {
+        let qb = filter().select(PostSort::Trust, None, 0, 20);
+        assert!(qb.sql().contains(
+            " ORDER BY (SELECT trust_score FROM users WHERE users.id = posts.userid) DESC, \
+             bumped_at DESC, id DESC"
+        ));
+    }
+
+    #[test]
+    fn test_count() {
+        let qb = filter().count(1000);
+        assert_eq!(
+            qb.sql(),
+            "SELECT COUNT(*) FROM (SELECT 1 FROM posts WHERE NOT held AND post_type IN ($1) LIMIT $2) capped"
+        );
+    }
+}
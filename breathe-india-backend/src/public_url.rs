@@ -0,0 +1,169 @@
+// URLs that the server sends requests to on a user's behalf, like webhooks.
+// They have to resolve to public addresses only, or anyone could point them
+// at the server itself, the cloud metadata service or the private network
+// behind it. Checked when they are saved and again before every request,
+// since what a name resolves to can change in between.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum UrlError {
+    #[error("Not an http or https URL")]
+    Invalid,
+    #[error("Host did not resolve")]
+    Unresolved,
+    #[error("Host resolves to a private address")]
+    NotPublic,
+}
+
+// Follows the same rules, for clients sending to checked URLs. A redirect
+// could go anywhere.
+pub fn client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder().redirect(reqwest::redirect::Policy::none())
+}
+
+pub async fn check(url: &str) -> Result<reqwest::Url, UrlError> {
+    let url = reqwest::Url::parse(url.trim()).map_err(|_| UrlError::Invalid)?;
+    if url.scheme() != "https" && url.scheme() != "http" {
+        return Err(UrlError::Invalid);
+    }
+    let port = url.port_or_known_default().ok_or(UrlError::Invalid)?;
+    // IPv6 hosts come bracketed, like [::1].
+    let host = url.host_str().ok_or(UrlError::Invalid)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<IpAddr> = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![ip],
+        Err(_) => {
+            let domain = host.trim_end_matches('.').to_ascii_lowercase();
+            if domain == "localhost" || domain.ends_with(".localhost") {
+                return Err(UrlError::NotPublic);
+            }
+            tokio::net::lookup_host((domain.as_str(), port))
+                .await
+                .map_err(|_| UrlError::Unresolved)?
+                .map(|addr| addr.ip())
+                .collect()
+        }
+    };
+    if addrs.is_empty() {
+        return Err(UrlError::Unresolved);
+    }
+    // Every address, since the client may pick any of them.
+    if !addrs.into_iter().all(is_public) {
+        return Err(UrlError::NotPublic);
+    }
+    Ok(url)
+}
+
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, _, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "This network", 0.0.0.0/8.
+        || a == 0
+        // Carrier-grade NAT, 100.64.0.0/10.
+        || (a == 100 && (b & 0xc0) == 64)
+        // Benchmarking, 198.18.0.0/15.
+        || (a == 198 && (b & 0xfe) == 18)
+        // Reserved, 240.0.0.0/4.
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // IPv4-mapped ::ffff:0:0/96 and NAT64 64:ff9b::/96 reach IPv4 hosts.
+    let embedded_v4 = Ipv4Addr::new(
+        (segments[6] >> 8) as u8,
+        segments[6] as u8,
+        (segments[7] >> 8) as u8,
+        segments[7] as u8,
+    );
+    if segments[..6] == [0, 0, 0, 0, 0, 0xffff] || segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return is_public_v4(embedded_v4);
+    }
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local, fc00::/7.
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link-local, fe80::/10, and the old site-local, fec0::/10.
+        || (segments[0] & 0xffc0) == 0xfe80
+        || (segments[0] & 0xffc0) == 0xfec0
+        // Documentation, 2001:db8::/32.
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_public() {
+        let private = &[
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::a00:1",
+        ];
+        for ip in private {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+
+        let public = &[
+            "1.1.1.1",
+            "142.250.183.46",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+        ];
+        for ip in public {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_check() {
+        assert_eq!(check("ftp://1.1.1.1/").await, Err(UrlError::Invalid));
+        assert_eq!(check("not a url").await, Err(UrlError::Invalid));
+        assert_eq!(
+            check("http://localhost:8000/").await,
+            Err(UrlError::NotPublic)
+        );
+        assert_eq!(
+            check("http://api.localhost/").await,
+            Err(UrlError::NotPublic)
+        );
+        assert_eq!(
+            check("http://127.0.0.1:8000/").await,
+            Err(UrlError::NotPublic)
+        );
+        assert_eq!(check("http://[::1]/").await, Err(UrlError::NotPublic));
+        assert_eq!(
+            check("http://169.254.169.254/latest/meta-data").await,
+            Err(UrlError::NotPublic)
+        );
+        assert!(check("https://1.1.1.1/hook").await.is_ok());
+    }
+}
@@ -0,0 +1,107 @@
+// Message encryption for Web Push, as per RFC 8291, using the aes128gcm
+// content coding from RFC 8188.
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes128Gcm, Nonce};
+use anyhow::{anyhow, Result};
+use hkdf::Hkdf;
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::{PublicKey, SecretKey};
+use rand::RngCore;
+use sha2::Sha256;
+
+// Push services must accept at least this record size, and our payloads
+// are much smaller, so everything goes in a single record.
+const RECORD_SIZE: u32 = 4096;
+
+// Encrypts `plaintext` for a subscription, identified by the p256dh and auth
+// keys that the browser handed out when subscribing.
+pub fn encrypt(ua_public: &[u8], auth_secret: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let as_secret = SecretKey::random(&mut rand::thread_rng());
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    encrypt_with(&as_secret, &salt, ua_public, auth_secret, plaintext)
+}
+
+fn encrypt_with(
+    as_secret: &SecretKey,
+    salt: &[u8; 16],
+    ua_public: &[u8],
+    auth_secret: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>> {
+    let ua_public_key =
+        PublicKey::from_sec1_bytes(ua_public).map_err(|_| anyhow!("Invalid p256dh key"))?;
+    let as_public = as_secret.public_key().to_encoded_point(false);
+    let as_public = as_public.as_bytes();
+
+    let ecdh_secret =
+        p256::ecdh::diffie_hellman(as_secret.to_nonzero_scalar(), ua_public_key.as_affine());
+
+    let mut key_info = b"WebPush: info\0".to_vec();
+    key_info.extend_from_slice(ua_public);
+    key_info.extend_from_slice(as_public);
+    let mut ikm = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(auth_secret), ecdh_secret.raw_secret_bytes())
+        .expand(&key_info, &mut ikm)
+        .map_err(|_| anyhow!("HKDF expand ikm"))?;
+
+    let hkdf = Hkdf::<Sha256>::new(Some(salt), &ikm);
+    let mut cek = [0u8; 16];
+    hkdf.expand(b"Content-Encoding: aes128gcm\0", &mut cek)
+        .map_err(|_| anyhow!("HKDF expand cek"))?;
+    let mut nonce = [0u8; 12];
+    hkdf.expand(b"Content-Encoding: nonce\0", &mut nonce)
+        .map_err(|_| anyhow!("HKDF expand nonce"))?;
+
+    // A single record, so it's also the last one.
+    let mut padded = plaintext.to_vec();
+    padded.push(2);
+    let cipher = Aes128Gcm::new_from_slice(&cek).map_err(|_| anyhow!("AES-GCM key"))?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), padded.as_slice())
+        .map_err(|_| anyhow!("AES-GCM encrypt"))?;
+
+    let mut body = Vec::with_capacity(16 + 4 + 1 + as_public.len() + ciphertext.len());
+    body.extend_from_slice(salt);
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(as_public.len() as u8);
+    body.extend_from_slice(as_public);
+    body.extend_from_slice(&ciphertext);
+    Ok(body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn b64(s: &str) -> Vec<u8> {
+        base64::decode_config(s, base64::URL_SAFE_NO_PAD).unwrap()
+    }
+
+    // The example from RFC 8291, Appendix A.
+    #[test]
+    fn test_encrypt_rfc8291_example() {
+        let as_secret = SecretKey::from_be_bytes(&b64("yfWPiYE-n46HLnH0KqZOF1fJJU3MYrct3AELtAQ-oRw"))
+            .unwrap();
+        let mut salt = [0u8; 16];
+        salt.copy_from_slice(&b64("DGv6ra1nlYgDCS1FRnbzlw"));
+        let ua_public = b64(
+            "BCVxsr7N_eNgVRqvHtD0zTZsEc6-VV-JvLexhqUzORcxaOzi6-AYWXvTBHm4bjyPjs7Vd8pZGH6SRpkNtoIAiw4",
+        );
+        let auth_secret = b64("BTBZMqHH6r4Tts7J_aSIgg");
+        let plaintext = b"When I grow up, I want to be a watermelon";
+
+        let body = encrypt_with(&as_secret, &salt, &ua_public, &auth_secret, plaintext).unwrap();
+        let expected = b64(concat!(
+            "DGv6ra1nlYgDCS1FRnbzlwAAEABBBP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocInmYWAmS6TlzAC8wEqKK6PBru3jl7A_",
+            "yl95bQpu6cVPTpK4Mqgkf1CXztLVBSt2Ks3oZwbuwXPXLWyouBWLVWGNWQexSgSxsj_Qulcy4a-fN"
+        ));
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn test_encrypt_rejects_bad_key() {
+        assert!(encrypt(b"not a key", &[0u8; 16], b"hello").is_err());
+    }
+}
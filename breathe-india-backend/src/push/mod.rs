@@ -0,0 +1,141 @@
+// Browser notifications over Web Push, without a native app.
+
+mod encrypt;
+mod sender;
+mod vapid;
+
+pub use sender::{PushNotifier, WebPushSender};
+pub use vapid::VapidKey;
+
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::myres::{HasStatusCode, MyRes};
+use crate::{fail, LoggedInUser};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        vapid_public_key,
+        push_subscriptions_create,
+        push_subscriptions_delete,
+    ]
+}
+
+#[derive(Serialize)]
+struct VapidPublicKey {
+    public_key: String,
+}
+
+#[get("/push/vapid_public_key")]
+async fn vapid_public_key(sender: State<'_, Arc<WebPushSender>>) -> MyRes<VapidPublicKey, ()> {
+    MyRes::Ok(VapidPublicKey {
+        public_key: sender.vapid_public_key().to_owned(),
+    })
+}
+
+// Same shape as `PushSubscription.toJSON()` in the browser.
+#[derive(Deserialize)]
+pub struct PushSubscriptionNew {
+    endpoint: String,
+    keys: PushSubscriptionKeys,
+}
+
+#[derive(Deserialize)]
+pub struct PushSubscriptionKeys {
+    p256dh: String,
+    auth: String,
+}
+
+#[derive(Serialize)]
+pub struct PushSubscription {
+    id: Uuid,
+    endpoint: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize)]
+enum PushSubscriptionError {
+    NotFound,
+    InvalidEndpoint,
+    InvalidKeys,
+}
+
+impl HasStatusCode for PushSubscriptionError {
+    fn get_status(&self) -> Status {
+        match self {
+            PushSubscriptionError::NotFound => Status::NotFound,
+            PushSubscriptionError::InvalidEndpoint => Status::BadRequest,
+            PushSubscriptionError::InvalidKeys => Status::BadRequest,
+        }
+    }
+}
+
+fn decoded_len(key: &str) -> Option<usize> {
+    base64::decode_config(key, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .map(|k| k.len())
+}
+
+#[post("/push/subscriptions", data = "<data>")]
+async fn push_subscriptions_create(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<PushSubscriptionNew>,
+) -> MyRes<PushSubscription, PushSubscriptionError> {
+    match reqwest::Url::parse(&data.endpoint) {
+        Ok(url) if url.scheme() == "https" => {}
+        _ => return MyRes::Err(PushSubscriptionError::InvalidEndpoint),
+    }
+    // An uncompressed P-256 point, and a 16 byte secret.
+    if decoded_len(&data.keys.p256dh) != Some(65) || decoded_len(&data.keys.auth) != Some(16) {
+        return MyRes::Err(PushSubscriptionError::InvalidKeys);
+    }
+
+    // Browsers resubscribe with the same endpoint, possibly after someone
+    // else logged in on the same browser.
+    let res = sqlx::query_as!(
+        PushSubscription,
+        r#"INSERT INTO push_subscriptions(userid, endpoint, p256dh, auth)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (endpoint) DO UPDATE SET
+            userid = EXCLUDED.userid,
+            p256dh = EXCLUDED.p256dh,
+            auth = EXCLUDED.auth
+        RETURNING id, endpoint, created_at
+        "#,
+        user.0,
+        data.endpoint,
+        data.keys.p256dh,
+        data.keys.auth
+    )
+    .fetch_one(&*db)
+    .await;
+    let subscription = fail!(res);
+
+    MyRes::Ok(subscription)
+}
+
+#[delete("/push/subscriptions/<id>")]
+async fn push_subscriptions_delete(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<(), PushSubscriptionError> {
+    let res = sqlx::query!(
+        "DELETE FROM push_subscriptions WHERE id = $1 AND userid = $2",
+        id.into_inner(),
+        user.0
+    )
+    .execute(&*db)
+    .await;
+
+    let res = fail!(res);
+    if res.rows_affected() == 0 {
+        return MyRes::Err(PushSubscriptionError::NotFound);
+    }
+    MyRes::Ok(())
+}
@@ -0,0 +1,183 @@
+use anyhow::{anyhow, Context, Result};
+use sqlx::PgPool;
+
+use super::encrypt;
+use super::vapid::VapidKey;
+use crate::notify::{Notification, Notifier, Recipient};
+
+// How long the push service should hold on to a message for an offline
+// browser. Older than this and the post has probably moved on.
+const TTL_SECONDS: u32 = 24 * 60 * 60;
+
+pub struct Subscription {
+    pub endpoint: String,
+    pub p256dh: String,
+    pub auth: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SendOutcome {
+    Delivered,
+    // The browser unsubscribed, or the subscription expired.
+    Gone,
+}
+
+pub struct WebPushSender {
+    client: reqwest::Client,
+    vapid: VapidKey,
+}
+
+impl WebPushSender {
+    pub fn new(vapid: VapidKey) -> Self {
+        WebPushSender {
+            client: reqwest::Client::new(),
+            vapid,
+        }
+    }
+
+    pub fn vapid_public_key(&self) -> &str {
+        self.vapid.public_key()
+    }
+
+    pub async fn send(&self, subscription: &Subscription, payload: &[u8]) -> Result<SendOutcome> {
+        let p256dh = base64::decode_config(&subscription.p256dh, base64::URL_SAFE_NO_PAD)
+            .context("Decode p256dh")?;
+        let auth = base64::decode_config(&subscription.auth, base64::URL_SAFE_NO_PAD)
+            .context("Decode auth")?;
+        let body = encrypt::encrypt(&p256dh, &auth, payload)?;
+
+        let res = self
+            .client
+            .post(&subscription.endpoint)
+            .header("TTL", TTL_SECONDS.to_string())
+            .header("Content-Encoding", "aes128gcm")
+            .header("Content-Type", "application/octet-stream")
+            .header(
+                "Authorization",
+                self.vapid.authorization(&subscription.endpoint)?,
+            )
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("POST {}", subscription.endpoint))?;
+
+        let status = res.status();
+        if status.is_success() {
+            Ok(SendOutcome::Delivered)
+        } else if status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::GONE {
+            Ok(SendOutcome::Gone)
+        } else {
+            Err(anyhow!(
+                "Push service at {} returned {}",
+                subscription.endpoint,
+                status
+            ))
+        }
+    }
+}
+
+// Sends a notification to every browser the recipient has subscribed from.
+pub struct PushNotifier {
+    db: PgPool,
+    sender: std::sync::Arc<WebPushSender>,
+}
+
+impl PushNotifier {
+    pub fn new(db: PgPool, sender: std::sync::Arc<WebPushSender>) -> Self {
+        PushNotifier { db, sender }
+    }
+}
+
+#[rocket::async_trait]
+impl Notifier for PushNotifier {
+    async fn notify(&self, recipient: &Recipient, notification: &Notification) -> Result<()> {
+        let subscriptions = sqlx::query!(
+            "SELECT id, endpoint, p256dh, auth FROM push_subscriptions WHERE userid = $1",
+            recipient.userid
+        )
+        .fetch_all(&self.db)
+        .await
+        .context("Fetch push subscriptions")?;
+
+        let payload = serde_json::to_vec(notification)?;
+        let mut last_err = None;
+        for row in subscriptions {
+            let subscription = Subscription {
+                endpoint: row.endpoint,
+                p256dh: row.p256dh,
+                auth: row.auth,
+            };
+            match self.sender.send(&subscription, &payload).await {
+                Ok(SendOutcome::Delivered) => {}
+                Ok(SendOutcome::Gone) => {
+                    sqlx::query!("DELETE FROM push_subscriptions WHERE id = $1", row.id)
+                        .execute(&self.db)
+                        .await
+                        .context("Delete expired push subscription")?;
+                }
+                // Keep going, so one broken browser doesn't cut off the others.
+                Err(e) => last_err = Some(e),
+            }
+        }
+        match last_err {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::stub_http_server;
+    use p256::elliptic_curve::sec1::ToEncodedPoint;
+
+    fn b64(bytes: &[u8]) -> String {
+        base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn test_sender() -> WebPushSender {
+        let key = p256::SecretKey::random(&mut rand::thread_rng());
+        let vapid = VapidKey::from_private_key(&b64(&key.to_be_bytes()), "mailto:test@example.com")
+            .unwrap();
+        WebPushSender::new(vapid)
+    }
+
+    fn test_subscription(endpoint: String) -> Subscription {
+        let browser_key = p256::SecretKey::random(&mut rand::thread_rng());
+        Subscription {
+            endpoint,
+            p256dh: b64(browser_key.public_key().to_encoded_point(false).as_bytes()),
+            auth: b64(&[7u8; 16]),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_send_to_stub_push_service() {
+        let (base_url, stub) = stub_http_server("201 Created").await;
+        let endpoint = format!("{}/push/abc", base_url);
+        let outcome = test_sender()
+            .send(&test_subscription(endpoint), b"{\"title\":\"hi\"}")
+            .await
+            .unwrap();
+        assert_eq!(outcome, SendOutcome::Delivered);
+
+        let request = stub.await.unwrap();
+        let head = request.head;
+        assert!(head.starts_with("post /push/abc "));
+        assert!(head.contains("content-encoding: aes128gcm"));
+        assert!(head.contains("ttl: 86400"));
+        assert!(head.contains("authorization: vapid t="));
+    }
+
+    #[rocket::async_test]
+    async fn test_send_to_expired_subscription() {
+        let (base_url, _stub) = stub_http_server("410 Gone").await;
+        let endpoint = format!("{}/push/abc", base_url);
+        let outcome = test_sender()
+            .send(&test_subscription(endpoint), b"{}")
+            .await
+            .unwrap();
+        assert_eq!(outcome, SendOutcome::Gone);
+    }
+}
@@ -0,0 +1,90 @@
+// VAPID (RFC 8292) identifies us to push services. Browsers tie each
+// subscription to our public key, so the key pair must stay the same across
+// restarts and across instances.
+
+use anyhow::{anyhow, Context, Result};
+use p256::ecdsa::signature::Signer;
+use p256::ecdsa::{Signature, SigningKey};
+use sqlx::PgPool;
+
+// How long a signed VAPID token is valid for. Push services reject anything
+// over 24 hours.
+const TOKEN_VALIDITY_HOURS: i64 = 12;
+
+pub struct VapidKey {
+    signing_key: SigningKey,
+    public_key: String,
+    subject: String,
+}
+
+fn b64(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+impl VapidKey {
+    pub fn from_private_key(private_key: &str, subject: &str) -> Result<Self> {
+        let bytes = base64::decode_config(private_key.trim(), base64::URL_SAFE_NO_PAD)
+            .context("Decode VAPID private key")?;
+        let signing_key =
+            SigningKey::from_bytes(&bytes).map_err(|_| anyhow!("Invalid VAPID private key"))?;
+        let public_key = signing_key.verifying_key().to_encoded_point(false);
+        Ok(VapidKey {
+            public_key: b64(public_key.as_bytes()),
+            signing_key,
+            subject: subject.to_owned(),
+        })
+    }
+
+    // VAPID_PRIVATE_KEY takes priority. Otherwise the key is kept in the
+    // database, and generated the first time.
+    pub async fn load(db: &PgPool) -> Result<Self> {
+        let subject = std::env::var("VAPID_SUBJECT")
+            .or_else(|_| std::env::var("PUBLIC_URL"))
+            .context("Get VAPID_SUBJECT env var")?;
+        if let Ok(private_key) = std::env::var("VAPID_PRIVATE_KEY") {
+            return Self::from_private_key(&private_key, &subject);
+        }
+
+        let generated = SigningKey::random(&mut rand::thread_rng());
+        sqlx::query!(
+            "INSERT INTO vapid_keys(private_key) VALUES ($1) ON CONFLICT DO NOTHING",
+            b64(&generated.to_bytes())
+        )
+        .execute(db)
+        .await
+        .context("Save generated VAPID key")?;
+        // Another instance may have got there first, so read back whichever won.
+        let row = sqlx::query!("SELECT private_key FROM vapid_keys")
+            .fetch_one(db)
+            .await
+            .context("Load VAPID key")?;
+        Self::from_private_key(&row.private_key, &subject)
+    }
+
+    // In the uncompressed form that browsers want as `applicationServerKey`.
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    // The Authorization header for a request to the given push endpoint.
+    pub fn authorization(&self, endpoint: &str) -> Result<String> {
+        let url = reqwest::Url::parse(endpoint).context("Parse push endpoint")?;
+        let claims = serde_json::json!({
+            "aud": url.origin().ascii_serialization(),
+            "exp": (chrono::Utc::now() + chrono::Duration::hours(TOKEN_VALIDITY_HOURS)).timestamp(),
+            "sub": self.subject,
+        });
+        let signing_input = format!(
+            "{}.{}",
+            b64(br#"{"typ":"JWT","alg":"ES256"}"#),
+            b64(&serde_json::to_vec(&claims)?)
+        );
+        let signature: Signature = self.signing_key.sign(signing_input.as_bytes());
+        Ok(format!(
+            "vapid t={}.{}, k={}",
+            signing_input,
+            b64(signature.as_ref()),
+            self.public_key
+        ))
+    }
+}
@@ -0,0 +1,360 @@
+// Token bucket rate limits for routes that write or log in. Adding a
+// `RateLimited` guard to a route limits it per user, if logged in, and per
+// client IP. Buckets live in memory, or in Postgres when several instances
+// need to share them.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use rocket_contrib::json::Json;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::myres::log_background_error;
+use crate::LoggedInUser;
+
+// Routes not listed here are not limited, even with the guard.
+const DEFAULT_LIMITS: &[(&str, &str)] = &[
+    ("login", "10/60"),
+    ("posts_create", "10/3600"),
+    ("posts_update", "30/3600"),
+    ("posts_report", "20/3600"),
+    ("posts_responses_create", "30/3600"),
+    ("posts_conversations_create", "20/3600"),
+    ("conversations_messages_create", "120/3600"),
+    ("contact_requests_create", "10/3600"),
+    ("orgs_invite", "20/3600"),
+];
+
+// Buckets untouched for this long are full again under any sane limit.
+const IDLE_BUCKET_MAX_AGE_HOURS: i64 = 24;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const MAX_MEMORY_BUCKETS: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limit {
+    // Requests that can be made in a burst.
+    capacity: f64,
+    // How fast the burst allowance comes back.
+    per_second: f64,
+}
+
+impl Limit {
+    // "<requests>/<seconds>", e.g. "10/60" for ten a minute.
+    fn parse(s: &str) -> Option<Self> {
+        let (count, seconds) = s.trim().split_once('/')?;
+        let count: u32 = count.trim().parse().ok()?;
+        let seconds: u32 = seconds.trim().parse().ok()?;
+        if count == 0 || seconds == 0 {
+            return None;
+        }
+        Some(Limit {
+            capacity: count as f64,
+            per_second: count as f64 / seconds as f64,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Bucket {
+    tokens: f64,
+    updated_at: DateTime<Utc>,
+}
+
+impl Bucket {
+    fn full(limit: &Limit, now: DateTime<Utc>) -> Self {
+        Bucket {
+            tokens: limit.capacity,
+            updated_at: now,
+        }
+    }
+
+    // Takes a token, or returns how long until there is one.
+    fn take(&self, limit: &Limit, now: DateTime<Utc>) -> Result<Bucket, Duration> {
+        let elapsed = (now - self.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+        let tokens = (self.tokens + elapsed * limit.per_second).min(limit.capacity);
+        if tokens >= 1.0 {
+            Ok(Bucket {
+                tokens: tokens - 1.0,
+                updated_at: now,
+            })
+        } else {
+            Err(Duration::from_secs_f64((1.0 - tokens) / limit.per_second))
+        }
+    }
+}
+
+#[rocket::async_trait]
+trait BucketStore: Send + Sync {
+    // Takes a token from the bucket at `key`, or returns how long until
+    // there is one.
+    async fn take(&self, key: &str, limit: &Limit) -> Result<Result<(), Duration>>;
+}
+
+#[derive(Default)]
+struct MemoryStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+#[rocket::async_trait]
+impl BucketStore for MemoryStore {
+    async fn take(&self, key: &str, limit: &Limit) -> Result<Result<(), Duration>> {
+        let now = Utc::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_MEMORY_BUCKETS {
+            let idle_since = now - chrono::Duration::hours(IDLE_BUCKET_MAX_AGE_HOURS);
+            buckets.retain(|_, b| b.updated_at > idle_since);
+        }
+        let bucket = buckets
+            .get(key)
+            .copied()
+            .unwrap_or_else(|| Bucket::full(limit, now));
+        Ok(bucket.take(limit, now).map(|bucket| {
+            buckets.insert(key.to_owned(), bucket);
+        }))
+    }
+}
+
+struct PgStore {
+    db: PgPool,
+}
+
+#[rocket::async_trait]
+impl BucketStore for PgStore {
+    async fn take(&self, key: &str, limit: &Limit) -> Result<Result<(), Duration>> {
+        let now = Utc::now();
+        let mut tx = self.db.begin().await?;
+        sqlx::query!(
+            r#"INSERT INTO rate_limit_buckets(key, tokens, updated_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (key) DO NOTHING"#,
+            key,
+            limit.capacity,
+            now
+        )
+        .execute(&mut tx)
+        .await
+        .context("Insert bucket")?;
+        let row = sqlx::query!(
+            "SELECT tokens, updated_at FROM rate_limit_buckets WHERE key = $1 FOR UPDATE",
+            key
+        )
+        .fetch_one(&mut tx)
+        .await
+        .context("Lock bucket")?;
+        let bucket = Bucket {
+            tokens: row.tokens,
+            updated_at: row.updated_at,
+        };
+        let taken = match bucket.take(limit, now) {
+            Ok(bucket) => {
+                sqlx::query!(
+                    "UPDATE rate_limit_buckets SET tokens = $2, updated_at = $3 WHERE key = $1",
+                    key,
+                    bucket.tokens,
+                    bucket.updated_at
+                )
+                .execute(&mut tx)
+                .await
+                .context("Update bucket")?;
+                Ok(())
+            }
+            Err(retry_after) => Err(retry_after),
+        };
+        tx.commit().await?;
+        Ok(taken)
+    }
+}
+
+pub struct RateLimiter {
+    limits: HashMap<String, Limit>,
+    store: Box<dyn BucketStore>,
+}
+
+impl RateLimiter {
+    // RATE_LIMITS overrides the default limits per route, e.g.
+    // "posts_create=5/3600,login=20/60". RATE_LIMIT_STORE=postgres shares
+    // buckets between instances.
+    pub fn from_env(db: &PgPool) -> Result<Self> {
+        let overrides = std::env::var("RATE_LIMITS").unwrap_or_default();
+        let limits = parse_limits(&overrides).ok_or_else(|| anyhow!("Invalid RATE_LIMITS"))?;
+        let store: Box<dyn BucketStore> = match std::env::var("RATE_LIMIT_STORE").as_deref() {
+            Ok("postgres") => Box::new(PgStore { db: db.clone() }),
+            Ok("memory") | Err(_) => Box::new(MemoryStore::default()),
+            Ok(_) => return Err(anyhow!("Invalid RATE_LIMIT_STORE")),
+        };
+        Ok(RateLimiter { limits, store })
+    }
+
+    async fn check(&self, route: &str, keys: &[String]) -> Result<Result<(), Duration>> {
+        let limit = match self.limits.get(route) {
+            Some(limit) => limit,
+            None => return Ok(Ok(())),
+        };
+        for key in keys {
+            let key = format!("{}:{}", route, key);
+            if let Err(retry_after) = self.store.take(&key, limit).await? {
+                return Ok(Err(retry_after));
+            }
+        }
+        Ok(Ok(()))
+    }
+}
+
+fn parse_limits(overrides: &str) -> Option<HashMap<String, Limit>> {
+    let mut limits = HashMap::new();
+    let rules = DEFAULT_LIMITS.iter().copied().chain(
+        overrides
+            .split(',')
+            .filter(|r| !r.trim().is_empty())
+            .map(|r| r.split_once('=').unwrap_or((r, ""))),
+    );
+    for (route, limit) in rules {
+        limits.insert(route.trim().to_owned(), Limit::parse(limit)?);
+    }
+    Some(limits)
+}
+
+// Removes idle buckets from Postgres, if that's where they are.
+pub async fn run_pruner(db: PgPool) {
+    if std::env::var("RATE_LIMIT_STORE").as_deref() != Ok("postgres") {
+        return;
+    }
+    loop {
+        let idle_since = Utc::now() - chrono::Duration::hours(IDLE_BUCKET_MAX_AGE_HOURS);
+        let res = sqlx::query!(
+            "DELETE FROM rate_limit_buckets WHERE updated_at < $1",
+            idle_since
+        )
+        .execute(&db)
+        .await;
+        if let Err(e) = res {
+            log_background_error("rate_limit_pruner", &anyhow::Error::from(e));
+        }
+        tokio::time::sleep(PRUNE_INTERVAL).await;
+    }
+}
+
+// Seconds until the request can be retried, for the catcher.
+struct RetryAfter(u64);
+
+// A request that is within the limits of its route.
+pub struct RateLimited;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimited {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let limiter = match request.rocket().state::<RateLimiter>() {
+            Some(limiter) => limiter,
+            None => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let route = match request.route().and_then(|r| r.name.as_deref()) {
+            Some(route) => route,
+            None => return Outcome::Success(RateLimited),
+        };
+        let mut keys = vec![];
+        if let Outcome::Success(user) = request.guard::<LoggedInUser>().await {
+            keys.push(format!("user:{}", user.0));
+        }
+        if let Some(ip) = request.client_ip() {
+            keys.push(format!("ip:{}", ip));
+        }
+
+        match limiter.check(route, &keys).await {
+            Ok(Ok(())) => Outcome::Success(RateLimited),
+            Ok(Err(retry_after)) => {
+                let secs = retry_after.as_secs_f64().ceil() as u64;
+                request.local_cache(|| RetryAfter(secs.max(1)));
+                Outcome::Failure((Status::TooManyRequests, ()))
+            }
+            // Better to let requests through than to lock everyone out.
+            Err(e) => {
+                log_background_error("rate_limit", &e);
+                Outcome::Success(RateLimited)
+            }
+        }
+    }
+}
+
+pub fn catchers() -> Vec<rocket::Catcher> {
+    catchers![too_many_requests]
+}
+
+#[derive(Serialize)]
+enum RateLimitError {
+    TooManyRequests { retry_after: u64 },
+}
+
+#[derive(Responder)]
+#[response(status = 429)]
+struct TooManyRequests {
+    inner: Json<RateLimitError>,
+    retry_after: Header<'static>,
+}
+
+#[catch(429)]
+fn too_many_requests(request: &Request<'_>) -> TooManyRequests {
+    let RetryAfter(secs) = request.local_cache(|| RetryAfter(1));
+    TooManyRequests {
+        inner: Json(RateLimitError::TooManyRequests { retry_after: *secs }),
+        retry_after: Header::new("Retry-After", secs.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_limit_parse() {
+        assert_eq!(
+            Limit::parse("10/60"),
+            Some(Limit {
+                capacity: 10.0,
+                per_second: 10.0 / 60.0
+            })
+        );
+        for invalid in &["", "10", "0/60", "10/0", "ten/60", "-1/60"] {
+            assert_eq!(Limit::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let limits = parse_limits("posts_create=5/3600, hospitals_create=1/60").unwrap();
+        assert_eq!(limits["posts_create"], Limit::parse("5/3600").unwrap());
+        assert_eq!(limits["hospitals_create"], Limit::parse("1/60").unwrap());
+        assert_eq!(limits["login"], Limit::parse("10/60").unwrap());
+        assert_eq!(parse_limits("login"), None);
+        assert_eq!(parse_limits("login=lots"), None);
+    }
+
+    #[test]
+    fn test_bucket_take() {
+        let limit = Limit::parse("2/60").unwrap();
+        let start = Utc.timestamp_opt(1620000000, 0).unwrap();
+        let bucket = Bucket::full(&limit, start);
+
+        let bucket = bucket.take(&limit, start).unwrap();
+        let bucket = bucket.take(&limit, start).unwrap();
+        // Empty, and a token comes back every 30 seconds.
+        assert_eq!(bucket.take(&limit, start), Err(Duration::from_secs(30)));
+        let later = start + chrono::Duration::seconds(20);
+        assert_eq!(bucket.take(&limit, later), Err(Duration::from_secs(10)));
+        let later = start + chrono::Duration::seconds(30);
+        let bucket = bucket.take(&limit, later).unwrap();
+        assert_eq!(bucket.tokens, 0.0);
+
+        // Never refills past capacity.
+        let much_later = later + chrono::Duration::days(1);
+        let bucket = bucket.take(&limit, much_later).unwrap();
+        assert_eq!(bucket.tokens, 1.0);
+    }
+}
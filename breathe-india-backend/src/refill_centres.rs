@@ -0,0 +1,552 @@
+// A directory of oxygen refill centres. Their operators keep the status up
+// to date, and anyone who has been there can confirm what they saw, which
+// matters when an operator is too busy to post.
+
+use chrono::{DateTime, Duration, Utc};
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::myres::{HasStatusCode, MyRes};
+use crate::params::{InvalidParam, PageParams};
+use crate::{bail, fail, LoggedInUser};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        refill_centres,
+        refill_centres_single,
+        refill_centres_create,
+        refill_centres_update,
+        refill_centres_operators_add,
+        refill_centres_status_update,
+        refill_centres_confirm,
+    ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "snake_case")]
+pub enum RefillStatus {
+    Open,
+    StockLow,
+    Closed,
+}
+
+impl RefillStatus {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "open" => Some(RefillStatus::Open),
+            "stock_low" => Some(RefillStatus::StockLow),
+            "closed" => Some(RefillStatus::Closed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RefillCentre {
+    id: Uuid,
+    name: String,
+    address: String,
+    state: String,
+    district: String,
+    city: String,
+    spot: String,
+    phone: String,
+    // Free text, like "9am - 6pm, closed Sundays".
+    opening_hours: String,
+    cylinder_sizes: Vec<String>,
+    status: RefillStatus,
+    status_note: String,
+    status_updated_at: DateTime<Utc>,
+    // What most visitors reported within the last CROWD_WINDOW_HOURS, if
+    // anyone did.
+    crowd_status: Option<RefillStatus>,
+    crowd_reports: i64,
+}
+
+#[derive(Deserialize)]
+pub struct RefillCentreNew {
+    name: String,
+    address: String,
+    state: String,
+    district: String,
+    city: String,
+    spot: String,
+    phone: String,
+    opening_hours: String,
+    cylinder_sizes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RefillStatusUpdate {
+    status: RefillStatus,
+    #[serde(default)]
+    note: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefillConfirmation {
+    status: RefillStatus,
+}
+
+#[derive(Deserialize)]
+pub struct OperatorNew {
+    userid: Uuid,
+}
+
+#[derive(Serialize)]
+enum RefillCentreError {
+    NotFound,
+    NotVerified,
+    NotOperator,
+    // Only verified users can be added as operators.
+    OperatorNotVerified,
+    MissingFields,
+    InvalidParam(InvalidParam),
+}
+
+impl HasStatusCode for RefillCentreError {
+    fn get_status(&self) -> Status {
+        match self {
+            RefillCentreError::NotFound => Status::NotFound,
+            RefillCentreError::NotVerified => Status::Forbidden,
+            RefillCentreError::NotOperator => Status::Forbidden,
+            RefillCentreError::OperatorNotVerified => Status::BadRequest,
+            RefillCentreError::MissingFields => Status::BadRequest,
+            RefillCentreError::InvalidParam(_) => Status::BadRequest,
+        }
+    }
+}
+
+const CROWD_WINDOW_HOURS: i64 = 3;
+
+impl RefillCentreNew {
+    fn normalize(&mut self) -> Result<(), RefillCentreError> {
+        for field in [
+            &mut self.name,
+            &mut self.address,
+            &mut self.state,
+            &mut self.district,
+            &mut self.city,
+            &mut self.spot,
+            &mut self.phone,
+            &mut self.opening_hours,
+        ]
+        .iter_mut()
+        {
+            **field = field.trim().to_owned();
+        }
+        self.cylinder_sizes = self
+            .cylinder_sizes
+            .iter()
+            .map(|s| s.trim().to_owned())
+            .filter(|s| !s.is_empty())
+            .collect();
+        if self.name.is_empty() || self.state.is_empty() || self.district.is_empty() {
+            return Err(RefillCentreError::MissingFields);
+        }
+        Ok(())
+    }
+}
+
+fn like(s: &Option<String>) -> Option<String> {
+    s.as_ref()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| format!("%{}%", s))
+}
+
+// Centres anywhere in `location`, the way `posts` matches locations, open
+// ones first.
+pub async fn search(
+    db: &PgPool,
+    location: Option<String>,
+    status: Option<RefillStatus>,
+    start: i64,
+    n: i64,
+) -> sqlx::Result<Vec<RefillCentre>> {
+    let location = like(&location);
+    sqlx::query_as!(
+        RefillCentre,
+        r#"
+        SELECT r.id,
+               r.name,
+               r.address,
+               r.state,
+               r.district,
+               r.city,
+               r.spot,
+               r.phone,
+               r.opening_hours,
+               r.cylinder_sizes,
+               r.status as "status: _",
+               r.status_note,
+               r.status_updated_at,
+               crowd.status as "crowd_status?: _",
+               COALESCE(crowd.reports, 0) as "crowd_reports!"
+        FROM refill_centres r
+        LEFT JOIN LATERAL (
+            SELECT c.status, (SUM(COUNT(*)) OVER ())::bigint as reports
+            FROM refill_centre_confirmations c
+            WHERE c.centre_id = r.id AND c.confirmed_at > $5
+            GROUP BY c.status
+            ORDER BY COUNT(*) DESC, MAX(c.confirmed_at) DESC
+            LIMIT 1
+        ) crowd ON TRUE
+        WHERE (
+            $1::text IS NULL OR
+            r.state ILIKE $1 OR
+            r.district ILIKE $1 OR
+            r.city ILIKE $1 OR
+            r.spot ILIKE $1
+        ) AND ($2::RefillStatus IS NULL OR r.status = $2)
+        ORDER BY r.status, r.status_updated_at DESC
+        OFFSET $3
+        LIMIT $4
+        "#,
+        location,
+        status: _,
+        start,
+        n,
+        Utc::now() - Duration::hours(CROWD_WINDOW_HOURS)
+    )
+    .fetch_all(db)
+    .await
+}
+
+async fn fetch_centre(db: &PgPool, id: Uuid) -> sqlx::Result<Option<RefillCentre>> {
+    sqlx::query_as!(
+        RefillCentre,
+        r#"
+        SELECT r.id,
+               r.name,
+               r.address,
+               r.state,
+               r.district,
+               r.city,
+               r.spot,
+               r.phone,
+               r.opening_hours,
+               r.cylinder_sizes,
+               r.status as "status: _",
+               r.status_note,
+               r.status_updated_at,
+               crowd.status as "crowd_status?: _",
+               COALESCE(crowd.reports, 0) as "crowd_reports!"
+        FROM refill_centres r
+        LEFT JOIN LATERAL (
+            SELECT c.status, (SUM(COUNT(*)) OVER ())::bigint as reports
+            FROM refill_centre_confirmations c
+            WHERE c.centre_id = r.id AND c.confirmed_at > $2
+            GROUP BY c.status
+            ORDER BY COUNT(*) DESC, MAX(c.confirmed_at) DESC
+            LIMIT 1
+        ) crowd ON TRUE
+        WHERE r.id = $1
+        "#,
+        id,
+        Utc::now() - Duration::hours(CROWD_WINDOW_HOURS)
+    )
+    .fetch_optional(db)
+    .await
+}
+
+async fn is_operator(db: &PgPool, centre_id: Uuid, userid: Uuid) -> sqlx::Result<bool> {
+    let row = sqlx::query!(
+        "SELECT 1 as one FROM refill_centre_operators WHERE centre_id = $1 AND userid = $2",
+        centre_id,
+        userid
+    )
+    .fetch_optional(db)
+    .await?;
+    Ok(row.is_some())
+}
+
+#[get("/refill_centres?<location>&<status>&<start>&<n>")]
+async fn refill_centres(
+    location: Option<String>,
+    status: Option<String>,
+    start: Option<String>,
+    n: Option<String>,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<RefillCentre>, RefillCentreError> {
+    let page = bail!(
+        PageParams::parse(start.as_deref(), n.as_deref()),
+        RefillCentreError::InvalidParam
+    );
+    let status = match status.as_deref().map(RefillStatus::parse) {
+        None => None,
+        Some(Some(status)) => Some(status),
+        Some(None) => {
+            let err = InvalidParam::new("status", "must be open, stock_low or closed");
+            return MyRes::Err(RefillCentreError::InvalidParam(err));
+        }
+    };
+    let res = search(&db, location, status, page.start, page.n).await;
+    let centres = fail!(res);
+
+    MyRes::Ok(centres)
+}
+
+#[get("/refill_centres/<id>")]
+async fn refill_centres_single(
+    id: rocket_contrib::uuid::Uuid,
+    db: State<'_, PgPool>,
+) -> MyRes<RefillCentre, RefillCentreError> {
+    let res = fetch_centre(&db, id.into_inner()).await;
+    let centre = fail!(res);
+    let centre = bail!(centre.ok_or(()), |_| RefillCentreError::NotFound);
+
+    MyRes::Ok(centre)
+}
+
+// Only verified users can list a centre. They become its first operator.
+#[post("/refill_centres", data = "<data>")]
+async fn refill_centres_create(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    mut data: Json<RefillCentreNew>,
+) -> MyRes<RefillCentre, RefillCentreError> {
+    let res = sqlx::query!("SELECT verified FROM users WHERE id = $1", user.0)
+        .fetch_optional(&*db)
+        .await;
+    let verified = fail!(res).map(|u| u.verified).unwrap_or(false);
+    if !verified {
+        return MyRes::Err(RefillCentreError::NotVerified);
+    }
+    bail!(data.normalize(), |e| e);
+
+    let mut tx = fail!(db.begin().await);
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO refill_centres(
+            name,
+            address,
+            state,
+            district,
+            city,
+            spot,
+            phone,
+            opening_hours,
+            cylinder_sizes,
+            created_by
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING id
+        "#,
+        data.name,
+        data.address,
+        data.state,
+        data.district,
+        data.city,
+        data.spot,
+        data.phone,
+        data.opening_hours,
+        &data.cylinder_sizes[..],
+        user.0
+    )
+    .fetch_one(&mut tx)
+    .await;
+    let id = fail!(res).id;
+    let res = sqlx::query!(
+        "INSERT INTO refill_centre_operators(centre_id, userid) VALUES ($1, $2)",
+        id,
+        user.0
+    )
+    .execute(&mut tx)
+    .await;
+    fail!(res);
+    fail!(tx.commit().await);
+
+    let res = fetch_centre(&db, id).await;
+    let centre = fail!(res);
+    let centre = bail!(centre.ok_or(()), |_| RefillCentreError::NotFound);
+    MyRes::Ok(centre)
+}
+
+#[patch("/refill_centres/<id>", data = "<data>")]
+async fn refill_centres_update(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    mut data: Json<RefillCentreNew>,
+) -> MyRes<RefillCentre, RefillCentreError> {
+    let id = id.into_inner();
+    if !fail!(is_operator(&db, id, user.0).await) {
+        return MyRes::Err(RefillCentreError::NotOperator);
+    }
+    bail!(data.normalize(), |e| e);
+
+    let res = sqlx::query!(
+        r#"
+        UPDATE refill_centres SET
+            name = $2,
+            address = $3,
+            state = $4,
+            district = $5,
+            city = $6,
+            spot = $7,
+            phone = $8,
+            opening_hours = $9,
+            cylinder_sizes = $10
+        WHERE id = $1
+        "#,
+        id,
+        data.name,
+        data.address,
+        data.state,
+        data.district,
+        data.city,
+        data.spot,
+        data.phone,
+        data.opening_hours,
+        &data.cylinder_sizes[..]
+    )
+    .execute(&*db)
+    .await;
+    fail!(res);
+
+    let res = fetch_centre(&db, id).await;
+    let centre = fail!(res);
+    let centre = bail!(centre.ok_or(()), |_| RefillCentreError::NotFound);
+    MyRes::Ok(centre)
+}
+
+// Operators can add others, as long as they are verified like whoever
+// listed the centre had to be.
+#[post("/refill_centres/<id>/operators", data = "<data>")]
+async fn refill_centres_operators_add(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<OperatorNew>,
+) -> MyRes<(), RefillCentreError> {
+    let id = id.into_inner();
+    if !fail!(is_operator(&db, id, user.0).await) {
+        return MyRes::Err(RefillCentreError::NotOperator);
+    }
+    let res = sqlx::query!("SELECT verified FROM users WHERE id = $1", data.userid)
+        .fetch_optional(&*db)
+        .await;
+    let verified = fail!(res).map(|u| u.verified).unwrap_or(false);
+    if !verified {
+        return MyRes::Err(RefillCentreError::OperatorNotVerified);
+    }
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO refill_centre_operators(centre_id, userid)
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        id,
+        data.userid
+    )
+    .execute(&*db)
+    .await;
+    fail!(res);
+
+    MyRes::Ok(())
+}
+
+#[post("/refill_centres/<id>/status", data = "<data>")]
+async fn refill_centres_status_update(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<RefillStatusUpdate>,
+) -> MyRes<RefillCentre, RefillCentreError> {
+    let id = id.into_inner();
+    if !fail!(is_operator(&db, id, user.0).await) {
+        return MyRes::Err(RefillCentreError::NotOperator);
+    }
+    let res = sqlx::query!(
+        r#"
+        UPDATE refill_centres
+        SET status = $2, status_note = $3, status_updated_at = NOW()
+        WHERE id = $1
+        "#,
+        id,
+        data.status: _,
+        data.note.trim()
+    )
+    .execute(&*db)
+    .await;
+    fail!(res);
+
+    let res = fetch_centre(&db, id).await;
+    let centre = fail!(res);
+    let centre = bail!(centre.ok_or(()), |_| RefillCentreError::NotFound);
+    MyRes::Ok(centre)
+}
+
+// Anyone logged in can report what they found. Only their latest report
+// counts.
+#[post("/refill_centres/<id>/confirmations", data = "<data>")]
+async fn refill_centres_confirm(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<RefillConfirmation>,
+) -> MyRes<RefillCentre, RefillCentreError> {
+    let id = id.into_inner();
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO refill_centre_confirmations(centre_id, userid, status)
+        SELECT id, $2, $3 FROM refill_centres WHERE id = $1
+        ON CONFLICT (centre_id, userid) DO UPDATE SET
+            status = EXCLUDED.status,
+            confirmed_at = NOW()
+        "#,
+        id,
+        user.0,
+        data.status: _
+    )
+    .execute(&*db)
+    .await;
+    let res = fail!(res);
+    if res.rows_affected() == 0 {
+        return MyRes::Err(RefillCentreError::NotFound);
+    }
+
+    let res = fetch_centre(&db, id).await;
+    let centre = fail!(res);
+    let centre = bail!(centre.ok_or(()), |_| RefillCentreError::NotFound);
+    MyRes::Ok(centre)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let mut centre = RefillCentreNew {
+            name: " Kochi Oxygen Works ".to_owned(),
+            address: "".to_owned(),
+            state: "Kerala".to_owned(),
+            district: " Ernakulam".to_owned(),
+            city: "".to_owned(),
+            spot: "".to_owned(),
+            phone: "".to_owned(),
+            opening_hours: "24x7".to_owned(),
+            cylinder_sizes: vec![" B-type".to_owned(), "".to_owned(), "47L".to_owned()],
+        };
+        assert!(centre.normalize().is_ok());
+        assert_eq!(centre.name, "Kochi Oxygen Works");
+        assert_eq!(centre.district, "Ernakulam");
+        assert_eq!(centre.cylinder_sizes, vec!["B-type", "47L"]);
+
+        centre.state = " ".to_owned();
+        assert!(centre.normalize().is_err());
+    }
+
+    #[test]
+    fn test_parse_status() {
+        assert_eq!(
+            RefillStatus::parse("Stock_Low"),
+            Some(RefillStatus::StockLow)
+        );
+        assert_eq!(RefillStatus::parse("busy"), None);
+    }
+}
@@ -0,0 +1,325 @@
+// Responses under a post, so that helpers can offer what a post asks for,
+// ask about it or say what changed, and its author hears about it. Threads
+// are one level deep: replies go under the response that started the
+// thread, even when they answer another reply.
+
+use chrono::{DateTime, Utc};
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::content_filter::{self, FilterAction};
+use crate::myres::{HasStatusCode, MyRes};
+use crate::notify::{Notifiers, PostActivity};
+use crate::rate_limit::RateLimited;
+use crate::{bail, fail, is_admin, spawn_notify_post_author, LoggedInUser};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        posts_responses,
+        posts_responses_create,
+        posts_responses_delete,
+    ]
+}
+
+const MAX_BODY_LEN: usize = 2000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum ResponseKind {
+    // Like "I can give 2 cylinders" on a needs post.
+    Offer,
+    Question,
+    // News about the need or supply, like it being gone.
+    Update,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Response {
+    id: Uuid,
+    // The response that started the thread, for replies.
+    parent_id: Option<Uuid>,
+    userid: Uuid,
+    author_name: String,
+    // Whether whoever wrote the post wrote this too.
+    by_post_author: bool,
+    kind: ResponseKind,
+    // Empty once deleted.
+    body: String,
+    deleted: bool,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Thread {
+    #[serde(flatten)]
+    response: Response,
+    replies: Vec<Response>,
+}
+
+#[derive(Deserialize)]
+pub struct ResponseNew {
+    kind: ResponseKind,
+    body: String,
+    // The response this replies to.
+    parent_id: Option<Uuid>,
+}
+
+#[derive(Serialize)]
+enum ResponseError {
+    NotFound,
+    ParentNotFound,
+    EmptyBody,
+    TooLong,
+    // Matched a content filter rule that holds or turns away posts.
+    Rejected,
+    NotAllowed,
+}
+
+impl HasStatusCode for ResponseError {
+    fn get_status(&self) -> Status {
+        match self {
+            ResponseError::NotFound => Status::NotFound,
+            ResponseError::ParentNotFound => Status::BadRequest,
+            ResponseError::EmptyBody => Status::BadRequest,
+            ResponseError::TooLong => Status::BadRequest,
+            ResponseError::Rejected => Status::BadRequest,
+            ResponseError::NotAllowed => Status::Forbidden,
+        }
+    }
+}
+
+// Takes responses oldest first. Deleted responses are left out, unless they
+// started a thread that still has replies.
+fn thread(responses: Vec<Response>) -> Vec<Thread> {
+    let mut replies: HashMap<Uuid, Vec<Response>> = HashMap::new();
+    let mut starts = vec![];
+    for response in responses {
+        match response.parent_id {
+            Some(parent_id) if !response.deleted => {
+                replies.entry(parent_id).or_default().push(response)
+            }
+            Some(_) => {}
+            None => starts.push(response),
+        }
+    }
+    starts
+        .into_iter()
+        .filter_map(|response| {
+            let replies = replies.remove(&response.id).unwrap_or_default();
+            if response.deleted && replies.is_empty() {
+                return None;
+            }
+            Some(Thread { response, replies })
+        })
+        .collect()
+}
+
+// The post's author, if the post is up. Held posts take no responses.
+async fn post_author(db: &PgPool, id: Uuid) -> sqlx::Result<Option<Uuid>> {
+    let post = sqlx::query!("SELECT userid FROM posts WHERE id = $1 AND NOT held", id)
+        .fetch_optional(db)
+        .await?;
+    Ok(post.map(|p| p.userid))
+}
+
+#[get("/posts/<id>/responses")]
+async fn posts_responses(
+    id: rocket_contrib::uuid::Uuid,
+    _user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<Thread>, ResponseError> {
+    let id: Uuid = id.into_inner();
+    let author = fail!(post_author(&db, id).await);
+    bail!(author.ok_or(()), |_| ResponseError::NotFound);
+
+    let res = sqlx::query_as!(
+        Response,
+        r#"
+        SELECT r.id,
+               r.parent_id,
+               r.userid,
+               users.name as author_name,
+               r.userid = posts.userid as "by_post_author!",
+               r.kind as "kind: _",
+               CASE WHEN r.deleted_at IS NULL THEN r.body ELSE '' END as "body!",
+               r.deleted_at IS NOT NULL as "deleted!",
+               r.created_at
+        FROM post_responses r
+        JOIN users ON users.id = r.userid
+        JOIN posts ON posts.id = r.post_id
+        WHERE r.post_id = $1
+        ORDER BY r.created_at, r.id
+        "#,
+        id
+    )
+    .fetch_all(&*db)
+    .await;
+    let responses = fail!(res);
+
+    MyRes::Ok(thread(responses))
+}
+
+// The post's author gets notified, unless they are the one responding.
+#[post("/posts/<id>/responses", data = "<data>")]
+async fn posts_responses_create(
+    _limit: RateLimited,
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    notifiers: State<'_, Arc<Notifiers>>,
+    data: Json<ResponseNew>,
+) -> MyRes<Response, ResponseError> {
+    let id: Uuid = id.into_inner();
+    let body = data.body.trim();
+    if body.is_empty() {
+        return MyRes::Err(ResponseError::EmptyBody);
+    }
+    if body.chars().count() > MAX_BODY_LEN {
+        return MyRes::Err(ResponseError::TooLong);
+    }
+    let author = fail!(post_author(&db, id).await);
+    let author = bail!(author.ok_or(()), |_| ResponseError::NotFound);
+    let parent_id = match data.parent_id {
+        Some(parent_id) => {
+            let res = sqlx::query!(
+                r#"SELECT COALESCE(parent_id, id) as "thread!" FROM post_responses
+                WHERE id = $1 AND post_id = $2 AND deleted_at IS NULL"#,
+                parent_id,
+                id
+            )
+            .fetch_optional(&*db)
+            .await;
+            let parent = fail!(res);
+            Some(bail!(parent.ok_or(()), |_| ResponseError::ParentNotFound).thread)
+        }
+        None => None,
+    };
+
+    // Responses can't wait for a moderator like posts do, so anything that
+    // would hold a post turns a response away. It counts against whoever
+    // responded, not the post.
+    let verdict = fail!(content_filter::evaluate(&db, body).await);
+    fail!(verdict.log(&db, user.0, None).await);
+    if verdict.action() >= Some(FilterAction::Hold) {
+        return MyRes::Err(ResponseError::Rejected);
+    }
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO post_responses(post_id, parent_id, userid, kind, body)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id,
+                  created_at,
+                  (SELECT name FROM users WHERE id = $3) as "author_name!"
+        "#,
+        id,
+        parent_id,
+        user.0,
+        data.kind: _,
+        body
+    )
+    .fetch_one(&*db)
+    .await;
+    let saved = fail!(res);
+
+    spawn_notify_post_author(
+        &db,
+        &notifiers,
+        id,
+        user.0,
+        PostActivity::Responded(data.kind),
+    );
+    MyRes::Ok(Response {
+        id: saved.id,
+        parent_id,
+        userid: user.0,
+        author_name: saved.author_name,
+        by_post_author: author == user.0,
+        kind: data.kind,
+        body: body.to_owned(),
+        deleted: false,
+        created_at: saved.created_at,
+    })
+}
+
+// By whoever wrote the response, or an admin.
+#[delete("/posts/<id>/responses/<response_id>")]
+async fn posts_responses_delete(
+    id: rocket_contrib::uuid::Uuid,
+    response_id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<(), ResponseError> {
+    let response_id: Uuid = response_id.into_inner();
+    let res = sqlx::query!(
+        r#"SELECT userid FROM post_responses
+        WHERE id = $1 AND post_id = $2 AND deleted_at IS NULL"#,
+        response_id,
+        id.into_inner()
+    )
+    .fetch_optional(&*db)
+    .await;
+    let response = fail!(res);
+    let response = bail!(response.ok_or(()), |_| ResponseError::NotFound);
+    if response.userid != user.0 && !fail!(is_admin(&db, user.0).await) {
+        return MyRes::Err(ResponseError::NotAllowed);
+    }
+
+    let res = sqlx::query!(
+        "UPDATE post_responses SET deleted_at = NOW(), deleted_by = $2 WHERE id = $1",
+        response_id,
+        user.0
+    )
+    .execute(&*db)
+    .await;
+    fail!(res);
+
+    MyRes::Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response(id: u128, parent_id: Option<u128>, deleted: bool) -> Response {
+        Response {
+            id: Uuid::from_u128(id),
+            parent_id: parent_id.map(Uuid::from_u128),
+            userid: Uuid::nil(),
+            author_name: "Asha".to_owned(),
+            by_post_author: false,
+            kind: ResponseKind::Question,
+            body: String::new(),
+            deleted,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_thread() {
+        let threads = thread(vec![
+            response(1, None, false),
+            response(2, None, true),
+            response(3, Some(1), false),
+            response(4, None, true),
+            response(5, Some(4), false),
+            response(6, Some(1), true),
+            response(7, Some(1), false),
+        ]);
+        let ids = threads
+            .iter()
+            .map(|t| {
+                let replies = t.replies.iter().map(|r| r.id.as_u128()).collect();
+                (t.response.id.as_u128(), replies)
+            })
+            .collect::<Vec<(u128, Vec<u128>)>>();
+        // Deleted and without replies, 2 is gone. 4 stays for the reply
+        // under it.
+        assert_eq!(ids, vec![(1, vec![3, 7]), (4, vec![5])]);
+    }
+}
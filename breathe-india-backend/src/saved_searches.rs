@@ -0,0 +1,339 @@
+// Saved searches take the same filters as `posts`. A background worker
+// watches for new and updated posts and notifies the owners of matching
+// searches.
+
+use anyhow::{Context, Result};
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::myres::{log_background_error, HasStatusCode, MyRes};
+use crate::notify::{self, Notification, Notifiers, NotifyChannel, Recipient};
+use crate::post_events::{self, PostEventKind};
+use crate::{bail, fail, public_url, LoggedInUser, PostStatus, PostType};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        saved_searches,
+        saved_searches_create,
+        saved_searches_update,
+        saved_searches_delete,
+    ]
+}
+
+#[derive(Serialize)]
+pub struct SavedSearch {
+    id: Uuid,
+    userid: Uuid,
+    typ: PostType,
+    location: Option<String>,
+    item: Option<String>,
+    channel: NotifyChannel,
+    webhook_url: Option<String>,
+    paused: bool,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct SavedSearchNew {
+    typ: PostType,
+    location: Option<String>,
+    item: Option<String>,
+    channel: NotifyChannel,
+    webhook_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SavedSearchUpdate {
+    paused: bool,
+}
+
+#[derive(Serialize)]
+enum SavedSearchError {
+    NotFound,
+    ChannelUnavailable,
+    InvalidWebhookUrl,
+}
+
+impl HasStatusCode for SavedSearchError {
+    fn get_status(&self) -> Status {
+        match self {
+            SavedSearchError::NotFound => Status::NotFound,
+            SavedSearchError::ChannelUnavailable => Status::BadRequest,
+            SavedSearchError::InvalidWebhookUrl => Status::BadRequest,
+        }
+    }
+}
+
+fn non_empty(s: &Option<String>) -> Option<String> {
+    s.as_ref()
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+}
+
+#[get("/saved_searches")]
+async fn saved_searches(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<SavedSearch>, ()> {
+    let res = sqlx::query_as!(
+        SavedSearch,
+        r#"
+        SELECT id,
+               userid,
+               post_type as "typ: _",
+               location,
+               item,
+               channel as "channel: _",
+               webhook_url,
+               paused,
+               created_at
+        FROM saved_searches
+        WHERE userid = $1
+        ORDER BY created_at DESC
+        "#,
+        user.0
+    )
+    .fetch_all(&*db)
+    .await;
+    let searches = fail!(res);
+
+    MyRes::Ok(searches)
+}
+
+#[post("/saved_searches", data = "<data>")]
+async fn saved_searches_create(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    notifiers: State<'_, Arc<Notifiers>>,
+    data: Json<SavedSearchNew>,
+) -> MyRes<SavedSearch, SavedSearchError> {
+    if notifiers.get(data.channel).is_none() {
+        return MyRes::Err(SavedSearchError::ChannelUnavailable);
+    }
+    let webhook_url = non_empty(&data.webhook_url);
+    if data.channel == NotifyChannel::Webhook {
+        let valid = match &webhook_url {
+            Some(url) => public_url::check(url).await.is_ok(),
+            None => false,
+        };
+        if !valid {
+            return MyRes::Err(SavedSearchError::InvalidWebhookUrl);
+        }
+    }
+
+    let res = sqlx::query_as!(
+        SavedSearch,
+        r#"INSERT INTO saved_searches(
+            userid,
+            post_type,
+            location,
+            item,
+            channel,
+            webhook_url
+        ) VALUES ($1, $2, $3, $4, $5, $6) RETURNING
+               id,
+               userid,
+               post_type as "typ: _",
+               location,
+               item,
+               channel as "channel: _",
+               webhook_url,
+               paused,
+               created_at
+        "#,
+        user.0,
+        data.typ: _,
+        non_empty(&data.location),
+        non_empty(&data.item),
+        data.channel: _,
+        webhook_url
+    )
+    .fetch_one(&*db)
+    .await;
+    let search = fail!(res);
+
+    MyRes::Ok(search)
+}
+
+#[patch("/saved_searches/<id>", data = "<data>")]
+async fn saved_searches_update(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<SavedSearchUpdate>,
+) -> MyRes<SavedSearch, SavedSearchError> {
+    let res = sqlx::query_as!(
+        SavedSearch,
+        r#"UPDATE saved_searches SET paused = $3
+        WHERE id = $1 AND userid = $2
+        RETURNING
+               id,
+               userid,
+               post_type as "typ: _",
+               location,
+               item,
+               channel as "channel: _",
+               webhook_url,
+               paused,
+               created_at
+        "#,
+        id.into_inner(),
+        user.0,
+        data.paused
+    )
+    .fetch_optional(&*db)
+    .await;
+    let search = fail!(res);
+    let search = bail!(search.ok_or(()), |_| SavedSearchError::NotFound);
+
+    MyRes::Ok(search)
+}
+
+#[delete("/saved_searches/<id>")]
+async fn saved_searches_delete(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<(), SavedSearchError> {
+    let res = sqlx::query!(
+        "DELETE FROM saved_searches WHERE id = $1 AND userid = $2",
+        id.into_inner(),
+        user.0
+    )
+    .execute(&*db)
+    .await;
+
+    let res = fail!(res);
+    if res.rows_affected() == 0 {
+        return MyRes::Err(SavedSearchError::NotFound);
+    }
+    MyRes::Ok(())
+}
+
+const WORKER_NAME: &str = "saved_searches";
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+const BATCH_SIZE: i64 = 100;
+
+pub async fn run_worker(db: PgPool, notifiers: Arc<Notifiers>) {
+    loop {
+        match process_events(&db, &notifiers).await {
+            Ok(n) if n == BATCH_SIZE as usize => continue,
+            Ok(_) => {}
+            Err(e) => log_background_error(WORKER_NAME, &e),
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+// Returns the number of events processed.
+async fn process_events(db: &PgPool, notifiers: &Notifiers) -> Result<usize> {
+    let cursor = post_events::load_cursor(db, WORKER_NAME).await?;
+    let events = post_events::fetch_after(db, cursor, BATCH_SIZE).await?;
+    for event in &events {
+        if event.kind != PostEventKind::Deleted {
+            notify_searches(db, notifiers, event.post_id).await?;
+        }
+        post_events::save_cursor(db, WORKER_NAME, event.id).await?;
+    }
+    Ok(events.len())
+}
+
+async fn notify_searches(db: &PgPool, notifiers: &Notifiers, post_id: Uuid) -> Result<()> {
+    let post = crate::fetch_post(db, post_id, false)
+        .await
+        .context("Fetch post")?;
+    let post = match post {
+        Some(post) if post.status == PostStatus::Open => post,
+        // Deleted or closed since the event was recorded. Needs that pledges
+        // already partly cover aren't news to anyone either.
+        _ => return Ok(()),
+    };
+
+    // Same matching as the `posts` listing, but from the other side.
+    let searches = sqlx::query!(
+        r#"
+        SELECT saved_searches.id,
+               saved_searches.userid,
+               channel as "channel: NotifyChannel",
+               webhook_url,
+               users.email
+        FROM saved_searches
+        JOIN users ON users.id = saved_searches.userid
+        WHERE NOT paused
+          AND post_type = $1
+          AND saved_searches.userid <> $2
+          AND (
+              location IS NULL OR
+              $3 ILIKE '%' || location || '%' OR
+              $4 ILIKE '%' || location || '%' OR
+              $5 ILIKE '%' || location || '%' OR
+              $6 ILIKE '%' || location || '%'
+          ) AND (
+              item IS NULL OR
+              $7 ILIKE '%' || item || '%'
+          ) AND NOT EXISTS (
+              SELECT 1 FROM saved_search_notifications
+              WHERE saved_search_id = saved_searches.id AND post_id = $8
+          )
+        "#,
+        post.post_type: _,
+        post.userid,
+        post.state,
+        post.district,
+        post.city,
+        post.spot,
+        post.item,
+        post.id
+    )
+    .fetch_all(db)
+    .await
+    .context("Fetch matching saved searches")?;
+
+    let notification = Notification {
+        title: format!("New post matching your search: {}", post.item),
+        body: format!(
+            "{} {} at {}",
+            post.quantity,
+            post.item,
+            [&post.spot, &post.city, &post.district, &post.state]
+                .iter()
+                .filter(|s| !s.is_empty())
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        url: notify::post_url(post.id),
+    };
+
+    for search in searches {
+        let notifier = match notifiers.get(search.channel) {
+            Some(notifier) => notifier,
+            None => continue,
+        };
+        let recipient = Recipient {
+            userid: search.userid,
+            email: search.email,
+            webhook_url: search.webhook_url,
+        };
+        // One failing recipient shouldn't hold up everyone else.
+        if let Err(e) = notifier.notify(&recipient, &notification).await {
+            log_background_error(WORKER_NAME, &e);
+            continue;
+        }
+        sqlx::query!(
+            r#"INSERT INTO saved_search_notifications(saved_search_id, post_id)
+            VALUES ($1, $2) ON CONFLICT DO NOTHING"#,
+            search.id,
+            post.id
+        )
+        .execute(db)
+        .await
+        .context("Record notification")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,441 @@
+// A live feed of post changes over Server-Sent Events, for dashboards that
+// would otherwise poll `posts`.
+//
+// Each instance runs one listener on the `post_events` Postgres channel and
+// fans events out to its own clients, so it doesn't matter which instance
+// made the change. Event ids are `post_events` ids, so a client that
+// reconnects with Last-Event-ID gets whatever it missed from the log, as
+// long as it was away for less than an hour. Otherwise it gets a `reset`
+// event, and should reload the listing.
+
+use anyhow::{Context, Result};
+use rocket::http::{ContentType, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio::sync::broadcast;
+
+use crate::myres::log_background_error;
+use crate::params::{self, InvalidParam};
+use crate::post_events::{self, PostEvent, PostEventKind};
+use crate::post_query::{Author, PostFilter};
+use crate::{Post, PostType, PostsParams};
+
+const CHANNEL: &str = "post_events";
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// Clients that fall further behind than this are dropped, and catch up
+// from the log when they reconnect.
+const CLIENT_BUFFER: usize = 256;
+// Events read from the log at a time, both by the listener and by clients
+// catching up.
+const REPLAY_PAGE: i64 = 1000;
+// How far back a reconnecting client can catch up from. Clients that were
+// away for longer are told to reload the listing instead, since replaying
+// days of events would take longer than that.
+const REPLAY_WINDOW_SECONDS: f64 = 60.0 * 60.0;
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![posts_stream]
+}
+
+// The post as it was at the time of the event, see `post_events`.
+struct Snapshot {
+    post_type: Option<PostType>,
+    places: [String; 4],
+    item: String,
+}
+
+fn snapshot_field(snapshot: &serde_json::Value, field: &str) -> String {
+    snapshot
+        .get(field)
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_owned()
+}
+
+impl Snapshot {
+    fn of(event: &PostEvent) -> Option<Self> {
+        let snapshot = event.post.as_ref()?;
+        Some(Snapshot {
+            post_type: params::parse_post_type(Some(&snapshot_field(snapshot, "post_type"))).ok(),
+            places: [
+                snapshot_field(snapshot, "state"),
+                snapshot_field(snapshot, "district"),
+                snapshot_field(snapshot, "city"),
+                snapshot_field(snapshot, "spot"),
+            ],
+            item: snapshot_field(snapshot, "item"),
+        })
+    }
+}
+
+pub struct StreamEvent {
+    id: i64,
+    kind: PostEventKind,
+    // What deleted posts are matched on.
+    snapshot: Snapshot,
+    // What everything else is matched on. None for deleted posts.
+    post: Option<(Post, Author)>,
+    data: String,
+}
+
+impl StreamEvent {
+    async fn load(db: &PgPool, event: &PostEvent) -> Result<Option<Self>> {
+        let snapshot = match Snapshot::of(event) {
+            Some(snapshot) => snapshot,
+            None => return Ok(None),
+        };
+        let post = if event.kind == PostEventKind::Deleted {
+            None
+        } else {
+            match crate::fetch_post(db, event.post_id, false).await? {
+                // The stream needs no login, so it gets what anonymous
+                // viewers of `posts` get.
+                Some(mut post) => {
+                    post.hide_patient_details();
+                    let author = sqlx::query_as!(
+                        Author,
+                        "SELECT verified, trust_score FROM users WHERE id = $1",
+                        post.userid
+                    )
+                    .fetch_one(db)
+                    .await?;
+                    Some((post, author))
+                }
+                // Deleted since, and that has an event of its own.
+                None => return Ok(None),
+            }
+        };
+        let data = serde_json::json!({
+            "event": event.kind,
+            "post_id": event.post_id,
+            "post": post.as_ref().map(|(post, _)| post),
+        });
+        Ok(Some(StreamEvent {
+            id: event.id,
+            kind: event.kind,
+            snapshot,
+            post,
+            data: serde_json::to_string(&data)?,
+        }))
+    }
+
+    fn to_sse(&self) -> String {
+        let event = match self.kind {
+            PostEventKind::Created => "created",
+            PostEventKind::Updated => "updated",
+            PostEventKind::Deleted => "deleted",
+            PostEventKind::StatusChanged => "status_changed",
+        };
+        format!("id: {}\nevent: {}\ndata: {}\n\n", self.id, event, self.data)
+    }
+}
+
+pub struct PostStream {
+    sender: broadcast::Sender<Arc<StreamEvent>>,
+}
+
+impl Default for PostStream {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CLIENT_BUFFER);
+        PostStream { sender }
+    }
+}
+
+pub async fn run_listener(db: PgPool, stream: Arc<PostStream>) {
+    let mut last_id = None;
+    loop {
+        if let Err(e) = listen(&db, &stream, &mut last_id).await {
+            log_background_error("post_stream", &e);
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+// Notifications are only used as a wake up call. The events themselves are
+// read from the log, so none are lost if a notification is.
+async fn listen(db: &PgPool, stream: &PostStream, last_id: &mut Option<i64>) -> Result<()> {
+    let mut listener = PgListener::connect_with(db)
+        .await
+        .context("Connect listener")?;
+    listener.listen(CHANNEL).await.context("LISTEN")?;
+    if last_id.is_none() {
+        let row = sqlx::query!(r#"SELECT COALESCE(MAX(id), 0) as "id!" FROM post_events"#)
+            .fetch_one(db)
+            .await?;
+        *last_id = Some(row.id);
+    }
+    loop {
+        listener.recv().await.context("Receive notification")?;
+        loop {
+            let after = last_id.unwrap_or(0);
+            let events = post_events::fetch_after(db, after, REPLAY_PAGE).await?;
+            if events.is_empty() {
+                break;
+            }
+            for event in &events {
+                *last_id = Some(event.id);
+                if let Some(event) = StreamEvent::load(db, event).await? {
+                    // Fails only when nobody is listening, which is fine.
+                    let _ = stream.sender.send(Arc::new(event));
+                }
+            }
+        }
+    }
+}
+
+// Same filters as the `posts` listing. Deleted posts only have their
+// snapshot left, so they are matched on type, place and item alone.
+pub struct StreamFilter(PostFilter);
+
+impl StreamFilter {
+    fn matches(&self, event: &StreamEvent) -> bool {
+        match &event.post {
+            Some((post, author)) => self.0.matches(post, author),
+            None => self.matches_snapshot(&event.snapshot),
+        }
+    }
+
+    // Also lets catching up skip loading posts that can't match.
+    fn matches_snapshot(&self, snapshot: &Snapshot) -> bool {
+        let post_type = match snapshot.post_type {
+            Some(post_type) => post_type,
+            None => return false,
+        };
+        let places = [
+            &snapshot.places[0][..],
+            &snapshot.places[1],
+            &snapshot.places[2],
+            &snapshot.places[3],
+        ];
+        self.0.matches_snapshot(post_type, places, &snapshot.item)
+    }
+}
+
+pub struct LastEventId(Option<i64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = ();
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let id = request
+            .headers()
+            .get_one("Last-Event-ID")
+            .and_then(|id| id.trim().parse().ok());
+        Outcome::Success(LastEventId(id))
+    }
+}
+
+pub struct EventStream(DuplexStream);
+
+impl<'r> Responder<'r, 'static> for EventStream {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .header(ContentType::new("text", "event-stream"))
+            .raw_header("Cache-Control", "no-cache")
+            // Otherwise nginx holds on to events until its buffer fills up.
+            .raw_header("X-Accel-Buffering", "no")
+            .streamed_body(self.0)
+            .ok()
+    }
+}
+
+// Takes the same filters as `posts`. Paging and sorting don't apply.
+#[get("/posts/stream?<query..>")]
+async fn posts_stream(
+    query: PostsParams,
+    last_event_id: LastEventId,
+    db: State<'_, PgPool>,
+    stream: State<'_, Arc<PostStream>>,
+) -> Result<EventStream, (Status, Json<InvalidParam>)> {
+    let filter = match query.parse() {
+        Ok((filter, _, _)) => StreamFilter(filter),
+        Err(e) => return Err((Status::BadRequest, Json(e))),
+    };
+    // Subscribe before catching up, so nothing falls in between.
+    let receiver = stream.sender.subscribe();
+    let (writer, reader) = tokio::io::duplex(16 * 1024);
+    let db = (*db).clone();
+    tokio::spawn(async move {
+        let res = send_events(&db, writer, receiver, filter, last_event_id.0).await;
+        if let Err(e) = res {
+            // Clients going away is the usual way for a stream to end.
+            if e.downcast_ref::<std::io::Error>().is_none() {
+                log_background_error("post_stream", &e);
+            }
+        }
+    });
+    Ok(EventStream(reader))
+}
+
+// Where a client that last saw `after` catches up from. Anything older than
+// the replay window is skipped, and the client gets a `reset` event telling
+// it to reload.
+async fn replay_start(db: &PgPool, after: i64) -> Result<i64> {
+    let row = sqlx::query!(
+        r#"
+        SELECT GREATEST($1, COALESCE(
+            (SELECT MIN(id) - 1 FROM post_events
+             WHERE created_at >= NOW() - make_interval(secs => $2)),
+            (SELECT MAX(id) FROM post_events),
+            0
+        )) as "id!"
+        "#,
+        after,
+        REPLAY_WINDOW_SECONDS
+    )
+    .fetch_one(db)
+    .await
+    .context("Find replay start")?;
+    Ok(row.id)
+}
+
+async fn send_events(
+    db: &PgPool,
+    mut writer: DuplexStream,
+    mut receiver: broadcast::Receiver<Arc<StreamEvent>>,
+    filter: StreamFilter,
+    last_event_id: Option<i64>,
+) -> Result<()> {
+    writer.write_all(b"retry: 5000\n\n").await?;
+
+    // Catches up a page at a time until it reaches the end of the log.
+    let mut last_sent = 0;
+    if let Some(after) = last_event_id {
+        last_sent = replay_start(db, after).await?;
+        if last_sent > after {
+            writer.write_all(b"event: reset\ndata: {}\n\n").await?;
+        }
+        loop {
+            let events = post_events::fetch_after(db, last_sent, REPLAY_PAGE).await?;
+            let caught_up = (events.len() as i64) < REPLAY_PAGE;
+            for event in &events {
+                last_sent = event.id;
+                match Snapshot::of(event) {
+                    Some(snapshot) if filter.matches_snapshot(&snapshot) => {}
+                    _ => continue,
+                }
+                if let Some(event) = StreamEvent::load(db, event).await? {
+                    if filter.matches(&event) {
+                        writer.write_all(event.to_sse().as_bytes()).await?;
+                    }
+                }
+            }
+            if caught_up {
+                break;
+            }
+        }
+    }
+
+    loop {
+        tokio::select! {
+            event = receiver.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    // Too slow, or shutting down. Either way the client
+                    // reconnects and catches up from the log.
+                    Err(_) => return Ok(()),
+                };
+                if event.id <= last_sent {
+                    continue;
+                }
+                last_sent = event.id;
+                if filter.matches(&event) {
+                    writer.write_all(event.to_sse().as_bytes()).await?;
+                }
+            }
+            _ = tokio::time::sleep(KEEP_ALIVE) => {
+                writer.write_all(b": keep-alive\n\n").await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(kind: PostEventKind) -> StreamEvent {
+        StreamEvent {
+            id: 42,
+            kind,
+            snapshot: Snapshot {
+                post_type: Some(PostType::Needs),
+                places: [
+                    "Kerala".to_owned(),
+                    "Ernakulam".to_owned(),
+                    "Kochi".to_owned(),
+                    "Edappally".to_owned(),
+                ],
+                item: "Oxygen cylinder".to_owned(),
+            },
+            post: None,
+            data: r#"{"event":"Created"}"#.to_owned(),
+        }
+    }
+
+    fn filter(query: &str) -> StreamFilter {
+        let mut params = PostsParams {
+            start: None,
+            n: None,
+            typ: vec![],
+            location: None,
+            item: vec![],
+            cursor: None,
+            count: None,
+            sort: None,
+            status: vec![],
+            urgency: vec![],
+            author: None,
+            verified_only: None,
+            min_trust: None,
+            updated_since: None,
+        };
+        for pair in query.split('&') {
+            let (key, value) = pair.split_once('=').unwrap();
+            match key {
+                "typ" => params.typ.push(value.to_owned()),
+                "location" => params.location = Some(value.to_owned()),
+                "item" => params.item.push(value.to_owned()),
+                "status" => params.status.push(value.to_owned()),
+                _ => unreachable!(),
+            }
+        }
+        StreamFilter(params.parse().unwrap().0)
+    }
+
+    // Deleted posts, matched on their snapshot. Everything else goes through
+    // `PostFilter::matches`, which has tests of its own.
+    #[test]
+    fn test_filter_matches() {
+        let e = event(PostEventKind::Deleted);
+        assert!(filter("typ=needs").matches(&e));
+        assert!(filter("typ=supplies&typ=needs").matches(&e));
+        assert!(filter("typ=needs&location=kochi&item=oxygen").matches(&e));
+        assert!(filter("typ=needs&location=erna").matches(&e));
+        assert!(filter("typ=needs&item=plasma&item=oxygen").matches(&e));
+        assert!(filter("typ=needs&status=closed").matches(&e));
+        assert!(!filter("typ=supplies").matches(&e));
+        assert!(!filter("typ=needs&location=thrissur").matches(&e));
+        assert!(!filter("typ=needs&item=plasma").matches(&e));
+
+        let mut e = event(PostEventKind::Deleted);
+        e.snapshot.post_type = None;
+        assert!(!filter("typ=needs").matches(&e));
+    }
+
+    #[test]
+    fn test_to_sse() {
+        assert_eq!(
+            event(PostEventKind::StatusChanged).to_sse(),
+            "id: 42\nevent: status_changed\ndata: {\"event\":\"Created\"}\n\n"
+        );
+    }
+}
@@ -0,0 +1,45 @@
+// Helpers shared by tests.
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+pub struct StubRequest {
+    // Request line and headers, lowercased.
+    pub head: String,
+    pub body: Vec<u8>,
+}
+
+// An HTTP server that answers a single request with the given status, like
+// "201 Created", and hands back the request it received.
+pub async fn stub_http_server(status: &'static str) -> (String, JoinHandle<StubRequest>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let handle = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        let (head_end, content_length) = loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request).to_lowercase();
+            if let Some(head_end) = text.find("\r\n\r\n") {
+                let content_length = text[..head_end]
+                    .lines()
+                    .find_map(|l| l.strip_prefix("content-length: "))
+                    .and_then(|l| l.trim().parse::<usize>().ok())
+                    .unwrap_or(0);
+                if request.len() >= head_end + 4 + content_length {
+                    break (head_end, content_length);
+                }
+            }
+        };
+        let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\n\r\n", status);
+        socket.write_all(response.as_bytes()).await.unwrap();
+        StubRequest {
+            head: String::from_utf8_lossy(&request[..head_end]).to_lowercase(),
+            body: request[head_end + 4..head_end + 4 + content_length].to_vec(),
+        }
+    });
+    (base_url, handle)
+}
@@ -0,0 +1,285 @@
+// A score from 0 to 100 for how far readers can rely on a user's posts,
+// going by their history rather than just the verified badge. It looks at
+// all of a user's posts, so a background worker recomputes it for everyone
+// instead of working it out per request.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::myres::{log_background_error, HasStatusCode, MyRes};
+use crate::rate_limit::RateLimited;
+use crate::{fail, LoggedInUser};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![posts_report]
+}
+
+pub const MAX_SCORE: i16 = 100;
+
+const RECOMPUTE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+// What every account starts from, and what new accounts show until the
+// worker gets to them.
+const BASE: f64 = 20.0;
+const VERIFIED: f64 = 30.0;
+// Grows with account age, up to AGE_FULL_DAYS.
+const AGE: f64 = 15.0;
+const AGE_FULL_DAYS: f64 = 90.0;
+// Up or down with the share of confirmations saying a post's supply or need
+// was really there. A handful of confirmations count for less.
+const CONFIRMATIONS: f64 = 25.0;
+const CONFIRMATIONS_FULL: f64 = 10.0;
+// Each person reporting the user's posts, and each post a moderator merged
+// away or the content filter held or rejected.
+const PER_REPORTER: f64 = 5.0;
+const PER_MODERATION: f64 = 5.0;
+const MAX_PENALTY: f64 = 25.0;
+// Reports only count from verified users or ones with some history, so a
+// batch of new accounts can't report someone's score away.
+const MIN_REPORTER_SCORE: i16 = 35;
+// Old reports and moderator actions are forgiven.
+const PENALTY_WINDOW_DAYS: i64 = 90;
+
+const MAX_REASON_LEN: usize = 500;
+
+#[derive(Debug)]
+pub struct TrustInputs {
+    pub created_at: DateTime<Utc>,
+    pub verified: bool,
+    // By others, on the user's posts.
+    pub confirmations: i64,
+    pub available: i64,
+    pub reporters: i64,
+    pub moderations: i64,
+}
+
+pub fn score(inputs: &TrustInputs, now: DateTime<Utc>) -> i16 {
+    let mut score = BASE;
+    if inputs.verified {
+        score += VERIFIED;
+    }
+    let age_days = (now - inputs.created_at).num_hours().max(0) as f64 / 24.0;
+    score += AGE * (age_days / AGE_FULL_DAYS).min(1.0);
+
+    if inputs.confirmations > 0 {
+        // Smoothed, so one confirmation either way isn't all or nothing.
+        let rate = (inputs.available as f64 + 1.0) / (inputs.confirmations as f64 + 2.0);
+        let confidence = (inputs.confirmations as f64 / CONFIRMATIONS_FULL).min(1.0);
+        score += CONFIRMATIONS * (rate - 0.5) * 2.0 * confidence;
+    }
+
+    score -= (PER_REPORTER * inputs.reporters as f64).min(MAX_PENALTY);
+    score -= (PER_MODERATION * inputs.moderations as f64).min(MAX_PENALTY);
+    score.round().max(0.0).min(MAX_SCORE as f64) as i16
+}
+
+// Returns the number of users updated.
+pub async fn recompute(db: &PgPool) -> Result<usize> {
+    let now = Utc::now();
+    let since = now - chrono::Duration::days(PENALTY_WINDOW_DAYS);
+    let users = sqlx::query!(
+        r#"
+        SELECT u.id,
+               u.created_at,
+               u.verified,
+               c.total as "confirmations!",
+               c.available as "available!",
+               (
+                   SELECT COUNT(DISTINCT pr.userid) FROM post_reports pr
+                   JOIN users r ON r.id = pr.userid
+                   WHERE pr.reported_userid = u.id AND pr.created_at > $1
+                     AND (r.verified OR r.trust_score >= $2)
+               ) as "reporters!",
+               (
+                   SELECT COUNT(*) FROM post_merges
+                   WHERE duplicate_userid = u.id AND merged_at > $1
+               ) + (
+                   SELECT COUNT(DISTINCT COALESCE(post_id, id)) FROM content_filter_decisions
+                   WHERE userid = u.id AND action <> 'flag' AND created_at > $1
+               ) as "moderations!"
+        FROM users u
+        CROSS JOIN LATERAL (
+            SELECT COUNT(*) as total, COUNT(*) FILTER (WHERE pc.available) as available
+            FROM post_confirmations pc
+            JOIN posts p ON p.id = pc.post_id
+            WHERE p.userid = u.id AND pc.userid <> u.id
+        ) c
+        "#,
+        since,
+        MIN_REPORTER_SCORE
+    )
+    .fetch_all(db)
+    .await
+    .context("Fetch trust inputs")?;
+
+    let (ids, scores): (Vec<Uuid>, Vec<i16>) = users
+        .iter()
+        .map(|u| {
+            let inputs = TrustInputs {
+                created_at: u.created_at,
+                verified: u.verified,
+                confirmations: u.confirmations,
+                available: u.available,
+                reporters: u.reporters,
+                moderations: u.moderations,
+            };
+            (u.id, score(&inputs, now))
+        })
+        .unzip();
+    sqlx::query!(
+        r#"
+        UPDATE users SET trust_score = s.score
+        FROM UNNEST($1::uuid[], $2::smallint[]) as s(id, score)
+        WHERE users.id = s.id AND users.trust_score <> s.score
+        "#,
+        &ids[..],
+        &scores[..]
+    )
+    .execute(db)
+    .await
+    .context("Save trust scores")?;
+    Ok(ids.len())
+}
+
+pub async fn run_worker(db: PgPool) {
+    loop {
+        if let Err(e) = recompute(&db).await {
+            log_background_error("trust", &e);
+        }
+        tokio::time::sleep(RECOMPUTE_INTERVAL).await;
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Report {
+    reason: String,
+}
+
+#[derive(Serialize)]
+pub enum ReportError {
+    NotFound,
+    OwnPost,
+    ReasonTooLong,
+}
+
+impl HasStatusCode for ReportError {
+    fn get_status(&self) -> Status {
+        match self {
+            ReportError::NotFound => Status::NotFound,
+            ReportError::OwnPost | ReportError::ReasonTooLong => Status::BadRequest,
+        }
+    }
+}
+
+// Tells moderators a post is wrong or a scam. It counts against the author's
+// trust score once per person reporting, if they are verified or trusted
+// enough themselves.
+#[post("/posts/<id>/report", data = "<data>")]
+async fn posts_report(
+    _limit: RateLimited,
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<Report>,
+) -> MyRes<(), ReportError> {
+    let id: Uuid = id.into_inner();
+    let reason = data.reason.trim();
+    if reason.chars().count() > MAX_REASON_LEN {
+        return MyRes::Err(ReportError::ReasonTooLong);
+    }
+    let res = sqlx::query!("SELECT userid FROM posts WHERE id = $1", id)
+        .fetch_optional(&*db)
+        .await;
+    let author = match fail!(res) {
+        Some(post) => post.userid,
+        None => return MyRes::Err(ReportError::NotFound),
+    };
+    if author == user.0 {
+        return MyRes::Err(ReportError::OwnPost);
+    }
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO post_reports(post_id, reported_userid, userid, reason)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (post_id, userid) DO UPDATE SET
+            reason = EXCLUDED.reason,
+            created_at = NOW()
+        "#,
+        id,
+        author,
+        user.0,
+        reason
+    )
+    .execute(&*db)
+    .await;
+    fail!(res);
+
+    MyRes::Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn inputs(age_days: i64, now: DateTime<Utc>) -> TrustInputs {
+        TrustInputs {
+            created_at: now - chrono::Duration::days(age_days),
+            verified: false,
+            confirmations: 0,
+            available: 0,
+            reporters: 0,
+            moderations: 0,
+        }
+    }
+
+    #[test]
+    fn test_score() {
+        let now = Utc::now();
+        assert_eq!(score(&inputs(0, now), now), BASE as i16);
+
+        let established = TrustInputs {
+            verified: true,
+            confirmations: 20,
+            available: 20,
+            ..inputs(365, now)
+        };
+        assert_eq!(score(&established, now), 88);
+
+        // A single confirmation moves it a little.
+        let one = TrustInputs {
+            confirmations: 1,
+            available: 1,
+            ..inputs(0, now)
+        };
+        assert_eq!(score(&one, now), 21);
+
+        // Supplies that were never there.
+        let unreliable = TrustInputs {
+            confirmations: 10,
+            available: 1,
+            ..inputs(45, now)
+        };
+        assert_eq!(score(&unreliable, now), 11);
+
+        // Penalties are capped each, and the score never goes below 0.
+        let reported = TrustInputs {
+            reporters: 100,
+            moderations: 2,
+            ..inputs(0, now)
+        };
+        assert_eq!(score(&reported, now), 0);
+        let reported = TrustInputs {
+            verified: true,
+            reporters: 100,
+            ..inputs(0, now)
+        };
+        assert_eq!(score(&reported, now), 25);
+    }
+}
@@ -0,0 +1,379 @@
+// Deliveries happen in two steps. The fan out worker turns each post event
+// into a delivery row per matching webhook. The delivery worker then sends
+// those rows out, retrying with exponential backoff until they either get
+// through or are given up on as dead.
+
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::myres::log_background_error;
+use crate::post_events::{self, PostEvent, PostEventKind};
+use crate::public_url;
+
+const FAN_OUT_WORKER: &str = "webhooks";
+const DELIVERY_WORKER: &str = "webhook_delivery";
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BATCH_SIZE: i64 = 100;
+const DELIVERY_BATCH_SIZE: i64 = 20;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// After this many failed attempts a delivery is marked dead.
+const MAX_ATTEMPTS: i32 = 8;
+const BACKOFF_BASE_SECONDS: i64 = 30;
+const BACKOFF_MAX_SECONDS: i64 = 6 * 60 * 60;
+
+// A claimed delivery that isn't finished within this time, say because the
+// instance died, goes back to being due.
+const CLAIM_SECONDS: f64 = 300.0;
+
+pub async fn run_fan_out_worker(db: PgPool) {
+    loop {
+        match fan_out_events(&db).await {
+            Ok(n) if n == BATCH_SIZE as usize => continue,
+            Ok(_) => {}
+            Err(e) => log_background_error(FAN_OUT_WORKER, &e),
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+pub async fn run_delivery_worker(db: PgPool) {
+    let client = public_url::client_builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("Build webhook HTTP client");
+    loop {
+        match deliver_due(&db, &client).await {
+            Ok(n) if n == DELIVERY_BATCH_SIZE as usize => continue,
+            Ok(_) => {}
+            Err(e) => log_background_error(DELIVERY_WORKER, &e),
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+fn snapshot_field<'a>(snapshot: &'a serde_json::Value, field: &str) -> &'a str {
+    snapshot.get(field).and_then(|v| v.as_str()).unwrap_or("")
+}
+
+async fn fan_out_events(db: &PgPool) -> Result<usize> {
+    let cursor = post_events::load_cursor(db, FAN_OUT_WORKER).await?;
+    let events = post_events::fetch_after(db, cursor, BATCH_SIZE).await?;
+    for event in &events {
+        fan_out_event(db, event).await?;
+        post_events::save_cursor(db, FAN_OUT_WORKER, event.id).await?;
+    }
+    Ok(events.len())
+}
+
+async fn fan_out_event(db: &PgPool, event: &PostEvent) -> Result<()> {
+    // Filters are matched against the post as it was at the time of the
+    // event, which is all we have left after a delete.
+    let snapshot = match &event.post {
+        Some(snapshot) => snapshot,
+        None => return Ok(()),
+    };
+    let post = if event.kind == PostEventKind::Deleted {
+        None
+    } else {
+        match crate::fetch_post(db, event.post_id, false).await? {
+            Some(post) => Some(post),
+            // Deleted since, and that has an event of its own.
+            None => return Ok(()),
+        }
+    };
+    let payload = serde_json::json!({
+        "event": event.kind,
+        "event_id": event.id,
+        "post_id": event.post_id,
+        "occurred_at": event.created_at,
+        "post": post,
+    });
+
+    sqlx::query!(
+        r#"
+        INSERT INTO webhook_deliveries(webhook_id, event_id, event, payload)
+        SELECT id, $1, $2, $3
+        FROM webhooks
+        WHERE (post_type IS NULL OR post_type::text = $4)
+          AND (item IS NULL OR $5 ILIKE '%' || item || '%')
+          AND (
+              location IS NULL OR
+              $6 ILIKE '%' || location || '%' OR
+              $7 ILIKE '%' || location || '%' OR
+              $8 ILIKE '%' || location || '%' OR
+              $9 ILIKE '%' || location || '%'
+          )
+        ON CONFLICT (webhook_id, event_id) DO NOTHING
+        "#,
+        event.id,
+        event.kind: _,
+        payload,
+        snapshot_field(snapshot, "post_type"),
+        snapshot_field(snapshot, "item"),
+        snapshot_field(snapshot, "state"),
+        snapshot_field(snapshot, "district"),
+        snapshot_field(snapshot, "city"),
+        snapshot_field(snapshot, "spot"),
+    )
+    .execute(db)
+    .await
+    .context("Insert webhook deliveries")?;
+    Ok(())
+}
+
+pub fn backoff(attempts: i32) -> chrono::Duration {
+    let exponent = (attempts - 1).max(0).min(20) as u32;
+    let seconds = BACKOFF_BASE_SECONDS.saturating_mul(2i64.pow(exponent));
+    chrono::Duration::seconds(seconds.min(BACKOFF_MAX_SECONDS))
+}
+
+// Hex encoded HMAC-SHA256 of "<timestamp>.<body>". Including the timestamp
+// lets subscribers reject replayed deliveries.
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+struct Attempt {
+    status_code: Option<i32>,
+    error: Option<String>,
+}
+
+impl Attempt {
+    fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+async fn send(
+    client: &reqwest::Client,
+    url: &str,
+    secret: &str,
+    delivery_id: Uuid,
+    event: PostEventKind,
+    payload: &serde_json::Value,
+) -> Attempt {
+    let body = match serde_json::to_vec(payload) {
+        Ok(body) => body,
+        Err(e) => {
+            return Attempt {
+                status_code: None,
+                error: Some(e.to_string()),
+            }
+        }
+    };
+    let timestamp = chrono::Utc::now().timestamp();
+    let res = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header("X-Breathe-India-Delivery", delivery_id.to_string())
+        .header("X-Breathe-India-Event", format!("{:?}", event))
+        .header("X-Breathe-India-Timestamp", timestamp.to_string())
+        .header(
+            "X-Breathe-India-Signature",
+            format!("sha256={}", signature(secret, timestamp, &body)),
+        )
+        .body(body)
+        .send()
+        .await;
+    match res {
+        Ok(res) if res.status().is_success() => Attempt {
+            status_code: Some(res.status().as_u16() as i32),
+            error: None,
+        },
+        Ok(res) => Attempt {
+            status_code: Some(res.status().as_u16() as i32),
+            error: Some(format!("Subscriber returned {}", res.status())),
+        },
+        Err(e) => Attempt {
+            status_code: None,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+async fn deliver_due(db: &PgPool, client: &reqwest::Client) -> Result<usize> {
+    // Claim a batch by pushing its next attempt into the future, so other
+    // instances leave it alone while we send.
+    let due = sqlx::query!(
+        r#"
+        UPDATE webhook_deliveries SET
+            next_attempt_at = NOW() + make_interval(secs => $2)
+        FROM webhooks
+        WHERE webhooks.id = webhook_deliveries.webhook_id
+          AND webhook_deliveries.id IN (
+              SELECT id FROM webhook_deliveries
+              WHERE state = 'pending' AND next_attempt_at <= NOW()
+              ORDER BY next_attempt_at
+              LIMIT $1
+              FOR UPDATE SKIP LOCKED
+          )
+        RETURNING webhook_deliveries.id,
+                  webhook_deliveries.event as "event: PostEventKind",
+                  webhook_deliveries.payload,
+                  webhook_deliveries.attempts,
+                  webhooks.url,
+                  webhooks.secret
+        "#,
+        DELIVERY_BATCH_SIZE,
+        CLAIM_SECONDS
+    )
+    .fetch_all(db)
+    .await
+    .context("Claim due webhook deliveries")?;
+
+    for delivery in &due {
+        // The host may resolve somewhere else since the webhook was added.
+        let attempt = match public_url::check(&delivery.url).await {
+            Ok(_) => {
+                send(
+                    client,
+                    &delivery.url,
+                    &delivery.secret,
+                    delivery.id,
+                    delivery.event,
+                    &delivery.payload,
+                )
+                .await
+            }
+            Err(e) => Attempt {
+                status_code: None,
+                error: Some(e.to_string()),
+            },
+        };
+        let attempts = delivery.attempts + 1;
+        if attempt.succeeded() {
+            sqlx::query!(
+                r#"UPDATE webhook_deliveries SET
+                    state = 'delivered',
+                    attempts = $2,
+                    last_status_code = $3,
+                    last_error = NULL,
+                    delivered_at = NOW()
+                WHERE id = $1"#,
+                delivery.id,
+                attempts,
+                attempt.status_code
+            )
+            .execute(db)
+            .await
+            .context("Mark webhook delivered")?;
+        } else {
+            let state = if attempts >= MAX_ATTEMPTS {
+                super::WebhookDeliveryState::Dead
+            } else {
+                super::WebhookDeliveryState::Pending
+            };
+            sqlx::query!(
+                r#"UPDATE webhook_deliveries SET
+                    state = $2,
+                    attempts = $3,
+                    last_status_code = $4,
+                    last_error = $5,
+                    next_attempt_at = $6
+                WHERE id = $1"#,
+                delivery.id,
+                state: _,
+                attempts,
+                attempt.status_code,
+                attempt.error,
+                chrono::Utc::now() + backoff(attempts)
+            )
+            .execute(db)
+            .await
+            .context("Record failed webhook delivery")?;
+        }
+    }
+    Ok(due.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::stub_http_server;
+
+    #[test]
+    fn test_backoff() {
+        let cases = &[
+            (1, 30),
+            (2, 60),
+            (3, 120),
+            (8, 3840),
+            (11, 6 * 60 * 60),
+            (100, 6 * 60 * 60),
+        ];
+        for (attempts, seconds) in cases {
+            assert_eq!(backoff(*attempts), chrono::Duration::seconds(*seconds));
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        // HMAC-SHA256 of "1620000000.{}" with key "secret".
+        assert_eq!(
+            signature("secret", 1620000000, b"{}"),
+            "4de1b55993ef1e88b33397d145aef387b2cc39c5b78bd4300ced725aeffb8e0b"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_send_to_stub_subscriber() {
+        let (url, stub) = stub_http_server("204 No Content").await;
+        let payload = serde_json::json!({ "event": "Created" });
+        let attempt = send(
+            &reqwest::Client::new(),
+            &url,
+            "secret",
+            Uuid::new_v4(),
+            PostEventKind::Created,
+            &payload,
+        )
+        .await;
+        assert!(attempt.succeeded());
+        assert_eq!(attempt.status_code, Some(204));
+
+        let request = stub.await.unwrap();
+        assert!(request.head.contains("x-breathe-india-event: created"));
+        let timestamp = request
+            .head
+            .lines()
+            .find_map(|l| l.strip_prefix("x-breathe-india-timestamp: "))
+            .unwrap()
+            .parse::<i64>()
+            .unwrap();
+        let expected = format!(
+            "x-breathe-india-signature: sha256={}",
+            signature("secret", timestamp, &request.body)
+        );
+        assert!(request.head.contains(&expected));
+    }
+
+    #[rocket::async_test]
+    async fn test_send_to_failing_subscriber() {
+        let (url, _stub) = stub_http_server("500 Internal Server Error").await;
+        let attempt = send(
+            &reqwest::Client::new(),
+            &url,
+            "secret",
+            Uuid::new_v4(),
+            PostEventKind::Deleted,
+            &serde_json::json!({}),
+        )
+        .await;
+        assert!(!attempt.succeeded());
+        assert_eq!(attempt.status_code, Some(500));
+    }
+}
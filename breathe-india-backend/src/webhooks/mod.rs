@@ -0,0 +1,291 @@
+// Outgoing webhooks, so partner organisations can mirror posts into their
+// own dashboards as they change.
+
+mod delivery;
+
+pub use delivery::{run_delivery_worker, run_fan_out_worker};
+
+use rand::RngCore;
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::myres::{HasStatusCode, MyRes};
+use crate::orgs::{self, OrgRole};
+use crate::post_events::PostEventKind;
+use crate::{fail, public_url, LoggedInUser, PostType};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        webhooks,
+        webhooks_create,
+        webhooks_delete,
+        webhook_deliveries,
+        webhook_deliveries_retry,
+    ]
+}
+
+#[derive(Serialize)]
+pub struct Webhook {
+    id: Uuid,
+    org_id: Option<Uuid>,
+    url: String,
+    typ: Option<PostType>,
+    item: Option<String>,
+    location: Option<String>,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+// The secret is only ever shown once, when the webhook is created.
+#[derive(Serialize)]
+pub struct WebhookCreated {
+    webhook: Webhook,
+    secret: String,
+}
+
+#[derive(Deserialize)]
+pub struct WebhookNew {
+    // Set for an org's dashboard. Only its owners and editors can add one.
+    org_id: Option<Uuid>,
+    url: String,
+    typ: Option<PostType>,
+    item: Option<String>,
+    location: Option<String>,
+}
+
+#[derive(Clone, Copy, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum WebhookDeliveryState {
+    Pending,
+    Delivered,
+    Dead,
+}
+
+#[derive(Serialize)]
+pub struct WebhookDelivery {
+    id: Uuid,
+    event_id: i64,
+    event: PostEventKind,
+    state: WebhookDeliveryState,
+    attempts: i32,
+    next_attempt_at: chrono::DateTime<chrono::Utc>,
+    last_status_code: Option<i32>,
+    last_error: Option<String>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    delivered_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize)]
+enum WebhookError {
+    NotFound,
+    NotVerified,
+    NotOrgEditor,
+    InvalidUrl,
+}
+
+impl HasStatusCode for WebhookError {
+    fn get_status(&self) -> Status {
+        match self {
+            WebhookError::NotFound => Status::NotFound,
+            WebhookError::NotVerified => Status::Forbidden,
+            WebhookError::NotOrgEditor => Status::Forbidden,
+            WebhookError::InvalidUrl => Status::BadRequest,
+        }
+    }
+}
+
+fn non_empty(s: &Option<String>) -> Option<String> {
+    s.as_ref()
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+}
+
+fn generate_secret() -> String {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    base64::encode_config(&secret, base64::URL_SAFE_NO_PAD)
+}
+
+#[get("/webhooks")]
+async fn webhooks(user: LoggedInUser, db: State<'_, PgPool>) -> MyRes<Vec<Webhook>, ()> {
+    let res = sqlx::query_as!(
+        Webhook,
+        r#"
+        SELECT id,
+               org_id,
+               url,
+               post_type as "typ: _",
+               item,
+               location,
+               created_at
+        FROM webhooks
+        WHERE id IN (SELECT webhook_id FROM webhook_managers WHERE userid = $1)
+        ORDER BY created_at DESC
+        "#,
+        user.0
+    )
+    .fetch_all(&*db)
+    .await;
+    let webhooks = fail!(res);
+
+    MyRes::Ok(webhooks)
+}
+
+// Only verified users can subscribe, since they get every matching post
+// as soon as it changes. The URL has to be public, see `public_url`.
+#[post("/webhooks", data = "<data>")]
+async fn webhooks_create(
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+    data: Json<WebhookNew>,
+) -> MyRes<WebhookCreated, WebhookError> {
+    let res = sqlx::query!("SELECT verified FROM users WHERE id = $1", user.0)
+        .fetch_optional(&*db)
+        .await;
+    let verified = fail!(res).map(|u| u.verified).unwrap_or(false);
+    if !verified {
+        return MyRes::Err(WebhookError::NotVerified);
+    }
+    if let Some(org_id) = data.org_id {
+        let role = fail!(orgs::role(&db, org_id, user.0).await);
+        if !role.map(OrgRole::can_edit_posts).unwrap_or(false) {
+            return MyRes::Err(WebhookError::NotOrgEditor);
+        }
+    }
+    if public_url::check(&data.url).await.is_err() {
+        return MyRes::Err(WebhookError::InvalidUrl);
+    }
+
+    let secret = generate_secret();
+    let res = sqlx::query_as!(
+        Webhook,
+        r#"INSERT INTO webhooks(userid, org_id, url, secret, post_type, item, location)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING
+               id,
+               org_id,
+               url,
+               post_type as "typ: _",
+               item,
+               location,
+               created_at
+        "#,
+        user.0,
+        data.org_id,
+        data.url.trim(),
+        secret,
+        data.typ: _,
+        non_empty(&data.item),
+        non_empty(&data.location)
+    )
+    .fetch_one(&*db)
+    .await;
+    let webhook = fail!(res);
+
+    MyRes::Ok(WebhookCreated { webhook, secret })
+}
+
+#[delete("/webhooks/<id>")]
+async fn webhooks_delete(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<(), WebhookError> {
+    let res = sqlx::query!(
+        r#"DELETE FROM webhooks
+        WHERE id = $1 AND id IN (SELECT webhook_id FROM webhook_managers WHERE userid = $2)"#,
+        id.into_inner(),
+        user.0
+    )
+    .execute(&*db)
+    .await;
+
+    let res = fail!(res);
+    if res.rows_affected() == 0 {
+        return MyRes::Err(WebhookError::NotFound);
+    }
+    MyRes::Ok(())
+}
+
+// The most recent deliveries, for debugging a subscriber.
+#[get("/webhooks/<id>/deliveries")]
+async fn webhook_deliveries(
+    id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<Vec<WebhookDelivery>, WebhookError> {
+    let id: Uuid = id.into_inner();
+    let res = sqlx::query!(
+        r#"SELECT id FROM webhooks
+        WHERE id = $1 AND id IN (SELECT webhook_id FROM webhook_managers WHERE userid = $2)"#,
+        id,
+        user.0
+    )
+    .fetch_optional(&*db)
+    .await;
+    if fail!(res).is_none() {
+        return MyRes::Err(WebhookError::NotFound);
+    }
+
+    let res = sqlx::query_as!(
+        WebhookDelivery,
+        r#"
+        SELECT id,
+               event_id,
+               event as "event: _",
+               state as "state: _",
+               attempts,
+               next_attempt_at,
+               last_status_code,
+               last_error,
+               created_at,
+               delivered_at
+        FROM webhook_deliveries
+        WHERE webhook_id = $1
+        ORDER BY created_at DESC
+        LIMIT 100
+        "#,
+        id
+    )
+    .fetch_all(&*db)
+    .await;
+    let deliveries = fail!(res);
+
+    MyRes::Ok(deliveries)
+}
+
+// Puts a dead delivery back in the queue, once the subscriber is fixed.
+#[post("/webhooks/<id>/deliveries/<delivery_id>/retry")]
+async fn webhook_deliveries_retry(
+    id: rocket_contrib::uuid::Uuid,
+    delivery_id: rocket_contrib::uuid::Uuid,
+    user: LoggedInUser,
+    db: State<'_, PgPool>,
+) -> MyRes<(), WebhookError> {
+    let res = sqlx::query!(
+        r#"UPDATE webhook_deliveries SET
+            state = 'pending',
+            attempts = 0,
+            next_attempt_at = NOW()
+        FROM webhooks
+        WHERE webhook_deliveries.id = $1
+          AND webhook_deliveries.webhook_id = $2
+          AND webhooks.id = webhook_deliveries.webhook_id
+          AND webhooks.id IN (SELECT webhook_id FROM webhook_managers WHERE userid = $3)
+          AND webhook_deliveries.state = 'dead'
+        "#,
+        delivery_id.into_inner(),
+        id.into_inner(),
+        user.0
+    )
+    .execute(&*db)
+    .await;
+
+    let res = fail!(res);
+    if res.rows_affected() == 0 {
+        return MyRes::Err(WebhookError::NotFound);
+    }
+    MyRes::Ok(())
+}
@@ -4,6 +4,67 @@ const ajv = new Ajv();
 
 let BASE_URL = "/api"
 
+const parseChallengeResponse = ajv.compileParser({
+  properties: {
+    challenge: { type: "string" },
+    difficulty: { type: "uint32" },
+    expires_at: { type: "timestamp" },
+  },
+});
+
+function leadingZeroBits(hash) {
+  let bits = 0;
+  for (let b of hash) {
+    if (b == 0) {
+      bits += 8;
+      continue;
+    }
+    bits += Math.clz32(b) - 24;
+    break;
+  }
+  return bits;
+}
+
+// Finds a nonce for which the SHA-256 of "<challenge>:<nonce>" starts with
+// `difficulty` zero bits.
+async function solveChallenge() {
+  let { challenge, difficulty } = await ky.get(BASE_URL + "/challenge", {
+    parseJson: (text) => {
+      const parse = parseChallengeResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json();
+  const encoder = new TextEncoder();
+  for (let nonce = 0; ; nonce++) {
+    let solution = challenge + ":" + nonce;
+    let hash = await crypto.subtle.digest("SHA-256", encoder.encode(solution));
+    if (leadingZeroBits(new Uint8Array(hash)) >= difficulty) {
+      return solution;
+    }
+  }
+}
+
+// New accounts have to solve a challenge first. `request` is called with
+// the extra headers to send.
+async function withChallenge(request) {
+  try {
+    return await request({});
+  } catch (err) {
+    if (err.name == "HTTPError" && err.response.status == 403) {
+      let body = await err.response.clone().json();
+      if (body.Challenge == "Required") {
+        let solution = await solveChallenge();
+        return await request({ "X-Challenge-Solution": solution });
+      }
+    }
+    throw err;
+  }
+}
+
 const parseLoginResponse = ajv.compileParser({
   properties: {
     our_token: { type: "string" },
@@ -12,7 +73,8 @@ const parseLoginResponse = ajv.compileParser({
 });
 
 async function login({ token }) {
-  return await ky.post(BASE_URL + "/login", {
+  return await withChallenge((headers) => ky.post(BASE_URL + "/login", {
+    headers,
     json: {
       token
     },
@@ -23,7 +85,7 @@ async function login({ token }) {
       }
       return data;
     }
-  }).json()
+  }).json())
 }
 
 const profileSchema = {
@@ -82,6 +144,7 @@ const publicProfileSchema = {
     profile_pic_url: { type: "string" },
     bio: { type: "string" },
     verified: { type: "boolean" },
+    trust_score: { type: "int16" },
   }
 };
 
@@ -89,32 +152,73 @@ const postSchema = {
   properties: {
     id: { type: "string" },
     userid: { type: "string" },
+    org_id: { type: "string", nullable: true },
     post_type: { enum: ["Needs", "Supplies"] },
+    status: { enum: ["Open", "PartiallyFulfilled", "Fulfilled", "Closed"] },
+    urgency: { enum: ["Critical", "High", "Normal"], nullable: true },
+    patient_age_bracket: { enum: ["Under18", "From18To44", "From45To59", "From60"], nullable: true },
+    patient_spo2: { type: "int16", nullable: true },
+    patient_blood_group: { enum: ["A+", "A-", "B+", "B-", "AB+", "AB-", "O+", "O-"], nullable: true },
+    patient_hospital: { type: "string", nullable: true },
+    patient_public: { type: "boolean" },
+    contact_visibility: { enum: ["Public", "LoggedIn", "Verified"] },
+    on_behalf_of: { type: "boolean" },
+    beneficiary_name: { type: "string", nullable: true },
+    beneficiary_relationship: { type: "string", nullable: true },
     state: { type: "string" },
     district: { type: "string" },
     city: { type: "string" },
     spot: { type: "string" },
     created_at: { type: "timestamp" },
     updated_at: { type: "timestamp" },
+    bumped_at: { type: "timestamp" },
     message: { type: "string" },
     item: { type: "string" },
     quantity: { type: "string" },
+    quantity_remaining: { type: "int32", nullable: true },
   },
 }
+const refillStatusSchema = { enum: ["Open", "StockLow", "Closed"] };
+const refillCentreSchema = {
+  properties: {
+    id: { type: "string" },
+    name: { type: "string" },
+    address: { type: "string" },
+    state: { type: "string" },
+    district: { type: "string" },
+    city: { type: "string" },
+    spot: { type: "string" },
+    phone: { type: "string" },
+    opening_hours: { type: "string" },
+    cylinder_sizes: { elements: { type: "string" } },
+    status: refillStatusSchema,
+    status_note: { type: "string" },
+    status_updated_at: { type: "timestamp" },
+    crowd_status: { ...refillStatusSchema, nullable: true },
+    crowd_reports: { type: "int32" },
+  },
+}
+// Searches for oxygen supplies also get refill centres on the first page.
 const getPostsSchema = {
-  elements: { ref: "Post" },
+  properties: {
+    items: { elements: { ref: "Post" } },
+    has_more: { type: "boolean" },
+    next_cursor: { type: "string", nullable: true },
+    approx_total: { type: "int32", nullable: true },
+  },
+  optionalProperties: {
+    refill_centres: { elements: { ref: "RefillCentre" } },
+  },
   definitions: {
     "Post": postSchema,
-    "User": publicProfileSchema,
+    "RefillCentre": refillCentreSchema,
   }
 }
 const parseGetPostsResponse = ajv.compileParser(getPostsSchema)
 
-async function getPosts({ start = null, n = null, typ, location = null, item = null }) {
-  let searchParams = {};
-  if (start) {
-    searchParams.start = start;
-  }
+// `cursor` is the previous page's `next_cursor`, or empty for the first.
+async function getPosts({ cursor = "", n = null, typ, location = null, item = null }) {
+  let searchParams = { cursor };
   if (n) {
     searchParams.n = n;
   }
@@ -144,6 +248,15 @@ const getPostSingleSchema = {
   properties: {
     post: { ref: "Post" },
     user: { ref: "User", nullable: true },
+    org: {
+      properties: {
+        id: { type: "string" },
+        name: { type: "string" },
+        verified: { type: "boolean" },
+      },
+      nullable: true,
+    },
+    can_edit: { type: "boolean" },
   },
   definitions: {
     "Post": postSchema,
@@ -151,8 +264,13 @@ const getPostSingleSchema = {
   }
 }
 const parseGetPostSingleResponse = ajv.compileParser(getPostSingleSchema);
-async function getPostSingle({ id }) {
+async function getPostSingle({ id, token = null }) {
+  let headers = {};
+  if (token) {
+    headers["Authorization"] = "Bearer " + token;
+  }
   return await ky.get(BASE_URL + "/posts/" + id, {
+    headers,
     parseJson: (text) => {
       const parse = parseGetPostSingleResponse;
       let data = parse(text);
@@ -172,11 +290,16 @@ const getMyPostsSchema = {
 };
 const parseGetMyPostsResponse = ajv.compileParser(getMyPostsSchema)
 
-async function getMyPosts({ token }) {
+async function getMyPosts({ token, on_behalf_of = false }) {
+  let searchParams = {};
+  if (on_behalf_of) {
+    searchParams.on_behalf_of = true;
+  }
   return await ky.get(BASE_URL + "/my_posts", {
     headers: {
       "Authorization": "Bearer " + token,
     },
+    searchParams,
     parseJson: (text) => {
       const parse = parseGetMyPostsResponse;
       let data = parse(text);
@@ -188,13 +311,27 @@ async function getMyPosts({ token }) {
   }).json()
 }
 
-const parseCreatePostResponse = ajv.compileParser(postSchema);
+const parseCreatePostResponse = ajv.compileParser({
+  properties: {
+    ...postSchema.properties,
+    pii: {
+      elements: {
+        properties: {
+          kind: { enum: ["Phone", "Aadhaar", "Email", "Pan"] },
+          action: { enum: ["Warn", "Mask"] },
+        },
+      },
+    },
+    held: { type: "boolean" },
+  },
+});
 
-async function createPost({ post_type, state, district, city, spot, message, item, quantity, token }) {
+async function createPost({ post_type, state, district, city, spot, message, item, quantity, contact, keep_raw_message, allow_duplicate, org_id = null, on_behalf_of = null, token }) {
 
 
-  return await ky.post(BASE_URL + "/posts", {
+  return await withChallenge((headers) => ky.post(BASE_URL + "/posts", {
     headers: {
+      ...headers,
       "Authorization": "Bearer " + token,
     },
     json: {
@@ -206,6 +343,11 @@ async function createPost({ post_type, state, district, city, spot, message, ite
       message,
       item,
       quantity,
+      contact,
+      keep_raw_message,
+      allow_duplicate,
+      org_id,
+      on_behalf_of,
     },
     parseJson: (text) => {
       const parse = parseCreatePostResponse;
@@ -215,10 +357,10 @@ async function createPost({ post_type, state, district, city, spot, message, ite
       }
       return data;
     }
-  }).json()
+  }).json())
 }
 
-async function updatePost({ id, post_type, state, district, city, spot, message, item, quantity, token }) {
+async function updatePost({ id, post_type, state, district, city, spot, message, item, quantity, contact, keep_raw_message, allow_duplicate, on_behalf_of = null, token }) {
 
   return await ky.patch(BASE_URL + "/posts/" + id, {
     headers: {
@@ -232,7 +374,11 @@ async function updatePost({ id, post_type, state, district, city, spot, message,
       spot,
       message,
       item,
-      quantity
+      quantity,
+      contact,
+      keep_raw_message,
+      allow_duplicate,
+      on_behalf_of,
     },
     parseJson: (text) => {
       const parse = parseCreatePostResponse;
@@ -246,6 +392,545 @@ async function updatePost({ id, post_type, state, district, city, spot, message,
 }
 
 
+const parseRevealContactResponse = ajv.compileParser({
+  properties: {
+    phones: { elements: { type: "string" } },
+    whatsapp: { type: "string", nullable: true },
+    email: { type: "string", nullable: true },
+    visibility: { enum: ["Public", "LoggedIn", "Verified"] },
+    beneficiary_phone: { type: "string", nullable: true },
+  },
+});
+
+async function revealContact({ id, token = null }) {
+  let headers = {};
+  if (token) {
+    headers["Authorization"] = "Bearer " + token;
+  }
+  return await ky.post(BASE_URL + "/posts/" + id + "/reveal_contact", {
+    headers,
+    parseJson: (text) => {
+      const parse = parseRevealContactResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+const parseBumpPostResponse = ajv.compileParser(postSchema);
+
+async function bumpPost({ id, token }) {
+  return await ky.post(BASE_URL + "/posts/" + id + "/bump", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    parseJson: (text) => {
+      const parse = parseBumpPostResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+async function reportPost({ id, reason, token }) {
+  return await ky.post(BASE_URL + "/posts/" + id + "/report", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    json: {
+      reason,
+    },
+  })
+}
+
+const responseSchema = {
+  properties: {
+    id: { type: "string" },
+    parent_id: { type: "string", nullable: true },
+    userid: { type: "string" },
+    author_name: { type: "string" },
+    by_post_author: { type: "boolean" },
+    kind: { enum: ["Offer", "Question", "Update"] },
+    body: { type: "string" },
+    deleted: { type: "boolean" },
+    created_at: { type: "timestamp" },
+  },
+};
+const parseResponseResponse = ajv.compileParser(responseSchema);
+const parseGetResponsesResponse = ajv.compileParser({
+  elements: {
+    properties: {
+      ...responseSchema.properties,
+      replies: { elements: responseSchema },
+    },
+  },
+});
+
+async function getResponses({ id, token }) {
+  return await ky.get(BASE_URL + "/posts/" + id + "/responses", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    parseJson: (text) => {
+      const parse = parseGetResponsesResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+async function createResponse({ id, kind, body, parent_id = null, token }) {
+  return await ky.post(BASE_URL + "/posts/" + id + "/responses", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    json: {
+      kind,
+      body,
+      parent_id,
+    },
+    parseJson: (text) => {
+      const parse = parseResponseResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+async function deleteResponse({ id, response_id, token }) {
+  return await ky.delete(BASE_URL + "/posts/" + id + "/responses/" + response_id, {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+  })
+}
+
+const pledgeSchema = {
+  properties: {
+    id: { type: "string" },
+    needs_id: { type: "string" },
+    item: { type: "string" },
+    supplies_id: { type: "string", nullable: true },
+    userid: { type: "string" },
+    pledger_name: { type: "string" },
+    quantity: { type: "int32" },
+    eta: { type: "timestamp", nullable: true },
+    note: { type: "string" },
+    state: { enum: ["Pledged", "Delivered", "Cancelled"] },
+    created_at: { type: "timestamp" },
+    updated_at: { type: "timestamp" },
+  },
+};
+const parsePledgeResponse = ajv.compileParser(pledgeSchema);
+const parseGetPledgesResponse = ajv.compileParser({
+  properties: {
+    amount: { type: "int32", nullable: true },
+    pledged: { type: "int32" },
+    delivered: { type: "int32" },
+    remaining: { type: "int32", nullable: true },
+    pledges: { elements: pledgeSchema },
+  },
+});
+const parseGetMyPledgesResponse = ajv.compileParser({ elements: pledgeSchema });
+
+async function getPledges({ id, token }) {
+  return await ky.get(BASE_URL + "/posts/" + id + "/pledges", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    parseJson: (text) => {
+      const parse = parseGetPledgesResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+async function createPledge({ id, quantity, eta = null, note = "", supplies_id = null, token }) {
+  return await ky.post(BASE_URL + "/posts/" + id + "/pledges", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    json: {
+      quantity,
+      eta,
+      note,
+      supplies_id,
+    },
+    parseJson: (text) => {
+      const parse = parsePledgeResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+async function updatePledgeState({ id, state, token }) {
+  return await ky.post(BASE_URL + "/pledges/" + id + "/state", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    json: {
+      state,
+    },
+    parseJson: (text) => {
+      const parse = parsePledgeResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+async function getMyPledges({ token }) {
+  return await ky.get(BASE_URL + "/my_pledges", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    parseJson: (text) => {
+      const parse = parseGetMyPledgesResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+const conversationSchema = {
+  properties: {
+    id: { type: "string" },
+    post_id: { type: "string", nullable: true },
+    item: { type: "string", nullable: true },
+    other_userid: { type: "string" },
+    other_name: { type: "string" },
+    last_message_at: { type: "timestamp" },
+    unread: { type: "int32" },
+    blocked: { type: "boolean" },
+    reported: { type: "boolean" },
+  },
+};
+const messageSchema = {
+  properties: {
+    id: { type: "string" },
+    conversation_id: { type: "string" },
+    sender: { type: "string" },
+    sender_name: { type: "string" },
+    body: { type: "string" },
+    created_at: { type: "timestamp" },
+    read: { type: "boolean" },
+  },
+};
+const parseGetConversationsResponse = ajv.compileParser({ elements: conversationSchema });
+const parseMessageResponse = ajv.compileParser(messageSchema);
+const parseGetMessagesResponse = ajv.compileParser({ elements: messageSchema });
+
+async function getConversations({ token }) {
+  return await ky.get(BASE_URL + "/conversations", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    parseJson: (text) => {
+      const parse = parseGetConversationsResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+// Starts a conversation with the post's author, or carries on the existing one.
+async function startConversation({ post_id, body, token }) {
+  return await ky.post(BASE_URL + "/posts/" + post_id + "/conversations", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    json: {
+      body,
+    },
+    parseJson: (text) => {
+      const parse = parseMessageResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+async function getMessages({ id, token }) {
+  return await ky.get(BASE_URL + "/conversations/" + id + "/messages", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    parseJson: (text) => {
+      const parse = parseGetMessagesResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+async function sendMessage({ id, body, token }) {
+  return await ky.post(BASE_URL + "/conversations/" + id + "/messages", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    json: {
+      body,
+    },
+    parseJson: (text) => {
+      const parse = parseMessageResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+async function markConversationRead({ id, token }) {
+  return await ky.post(BASE_URL + "/conversations/" + id + "/read", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+  })
+}
+
+async function reportConversation({ id, reason, token }) {
+  return await ky.post(BASE_URL + "/conversations/" + id + "/report", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    json: {
+      reason,
+    },
+  })
+}
+
+async function blockUser({ id, token }) {
+  return await ky.post(BASE_URL + "/users/" + id + "/block", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+  })
+}
+
+async function unblockUser({ id, token }) {
+  return await ky.delete(BASE_URL + "/users/" + id + "/block", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+  })
+}
+
+const donationKinds = ["Blood", "Plasma"];
+const contactRequestStates = ["Pending", "Accepted", "Declined"];
+
+// Requests donors got from people looking for blood or plasma.
+const parseGetDonorRequestsResponse = ajv.compileParser({
+  elements: {
+    properties: {
+      id: { type: "string" },
+      kind: { enum: donationKinds },
+      post_id: { type: "string", nullable: true },
+      message: { type: "string" },
+      state: { enum: contactRequestStates },
+      created_at: { type: "timestamp" },
+      requester_name: { type: "string" },
+    },
+  },
+});
+
+// Requests sent to donors. Their name and email are only there once accepted.
+const parseGetDonorContactRequestsResponse = ajv.compileParser({
+  elements: {
+    properties: {
+      id: { type: "string" },
+      donor_id: { type: "string" },
+      kind: { enum: donationKinds },
+      post_id: { type: "string", nullable: true },
+      message: { type: "string" },
+      state: { enum: contactRequestStates },
+      created_at: { type: "timestamp" },
+      responded_at: { type: "timestamp", nullable: true },
+      donor_name: { type: "string", nullable: true },
+      donor_email: { type: "string", nullable: true },
+    },
+  },
+});
+
+async function getDonorRequests({ token }) {
+  return await ky.get(BASE_URL + "/donors/me/contact_requests", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    parseJson: (text) => {
+      const parse = parseGetDonorRequestsResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+async function respondDonorRequest({ id, accept, token }) {
+  return await ky.post(BASE_URL + "/donors/me/contact_requests/" + id, {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    json: {
+      accept,
+    },
+  })
+}
+
+async function getDonorContactRequests({ token }) {
+  return await ky.get(BASE_URL + "/donors/contact_requests", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    parseJson: (text) => {
+      const parse = parseGetDonorContactRequestsResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+const orgRoles = ["owner", "editor", "viewer"];
+
+const orgSchema = {
+  properties: {
+    id: { type: "string" },
+    name: { type: "string" },
+    role: { enum: orgRoles },
+    created_at: { type: "timestamp" },
+  },
+};
+const parseOrgResponse = ajv.compileParser(orgSchema);
+const parseGetOrgsResponse = ajv.compileParser({ elements: orgSchema });
+
+async function getOrgs({ token }) {
+  return await ky.get(BASE_URL + "/orgs", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    parseJson: (text) => {
+      const parse = parseGetOrgsResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+async function createOrg({ name, token }) {
+  return await ky.post(BASE_URL + "/orgs", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    json: {
+      name,
+    },
+    parseJson: (text) => {
+      const parse = parseOrgResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+async function inviteToOrg({ id, email, role, token }) {
+  return await ky.post(BASE_URL + "/orgs/" + id + "/invitations", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    json: {
+      email,
+      role,
+    },
+  }).json()
+}
+
+const parseGetOrgInvitationsResponse = ajv.compileParser({
+  elements: {
+    properties: {
+      id: { type: "string" },
+      org_id: { type: "string" },
+      org_name: { type: "string" },
+      role: { enum: orgRoles },
+      invited_by_name: { type: "string", nullable: true },
+      created_at: { type: "timestamp" },
+    },
+  },
+});
+
+async function getOrgInvitations({ token }) {
+  return await ky.get(BASE_URL + "/org_invitations", {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    parseJson: (text) => {
+      const parse = parseGetOrgInvitationsResponse;
+      let data = parse(text);
+      if (data === undefined) {
+        throw { message: parse.message, position: parse.position };
+      }
+      return data;
+    }
+  }).json()
+}
+
+async function respondOrgInvitation({ id, accept, token }) {
+  return await ky.post(BASE_URL + "/org_invitations/" + id, {
+    headers: {
+      "Authorization": "Bearer " + token,
+    },
+    json: {
+      accept,
+    },
+  })
+}
+
 async function deletePost({ id, token }) {
   return await ky.delete(BASE_URL + "/posts/" + id, {
     headers: {
@@ -254,4 +939,4 @@ async function deletePost({ id, token }) {
   })
 }
 
-export default { login, profile, profileUpdate, getPosts, getPostSingle, getMyPosts, createPost, updatePost, deletePost };
+export default { login, profile, profileUpdate, getPosts, getPostSingle, getMyPosts, createPost, updatePost, deletePost, bumpPost, reportPost, revealContact, getOrgs, createOrg, inviteToOrg, getOrgInvitations, respondOrgInvitation, getResponses, createResponse, deleteResponse, getPledges, createPledge, updatePledgeState, getMyPledges, getConversations, startConversation, getMessages, sendMessage, markConversationRead, reportConversation, blockUser, unblockUser, getDonorRequests, respondDonorRequest, getDonorContactRequests };